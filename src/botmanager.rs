@@ -1,27 +1,80 @@
 use std::collections::HashMap;
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 
-use serenity::http::Typing;
 use serenity::model::prelude::interaction::{Interaction, InteractionResponseType};
-use serenity::model::prelude::{Message, Ready, GuildId, ChannelId, Activity};
-use serenity::model::webhook::Webhook;
+use serenity::model::prelude::{Message, Ready, Activity, Guild};
 use serenity::prelude::{Context, EventHandler};
 use serenity::{async_trait};
 
 use crate::commands;
+use crate::commands::character::PendingCharacter;
+use crate::config::CommandScope;
+use crate::conversation::Conversation;
+use crate::fences::Fences;
+use crate::notes::AuthorsNotes;
+use crate::platform::discord::{self, DiscordPlatform};
+use crate::store::Store;
+use crate::swipes::{self, SwipeState};
 use crate::textgen::api::{TextgenApi};
 use crate::textgen::character::Character;
+use crate::transcript::ImportedHistory;
+use crate::users::UserProfiles;
 
 pub struct BotManager
 {
-    pub api: TextgenApi,
-    pub data: Mutex<BotManagerData>
+    pub api: Arc<TextgenApi>,
+    pub data: Arc<Mutex<BotManagerData>>,
+    pub characters_dir: String,
+    pub avatars_dir: String,
+    pub avatars_url: String,
+    pub command_scope: CommandScope,
+    pub health_check_started: AtomicBool
 }
 
 pub struct BotManagerData
 {
     pub characters: HashMap<String, Character>,
-    pub invited_characters: HashMap<ChannelId, String>
+    /// Character ID invited to each channel, keyed by the platform's channel ID
+    pub invited_characters: HashMap<String, String>,
+    pub swipes: HashMap<String, SwipeState>,
+    pub fences: Fences,
+    /// Voice channel replies are spoken in, keyed by the text channel they're sent to
+    pub voice_channels: HashMap<String, String>,
+    /// Transcripts posted for people speaking in voice channels, keyed by text channel: message and speaker IDs, oldest first
+    pub voice_transcripts: HashMap<String, Vec<(String, String)>>,
+    /// Messages imported with `/import`, keyed by channel
+    pub imported_history: Store<ImportedHistory>,
+    pub user_profiles: UserProfiles,
+    pub authors_notes: AuthorsNotes,
+    /// Visibility and avatar picked with `/character create` or `edit`, until its modal is submitted, keyed by user and character ID
+    pub pending_characters: HashMap<(u64, String), PendingCharacter>
+}
+
+impl BotManagerData {
+    pub fn new(characters: HashMap<String, Character>) -> BotManagerData {
+        BotManagerData {
+            characters,
+            invited_characters: HashMap::new(),
+            swipes: HashMap::new(),
+            fences: Fences::default(),
+            voice_channels: HashMap::new(),
+            voice_transcripts: HashMap::new(),
+            imported_history: Store::default(),
+            user_profiles: UserProfiles::default(),
+            authors_notes: AuthorsNotes::default(),
+            pending_characters: HashMap::new()
+        }
+    }
+
+    /// Characters the user can see that match the query, sorted by name then ID
+    pub fn visible_characters(&self, user_id: u64, guild_id: Option<u64>, query: &str) -> Vec<(&String, &Character)> {
+        let mut visible: Vec<(&String, &Character)> = self.characters.iter()
+            .filter(|(_, character)| character.is_visible_to(user_id, guild_id) && character.matches(query))
+            .collect();
+        visible.sort_by(|(a_id, a), (b_id, b)| a.char_name.to_lowercase().cmp(&b.char_name.to_lowercase()).then(a_id.cmp(b_id)));
+        visible
+    }
 }
 
 #[async_trait]
@@ -29,39 +82,129 @@ impl EventHandler for BotManager {
     async fn ready(&self, context: Context, ready: Ready) {
         println!("{} is connected!", ready.user.name);
 
-        let model = self.api.check_model().await.expect("Failed connecting to textgen API");
-        println!("Using model: {}", model);
+        match self.api.check_model().await {
+            Some(model) => {
+                println!("Using model: {}", model);
+                context.set_activity(Activity::playing(model)).await;
+            },
+            None => {
+                println!("No textgen backend is reachable, staying online anyway");
+                context.set_activity(Activity::playing("Backend unavailable")).await;
+            }
+        }
 
-        context.set_activity(Activity::playing(model)).await;
+        if !self.health_check_started.swap(true, Ordering::SeqCst) {
+            tokio::spawn(health_check_loop(self.api.clone(), context.clone()));
+        }
+
+        let global = self.command_scope == CommandScope::Global;
+        match commands::sync(&context.http, None, global).await {
+            Ok(true) => println!("{} global commands", if global {"Registered"} else {"Removed"}),
+            Ok(false) => {},
+            Err(why) => println!("Failed syncing global commands: {}", why)
+        }
+    }
 
-        for guild in ready.guilds {
-            println!("Registering commands for server: {:?}", guild.id.name(&context.cache).ok_or("UNKNOWN"));
-            GuildId::set_application_commands(&guild.id, &context.http, |commands| {
-                commands
-                    .create_application_command(|cmd| commands::list::register(cmd))
-                    .create_application_command(|cmd| commands::invite::register(cmd))
-                    .create_application_command(|cmd| commands::uninvite::register(cmd))
-                    .create_application_command(|cmd| commands::fence::register(cmd))
-            }).await.expect("Failed registering commands");
+    /// Sent for every server on connect, and for servers joined later
+    async fn guild_create(&self, context: Context, guild: Guild, _is_new: bool) {
+        let enabled = self.command_scope == CommandScope::Guild;
+        match commands::sync(&context.http, Some(guild.id), enabled).await {
+            Ok(true) => println!("{} commands for server: {}", if enabled {"Registered"} else {"Removed"}, guild.name),
+            Ok(false) => {},
+            Err(why) => println!("Failed syncing commands for server {}: {}", guild.name, why)
         }
     }
 
     async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        if let Interaction::Autocomplete(autocomplete) = &interaction {
+            if matches!(autocomplete.data.name.as_str(), "invite" | "profile" | "fence") {
+                if let Err(why) = autocomplete.create_autocomplete_response(&ctx.http, |response| {
+                    commands::invite::autocomplete(autocomplete, self, response)
+                }).await {
+                    println!("Cannot respond to autocomplete: {}", why);
+                }
+            }
+            return;
+        }
+
+        if let Interaction::MessageComponent(component) = &interaction {
+            if component.data.custom_id.starts_with("swipe:") {
+                swipes::run_button(&ctx, component, self).await;
+            }
+            else if component.data.custom_id.starts_with("list:") {
+                let is_owner = commands::list::is_owner(component);
+                if let Err(why) = component.create_interaction_response(&ctx.http, |response| {
+                    match is_owner {
+                        true => response
+                            .kind(InteractionResponseType::UpdateMessage)
+                            .interaction_response_data(|message| {
+                                commands::list::run_button(component, self, message);
+                                message
+                            }),
+                        false => response
+                            .kind(InteractionResponseType::ChannelMessageWithSource)
+                            .interaction_response_data(|message| message
+                                .ephemeral(true)
+                                .content("Only who ran this /list can turn its pages, run your own!"))
+                    }
+                }).await {
+                    println!("Cannot respond to button: {}", why);
+                }
+            }
+            return;
+        }
+
+        if let Interaction::ModalSubmit(modal) = &interaction {
+            if modal.data.custom_id.starts_with("character_") {
+                commands::character::submit(&ctx, modal, self).await;
+            }
+            return;
+        }
+
         if let Interaction::ApplicationCommand(command) = interaction {
+            if command.data.name.as_str() == "character" {
+                commands::character::run(&ctx, &command, self).await;
+                return;
+            }
+            if command.data.name.as_str() == "fence" {
+                commands::fence::run(&ctx, &command, self).await;
+                return;
+            }
+            if command.data.name.as_str() == "imagine" {
+                commands::imagine::run(&ctx, &command, self).await;
+                return;
+            }
+            if command.data.name.as_str() == "export" {
+                commands::export::run(&ctx, &command, self).await;
+                return;
+            }
+            if command.data.name.as_str() == "import" {
+                commands::import::run(&ctx, &command, self).await;
+                return;
+            }
+            #[cfg(feature = "voice")]
+            if command.data.name.as_str() == "voice" {
+                commands::voice::run(&ctx, &command, self).await;
+                return;
+            }
+
             if let Err(why) = command.create_interaction_response(&ctx.http, |response| {
                 response
                     .kind(InteractionResponseType::ChannelMessageWithSource)
                     .interaction_response_data(|message| {
                         match command.data.name.as_str() {
-                            "invite" => {commands::invite::run(&command, &self, message)},
-                            "fence" => {commands::fence::run(&command, &self, message)},
+                            "invite" => {commands::invite::run(&command, self, message)},
                             "uninvite" => {
-                                commands::uninvite::run(&command, &self, message);
+                                commands::uninvite::run(&command, self, message);
                             },
-                            "list" => {commands::list::run(&command, &self, message)},
+                            "list" => {commands::list::run(&command, self, message)},
+                            "profile" => {commands::profile::run(&command, self, message)},
+                            "callme" => {commands::callme::run(&command, self, message)},
+                            "persona" => {commands::persona::run(&command, self, message)},
+                            "note" => {commands::note::run(&command, self, message)},
                             _ => {message.content("Command not implemented");}
                         };
-                        return message;
+                        message
                     })
                 })
                 .await
@@ -71,98 +214,36 @@ impl EventHandler for BotManager {
 
             match command.data.name.as_str() {
                 "uninvite" => {
-                    self.delete_webhook(&ctx, &command.channel_id).await
+                    if let Err(why) = DiscordPlatform::new(&ctx).delete_webhook(&command.channel_id).await {
+                        println!("Failed deleting webhook: {}", why);
+                    }
                 },
+                "invite" => commands::invite::greet(&ctx, &command, self).await,
                 _ => {}
-            };
+            }
 
         }
     }
 
     async fn message(&self, context: Context, msg: Message) {
-        if msg.author.bot {
-            return; // No infinite loops pls
-        }
-
-        let messages = msg.channel_id.messages(&context, |builder| builder.limit(10)).await.expect("Failed getting message history");
-        let cache = &context.cache;
-        let mut history : Vec<crate::textgen::api::Message> = messages.iter()
-            .take_while(|discord_msg| !(discord_msg.is_own(&context.cache) && discord_msg.content.contains("--- Message Fence ---")))
-            .map(|discord_msg| crate::textgen::api::Message {
-                speaker: String::from(&discord_msg.author.name),
-                content: discord_msg.content_safe(cache)
-            })
-            .filter(|message| !message.content.is_empty())
-            .collect::<Vec<crate::textgen::api::Message>>();
-
-        history.reverse();
-
-        let mut prompt = None;
-        let mut name = None;
-        let mut avatar = None;
-        {
-            let data = self.data.try_lock().unwrap();
-            let character_in_channel = data.invited_characters.get(&msg.channel_id);
-            match character_in_channel {
-                Some(character) => {
-                    let character_def = data.characters.get(character).expect("Character is invited but not loaded");
-                    prompt = Some(self.api.make_prompt(character_def, &history).expect("Failed making prompt"));
-                    name = Some(character_def.char_name.to_owned());
-                    avatar = Some(character_def.avatar_url.to_owned());
-                },
-                None => return
-            };
-        }
-
-        match prompt {
-            Some(prompt_value) => {
-                let typing = msg.channel_id.start_typing(&context.http).expect("Failed saying I'm typing");
-                let result = match self.api.request(prompt_value).await
-                {
-                    Ok(res) => res,
-                    Err(err) => {println!("Received no response from API: {:?}", err); return;}
-                };
-                let webhook = self.ensure_webhook(&context, &msg.channel_id).await;
-                typing.stop().expect("Failed saying I'm no longer typing");
-                webhook.execute(context.http, false, |hook| hook
-                    .content(result)
-                    .username(name.unwrap())
-                    .avatar_url(avatar.unwrap())
-                ).await.expect("Error sending message");
-            }
-            None => {return;}
-        }
+        let platform = DiscordPlatform::new(&context);
+        let message = discord::platform_message(&context, &msg);
+        Conversation::new(&self.api, &self.data).on_message(&platform, &msg.channel_id.to_string(), &message).await;
     }
 }
 
-impl BotManager {
-    async fn get_webhook(&self, context: &Context, channel: &ChannelId) -> Option<Webhook> {
-        let webhooks = channel.webhooks(&context.http).await.expect("Failed getting webhook list");
-        for webhook in webhooks {
-            let name = webhook.name.to_owned().unwrap_or_default();
-            if String::from("Uc207_Bot").eq(&name) {
-                return Some(webhook);
+async fn health_check_loop(api: Arc<TextgenApi>, context: Context) {
+    let mut interval = tokio::time::interval(api.health_check_interval());
+    let mut last_model = None;
+    loop {
+        interval.tick().await;
+        let model = api.health_check().await;
+        if model != last_model {
+            match &model {
+                Some(model) => context.set_activity(Activity::playing(model)).await,
+                None => context.set_activity(Activity::playing("Backend unavailable")).await
             }
+            last_model = model;
         }
-        return None;
     }
-
-    pub async fn ensure_webhook(&self, context: &Context, channel: &ChannelId) -> Webhook {
-        match self.get_webhook(context, channel).await {
-            Some(webhook) => webhook,
-            None => {
-                channel.create_webhook(&context.http, "Uc207_Bot").await.expect("Failed making webhook")
-            }
-        }
-    }
-
-    pub async fn delete_webhook (&self, context: &Context, channel: &ChannelId) {
-        match self.get_webhook(context, channel).await {
-            Some(webhook) => {
-                webhook.delete(&context.http).await.expect("Failed deleting webhook")
-            },
-            None => {
-            }
-        }
-    }
-}
\ No newline at end of file
+}
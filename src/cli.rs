@@ -0,0 +1,191 @@
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::{Parser, Subcommand};
+use serenity::http::{GuildPagination, Http};
+use serenity::model::prelude::GuildId;
+
+use crate::commands;
+use crate::config::{CommandScope, Config, CONFIG_FILE_NAMES};
+use crate::textgen::character::Character;
+use crate::validation;
+
+#[derive(Parser)]
+#[command(name = "uc207", version, about = "Discord and Matrix chatbot manager")]
+pub struct Cli {
+    /// Directory containing the config file and the characters directory
+    #[arg(long, env = "UC207_DATA_DIR", default_value = ".", global = true)]
+    pub data_dir: PathBuf,
+    /// Config file, TOML, YAML or JSON [default: <data-dir>/config.toml, .yaml, .yml or .json]
+    #[arg(long, env = "UC207_CONFIG", global = true)]
+    pub config: Option<PathBuf>,
+    /// Characters directory, overrides `storage.characters_dir` in the config
+    #[arg(long, env = "UC207_CHARACTERS_DIR", global = true)]
+    pub characters_dir: Option<PathBuf>,
+    /// File containing the Discord bot token, overrides `DISCORD_TOKEN` and the token of the config
+    #[arg(long, env = "UC207_TOKEN_FILE", global = true)]
+    pub token_file: Option<PathBuf>,
+    /// Overrides a config setting, e.g. `--set sampling.temperature=0.5`. Can be repeated.
+    #[arg(long = "set", value_name = "SECTION.SETTING=VALUE", global = true)]
+    pub overrides: Vec<String>,
+    #[command(subcommand)]
+    pub command: Option<CliCommand>,
+}
+
+#[derive(Subcommand)]
+pub enum CliCommand {
+    /// Start the bots (default)
+    Run,
+    /// Check the config, prompt template and characters without starting anything
+    Validate,
+    /// Chat with a character in the terminal
+    Chat {
+        character_id: String,
+    },
+    /// List the loaded characters
+    ListCharacters,
+    /// Copy a character file into the characters directory
+    Import {
+        file: PathBuf,
+        /// ID of the imported character [default: the file name]
+        #[arg(long)]
+        id: Option<String>,
+        /// Replace an existing character with the same ID
+        #[arg(long)]
+        force: bool,
+    },
+    /// Sync the slash commands with Discord, in the scope set by `discord.command_scope`
+    RegisterCommands {
+        /// Only sync this server's commands, instead of every server the bot is in
+        #[arg(long)]
+        guild: Option<u64>,
+    },
+}
+
+impl Cli {
+    /// The `--config` file, or else the first config file found in the data directory
+    pub fn config_path(&self) -> String {
+        let path = match &self.config {
+            Some(config) => config.clone(),
+            None => CONFIG_FILE_NAMES.iter()
+                .map(|name| self.data_dir.join(name))
+                .find(|path| path.exists())
+                .unwrap_or_else(|| self.data_dir.join("config.json"))
+        };
+        path_string(path)
+    }
+
+    /// Loads the config file with environment overrides, then applies the command line flags on top
+    pub fn load_config(&self) -> Result<Config, Box<dyn Error>> {
+        let mut config = Config::load(&self.config_path(), &self.overrides)?;
+        if let Some(characters_dir) = &self.characters_dir {
+            config.storage.characters_dir = path_string(characters_dir.clone());
+        }
+        if let Some(token_file) = &self.token_file {
+            config.discord.token = None;
+            config.discord.token_file = Some(path_string(token_file.clone()));
+        }
+        Ok(config)
+    }
+
+    /// Characters directory of the config, or where it would be if the config can't be loaded
+    fn characters_path(&self) -> String {
+        match self.load_config() {
+            Ok(config) => config.storage.characters_dir,
+            Err(_) => path_string(self.characters_dir.clone().unwrap_or_else(|| self.data_dir.join("characters")))
+        }
+    }
+}
+
+fn path_string(path: PathBuf) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+/// Prints every problem found, returns whether everything is valid
+pub fn validate(cli: &Cli) -> bool {
+    let mut problems = validation::check_config(&cli.config_path());
+    let (checked, character_problems) = validation::check_characters(&cli.characters_path());
+    problems.extend(character_problems);
+
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    println!("Checked config and {} characters, found {} problems", checked, problems.len());
+    problems.is_empty()
+}
+
+pub fn list_characters(characters_dir: &str) -> Result<(), Box<dyn Error>> {
+    let characters = Character::load_all(characters_dir)?;
+    let mut ids: Vec<&String> = characters.keys().collect();
+    ids.sort();
+    for id in ids {
+        let character = &characters[id];
+        println!("{}\t{}\t{}", id, character.char_name, character.visibility.as_str());
+    }
+    Ok(())
+}
+
+pub fn import(characters_dir: &str, file: &Path, id: Option<&str>, force: bool) -> Result<String, Box<dyn Error>> {
+    let character: Character = serde_json::from_str(&fs::read_to_string(file)?)?;
+    let id = match id {
+        Some(id) => String::from(id),
+        None => file.file_stem().map(|stem| stem.to_string_lossy().to_lowercase()).unwrap_or_default()
+    };
+    if !Character::is_valid_id(&id) {
+        return Err(string_error::into_err(format!("Invalid character ID {}, pass one with --id", id)));
+    }
+    let problems = character.validate();
+    if !problems.is_empty() {
+        return Err(string_error::into_err(problems.join("\n")));
+    }
+
+    if !force && Path::new(characters_dir).join([&id, ".json"].join("")).exists() {
+        return Err(string_error::into_err(format!("Character {} already exists, use --force to replace it", id)));
+    }
+    character.save(characters_dir, &id)?;
+    Ok(id)
+}
+
+/// Brings the registered commands in line with the definitions: in every server (or only `guild`) for the guild scope, globally
+/// for the global scope, removing them from the other scope
+pub async fn register_commands(token: &str, scope: CommandScope, guild: Option<u64>) -> Result<(), Box<dyn Error>> {
+    let http = Http::new(token);
+    let application = http.get_current_application_info().await?;
+    http.set_application_id(application.id.0);
+
+    let global = scope == CommandScope::Global;
+    let updated = commands::sync(&http, None, global).await?;
+    println!("Global commands: {}", sync_status(updated, global));
+
+    let guilds = match guild {
+        Some(guild) => vec![GuildId(guild)],
+        None => all_guilds(&http).await?
+    };
+    for guild in guilds {
+        let updated = commands::sync(&http, Some(guild), !global).await?;
+        println!("Commands for server {}: {}", guild, sync_status(updated, !global));
+    }
+    Ok(())
+}
+
+/// Servers the bot is in, a page of at most 100 at a time
+async fn all_guilds(http: &Http) -> Result<Vec<GuildId>, Box<dyn Error>> {
+    let mut guilds = Vec::new();
+    loop {
+        let after = guilds.last().map(|guild| GuildPagination::After(*guild));
+        let page = http.get_guilds(after.as_ref(), Some(100)).await?;
+        if page.is_empty() {
+            return Ok(guilds);
+        }
+        guilds.extend(page.into_iter().map(|guild| guild.id));
+    }
+}
+
+fn sync_status(updated: bool, enabled: bool) -> &'static str {
+    match (updated, enabled) {
+        (false, _) => "up to date",
+        (true, true) => "registered",
+        (true, false) => "removed"
+    }
+}
@@ -0,0 +1,46 @@
+use serenity::{builder::{self, CreateInteractionResponseData}, model::prelude::{command::CommandOptionType, interaction::application_command::{ApplicationCommandInteraction}}};
+
+use crate::botmanager::BotManager;
+use super::persona::{MAX_DESCRIPTION_LENGTH, MAX_NAME_LENGTH};
+use super::string_option;
+
+pub fn register (command: &mut builder::CreateApplicationCommand) -> &mut builder::CreateApplicationCommand
+{
+    command
+        .name("callme")
+        .description("Choose the name bots know you by everywhere, or go back to your Discord name")
+        .create_option(|option| {
+            option
+                .name("name")
+                .description("Name the bots call you, leave out to use your Discord name again")
+                .kind(CommandOptionType::String)
+                .max_length(MAX_NAME_LENGTH)
+        })
+        .create_option(|option| {
+            option
+                .name("about")
+                .description("A few words about yourself, for prompts that use them")
+                .kind(CommandOptionType::String)
+                .max_length(MAX_DESCRIPTION_LENGTH)
+        })
+}
+
+pub fn run (command: &ApplicationCommandInteraction, manager: &BotManager, msg: &mut CreateInteractionResponseData){
+    let options = &command.data.options;
+    let (name, description) = (string_option(options, "name").unwrap_or_default(), string_option(options, "about"));
+    let user_id = command.user.id.to_string();
+
+    let mut data = manager.data.lock().unwrap();
+    let content = match name.is_empty() {
+        true => String::from("Bots will call you by your Discord name again."),
+        false => format!("Bots will call you {} from now on.", name)
+    };
+    let result = data.user_profiles.set_name(&user_id, &name, description).map(|_| content);
+    match result {
+        Ok(content) => msg.content(content),
+        Err(why) => {
+            println!("Failed saving user profiles: {}", why);
+            msg.content("Couldn't save that, try again later!")
+        }
+    }.ephemeral(true);
+}
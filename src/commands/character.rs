@@ -0,0 +1,487 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use serenity::{builder::{self, CreateComponents, CreateEmbed}, model::prelude::{AttachmentType, command::CommandOptionType, component::{ActionRowComponent, InputTextStyle}, interaction::{InteractionResponseType, application_command::{ApplicationCommandInteraction, CommandDataOptionValue}, modal::ModalSubmitInteraction}}, prelude::Context};
+
+use crate::botmanager::BotManager;
+use crate::textgen::{api::Message, character::{Character, Visibility}};
+use super::find_option;
+
+/// Avatars are shown small, larger pictures aren't worth keeping
+const MAX_AVATAR_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Told when an uploaded avatar can only link to the upload
+const EXPIRING_AVATAR: &str = "The avatar links to your upload, which Discord expires after about a day. Ask the bot's host to set `storage.avatars_url` to keep avatars.";
+
+/// What `/character create` or `edit` was given besides the modal's inputs, until the modal is submitted
+pub struct PendingCharacter {
+    pub visibility: Visibility,
+    /// URL and file extension of the uploaded avatar
+    pub avatar: Option<(String, &'static str)>,
+}
+
+pub fn register (command: &mut builder::CreateApplicationCommand) -> &mut builder::CreateApplicationCommand
+{
+    command
+        .name("character")
+        .description("Create and manage your own bots")
+        .create_option(|sub| {
+            sub
+                .name("create")
+                .description("Create a new bot")
+                .kind(CommandOptionType::SubCommand)
+                .create_sub_option(|option| id_option(option, "ID used to invite the new bot (lowercase letters, digits, - and _)"))
+                .create_sub_option(avatar_option)
+                .create_sub_option(visibility_option)
+        })
+        .create_option(|sub| {
+            sub
+                .name("edit")
+                .description("Edit one of your bots")
+                .kind(CommandOptionType::SubCommand)
+                .create_sub_option(|option| id_option(option, "The bot's ID"))
+                .create_sub_option(avatar_option)
+                .create_sub_option(visibility_option)
+        })
+        .create_option(|sub| {
+            sub
+                .name("delete")
+                .description("Delete one of your bots")
+                .kind(CommandOptionType::SubCommand)
+                .create_sub_option(|option| id_option(option, "The bot's ID"))
+        })
+        .create_option(|sub| {
+            sub
+                .name("export")
+                .description("Download a bot's definition file")
+                .kind(CommandOptionType::SubCommand)
+                .create_sub_option(|option| id_option(option, "The bot's ID"))
+        })
+}
+
+fn id_option<'a>(option: &'a mut builder::CreateApplicationCommandOption, description: &str) -> &'a mut builder::CreateApplicationCommandOption {
+    option
+        .name("id")
+        .description(description)
+        .kind(CommandOptionType::String)
+        .required(true)
+}
+
+fn avatar_option(option: &mut builder::CreateApplicationCommandOption) -> &mut builder::CreateApplicationCommandOption {
+    option
+        .name("avatar")
+        .description("Profile picture for the bot, instead of an image URL")
+        .kind(CommandOptionType::Attachment)
+        .required(false)
+}
+
+fn visibility_option(option: &mut builder::CreateApplicationCommandOption) -> &mut builder::CreateApplicationCommandOption {
+    option
+        .name("visibility")
+        .description("Who can see and invite the bot (defaults to this server only)")
+        .kind(CommandOptionType::String)
+        .add_string_choice("Everyone, in every server", "global")
+        .add_string_choice("Everyone in this server", "guild")
+        .add_string_choice("Only me", "private")
+        .required(false)
+}
+
+pub async fn run (ctx: &Context, command: &ApplicationCommandInteraction, manager: &BotManager) {
+    let subcommand = match command.data.options.first() {
+        Some(sub) => sub,
+        None => return
+    };
+    let id = match find_option(&subcommand.options, "id") {
+        Some(CommandDataOptionValue::String(id)) => id.to_owned(),
+        _ => {
+            reply(ctx, command, "Expected bot ID!").await;
+            return;
+        }
+    };
+    let visibility = match find_option(&subcommand.options, "visibility") {
+        Some(CommandDataOptionValue::String(visibility)) => Visibility::parse(visibility),
+        _ => None
+    };
+    // The upload is only downloaded once the modal is submitted, there's no time for it before showing the modal
+    let avatar = match find_option(&subcommand.options, "avatar") {
+        Some(CommandDataOptionValue::Attachment(attachment)) => {
+            match attachment.content_type.as_deref().and_then(avatar_extension) {
+                Some(extension) if attachment.size <= MAX_AVATAR_SIZE => Some((attachment.url.to_owned(), extension)),
+                _ => {
+                    reply(ctx, command, "The avatar must be a PNG, JPEG, GIF or WebP picture of at most 4 MB!").await;
+                    return;
+                }
+            }
+        },
+        _ => None
+    };
+    let uploaded = avatar.is_some();
+
+    match subcommand.name.as_str() {
+        "create" => {
+            if !Character::is_valid_id(&id) {
+                reply(ctx, command, "IDs can only contain lowercase letters, digits, `-` and `_`, and must be at most 32 characters long.").await;
+                return;
+            }
+            if manager.data.lock().unwrap().characters.contains_key(&id) {
+                reply(ctx, command, "A bot with this ID already exists!").await;
+                return;
+            }
+            let template = Character {
+                visibility: scoped(visibility.unwrap_or(Visibility::Guild), command.guild_id.is_some()),
+                ..Default::default()
+            };
+            let pending = PendingCharacter { visibility: template.visibility, avatar };
+            manager.data.lock().unwrap().pending_characters.insert((command.user.id.0, id.to_owned()), pending);
+            show_modal(ctx, command, &["character_create:", &id].join(""), "Create a bot", &template, uploaded).await;
+        },
+        "edit" => {
+            let existing = match command_character(manager, command, &id) {
+                Ok(character) => character,
+                Err(why) => {
+                    reply(ctx, command, &why).await;
+                    return;
+                }
+            };
+            let mut edited = existing;
+            if let Some(visibility) = visibility {
+                edited.visibility = scoped(visibility, edited.guild_id.is_some());
+            }
+            if uploaded {
+                edited.avatar_url.clear();
+            }
+            let pending = PendingCharacter { visibility: edited.visibility, avatar };
+            manager.data.lock().unwrap().pending_characters.insert((command.user.id.0, id.to_owned()), pending);
+            show_modal(ctx, command, &["character_edit:", &id].join(""), "Edit bot", &edited, uploaded).await;
+        },
+        "delete" => {
+            if let Err(why) = command_character(manager, command, &id) {
+                reply(ctx, command, &why).await;
+                return;
+            }
+            let result = Character::delete(&manager.characters_dir, &id).map_err(|why| why.to_string());
+            match result {
+                Ok(()) => {
+                    if let Err(why) = Character::delete_avatar(&manager.avatars_dir, &id) {
+                        println!("Failed deleting the avatar of {}: {}", id, why);
+                    }
+                    let mut data = manager.data.lock().unwrap();
+                    data.characters.remove(&id);
+                    data.invited_characters.retain(|_, invited| invited != &id);
+                },
+                Err(why) => {
+                    println!("Failed deleting character {}: {}", id, why);
+                    reply(ctx, command, "Failed deleting the bot's file!").await;
+                    return;
+                }
+            }
+            reply(ctx, command, "Bot deleted!").await;
+        },
+        "export" => {
+            let json = {
+                let data = manager.data.lock().unwrap();
+                data.characters.get(&id)
+                    .filter(|character| character.is_visible_to(command.user.id.0, command.guild_id.map(|guild| guild.0)))
+                    .map(serde_json::to_vec_pretty)
+            };
+            let json = match json {
+                Some(Ok(json)) => json,
+                Some(Err(why)) => {
+                    println!("Failed serializing character {}: {:?}", id, why);
+                    reply(ctx, command, "Failed exporting the bot!").await;
+                    return;
+                },
+                None => {
+                    reply(ctx, command, "The selected bot ID doesn't exist!").await;
+                    return;
+                }
+            };
+            if let Err(why) = command.create_interaction_response(&ctx.http, |response| {
+                response
+                    .kind(InteractionResponseType::ChannelMessageWithSource)
+                    .interaction_response_data(|message| message
+                        .ephemeral(true)
+                        .add_file(AttachmentType::Bytes { data: Cow::from(json), filename: [&id, ".json"].join("") }))
+            }).await {
+                println!("Cannot respond to slash command: {}", why);
+            }
+        },
+        _ => reply(ctx, command, "Command not implemented").await
+    }
+}
+
+pub async fn submit (ctx: &Context, modal: &ModalSubmitInteraction, manager: &BotManager) {
+    let (action, id) = match modal.data.custom_id.split_once(':') {
+        Some(parts) => parts,
+        None => return
+    };
+    // The visibility and upload aren't among the modal's inputs, they were kept when the modal was shown
+    let pending = manager.data.lock().unwrap().pending_characters.remove(&(modal.user.id.0, String::from(id)));
+    let pending_visibility = pending.as_ref().map(|pending| pending.visibility);
+
+    let mut character = Character {
+        char_name: input_value(modal, "name"),
+        char_description: input_value(modal, "description"),
+        char_persona: input_value(modal, "persona"),
+        example_dialogue: Vec::new(),
+        avatar_url: input_value(modal, "avatar").trim().to_owned(),
+        owner_id: Some(modal.user.id.0),
+        guild_id: modal.guild_id.map(|guild| guild.0),
+        visibility: pending_visibility.unwrap_or(Visibility::Guild),
+        ..Default::default()
+    };
+    // An avatar URL typed in the modal wins over the upload
+    let upload = pending.and_then(|pending| pending.avatar).filter(|_| character.avatar_url.is_empty());
+
+    let mut problems = match Message::parse_conversation(&input_value(modal, "example")) {
+        Ok(example) => {
+            character.example_dialogue = example;
+            Vec::new()
+        },
+        Err(why) => vec![why.to_string()]
+    };
+    problems.extend(character.validate());
+
+    {
+        let data = manager.data.lock().unwrap();
+        // The modal's custom ID comes back from the client, so it's checked again like in `run`
+        if !Character::is_valid_id(id) {
+            problems.push(String::from("IDs can only contain lowercase letters, digits, `-` and `_`, and must be at most 32 characters long."));
+        }
+        match action {
+            "character_create" if data.characters.contains_key(id) => problems.push(String::from("A bot with this ID already exists!")),
+            "character_create" => {},
+            "character_edit" => match owned_character(&data.characters, modal.user.id.0, modal.guild_id.map(|guild| guild.0), id) {
+                // Keep everything the modal doesn't cover
+                Ok(existing) => character = Character {
+                    char_name: character.char_name,
+                    char_description: character.char_description,
+                    char_persona: character.char_persona,
+                    example_dialogue: character.example_dialogue,
+                    avatar_url: character.avatar_url,
+                    visibility: pending_visibility.unwrap_or(existing.visibility),
+                    ..existing
+                },
+                Err(why) => problems.push(why)
+            },
+            _ => problems.push(String::from("Unknown bot action!"))
+        }
+        character.visibility = scoped(character.visibility, character.guild_id.is_some());
+    }
+    let title = if action == "character_create" {"Bot created!"} else {"Bot updated!"};
+    if !problems.is_empty() {
+        answer(ctx, modal, false, title, id, &character, Err(["The bot couldn't be saved:\n- ", &problems.join("\n- ")].join(""))).await;
+        return;
+    }
+
+    // Downloading the upload can take longer than Discord waits for an answer
+    let deferred = upload.is_some();
+    let mut notice = None;
+    if let Some((url, extension)) = upload {
+        if let Err(why) = modal.create_interaction_response(&ctx.http, |response| {
+            response.kind(InteractionResponseType::DeferredChannelMessageWithSource)
+        }).await {
+            println!("Cannot respond to modal: {}", why);
+            return;
+        }
+        match store_avatar(manager, id, &url, extension).await {
+            Ok(Some(avatar_url)) => character.avatar_url = avatar_url,
+            Ok(None) => {
+                character.avatar_url = url;
+                notice = Some(EXPIRING_AVATAR);
+            },
+            Err(why) => {
+                println!("Failed saving the avatar of {}: {}", id, why);
+                answer(ctx, modal, true, title, id, &character, Err(String::from("Failed saving the avatar!"))).await;
+                return;
+            }
+        }
+    }
+
+    let saved = {
+        let mut data = manager.data.lock().unwrap();
+        let saved = character.save(&manager.characters_dir, id).map_err(|why| why.to_string());
+        if saved.is_ok() {
+            data.characters.insert(String::from(id), character.clone());
+        }
+        saved
+    };
+    let result = match saved {
+        Ok(()) => Ok(notice),
+        Err(why) => {
+            println!("Failed saving character {}: {}", id, why);
+            Err(String::from("Failed saving the bot's file!"))
+        }
+    };
+    answer(ctx, modal, deferred, title, id, &character, result).await;
+}
+
+/// Downloads an uploaded avatar into `storage.avatars_dir`, returning where it's served if `storage.avatars_url` is set
+async fn store_avatar(manager: &BotManager, id: &str, url: &str, extension: &str) -> Result<Option<String>, String> {
+    let image = match reqwest::get(url).await.and_then(|response| response.error_for_status()) {
+        Ok(response) => response.bytes().await.map_err(|why| why.to_string())?,
+        Err(why) => return Err(why.to_string())
+    };
+    let file_name = Character::save_avatar(&manager.avatars_dir, id, extension, &image).map_err(|why| why.to_string())?;
+    if manager.avatars_url.is_empty() {
+        return Ok(None);
+    }
+    // Discord caches avatars by URL, a new upload needs a new one
+    let version = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or_default();
+    Ok(Some(format!("{}/{}?v={}", manager.avatars_url.trim_end_matches('/'), file_name, version)))
+}
+
+/// Answers the modal with the saved bot and an optional notice, or privately with what went wrong.
+/// A deferred answer is edited, or replaced with a private follow-up for problems.
+async fn answer(ctx: &Context, modal: &ModalSubmitInteraction, deferred: bool, title: &str, id: &str, character: &Character, result: Result<Option<&str>, String>) {
+    let embed = |embed: &mut CreateEmbed| {
+        embed
+            .title(title)
+            .description(["Use `/invite ", id, "` to invite ", &character.char_name, " to a channel!"].join(""))
+            .image(&character.avatar_url);
+    };
+    let answered = match (&result, deferred) {
+        (Ok(notice), false) => modal.create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|message| {
+                    if let Some(notice) = notice {
+                        message.content(notice);
+                    }
+                    message.embed(|e| {embed(e); e})
+                })
+        }).await,
+        (Ok(notice), true) => modal.edit_original_interaction_response(&ctx.http, |response| {
+            if let Some(notice) = notice {
+                response.content(notice);
+            }
+            response.embed(|e| {embed(e); e})
+        }).await.map(|_| ()),
+        (Err(problem), false) => modal.create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|message| message.ephemeral(true).content(problem))
+        }).await,
+        (Err(problem), true) => match modal.delete_original_interaction_response(&ctx.http).await {
+            Ok(()) => modal.create_followup_message(&ctx.http, |message| message.ephemeral(true).content(problem)).await.map(|_| ()),
+            Err(why) => Err(why)
+        }
+    };
+    if let Err(why) = answered {
+        println!("Cannot respond to modal: {}", why);
+    }
+}
+
+/// File extension of the image types avatars can be uploaded as
+pub fn avatar_extension(content_type: &str) -> Option<&'static str> {
+    match content_type {
+        "image/png" => Some("png"),
+        "image/jpeg" => Some("jpg"),
+        "image/gif" => Some("gif"),
+        "image/webp" => Some("webp"),
+        _ => None
+    }
+}
+
+/// Characters made outside of a server have no server to be limited to, so they're private instead
+fn scoped(visibility: Visibility, in_guild: bool) -> Visibility {
+    match visibility {
+        Visibility::Guild if !in_guild => Visibility::Private,
+        visibility => visibility
+    }
+}
+
+/// Returns the character if the user is allowed to modify it from that server (or DMs with `None`)
+fn owned_character(characters: &HashMap<String, Character>, user_id: u64, guild_id: Option<u64>, id: &str) -> Result<Character, String> {
+    let character = match characters.get(id) {
+        Some(character) => character,
+        None => return Err(String::from("The selected bot ID doesn't exist!"))
+    };
+    if character.owner_id != Some(user_id) {
+        return Err(String::from("You can only modify bots you created!"));
+    }
+    if character.guild_id.is_some() && character.guild_id != guild_id {
+        return Err(String::from("This bot belongs to another server!"));
+    }
+    Ok(character.clone())
+}
+
+/// Same as `owned_character`, for the user running the command
+fn command_character(manager: &BotManager, command: &ApplicationCommandInteraction, id: &str) -> Result<Character, String> {
+    let data = manager.data.lock().unwrap();
+    owned_character(&data.characters, command.user.id.0, command.guild_id.map(|guild| guild.0), id)
+}
+
+/// Shows the character's fields, with the avatar's left empty to use the upload if there's one
+async fn show_modal(ctx: &Context, command: &ApplicationCommandInteraction, custom_id: &str, title: &str, character: &Character, uploaded: bool) {
+    let mut components = CreateComponents::default();
+    components
+        .create_action_row(|row| row.create_input_text(|input| input
+            .custom_id("name")
+            .label("Name")
+            .style(InputTextStyle::Short)
+            .max_length(80)
+            .value(&character.char_name)
+            .required(true)))
+        .create_action_row(|row| row.create_input_text(|input| input
+            .custom_id("description")
+            .label("Description (shown in /list)")
+            .style(InputTextStyle::Paragraph)
+            .max_length(1000)
+            .value(&character.char_description)
+            .required(false)))
+        .create_action_row(|row| row.create_input_text(|input| input
+            .custom_id("persona")
+            .label("Persona (what the AI is told)")
+            .style(InputTextStyle::Paragraph)
+            .max_length(4000)
+            .value(&character.char_persona)
+            .required(true)))
+        .create_action_row(|row| row.create_input_text(|input| input
+            .custom_id("example")
+            .label("Example dialogue (\"Speaker: message\" lines)")
+            .style(InputTextStyle::Paragraph)
+            .max_length(4000)
+            .value(Message::format_conversation(&character.example_dialogue))
+            .required(false)))
+        .create_action_row(|row| row.create_input_text(|input| input
+            .custom_id("avatar")
+            .label(if uploaded {"Avatar URL (empty to use the uploaded one)"} else {"Avatar URL"})
+            .style(InputTextStyle::Short)
+            .value(&character.avatar_url)
+            .required(false)));
+
+    if let Err(why) = command.create_interaction_response(&ctx.http, |response| {
+        response
+            .kind(InteractionResponseType::Modal)
+            .interaction_response_data(|modal| modal
+                .custom_id(custom_id)
+                .title(title)
+                .set_components(components))
+    }).await {
+        println!("Cannot show modal: {}", why);
+    }
+}
+
+async fn reply(ctx: &Context, command: &ApplicationCommandInteraction, content: &str) {
+    if let Err(why) = command.create_interaction_response(&ctx.http, |response| {
+        response
+            .kind(InteractionResponseType::ChannelMessageWithSource)
+            .interaction_response_data(|message| message.ephemeral(true).content(content))
+    }).await {
+        println!("Cannot respond to slash command: {}", why);
+    }
+}
+
+fn input_value(modal: &ModalSubmitInteraction, custom_id: &str) -> String {
+    for row in &modal.data.components {
+        for component in &row.components {
+            if let ActionRowComponent::InputText(input) = component {
+                if input.custom_id == custom_id {
+                    return input.value.to_owned();
+                }
+            }
+        }
+    }
+    String::new()
+}
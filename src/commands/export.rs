@@ -0,0 +1,91 @@
+use std::borrow::Cow;
+
+use serenity::{builder, model::{Timestamp, prelude::{AttachmentType, command::CommandOptionType, interaction::{InteractionResponseType, application_command::{ApplicationCommandInteraction}}}}, prelude::Context};
+
+use crate::botmanager::BotManager;
+use crate::conversation::Conversation;
+use crate::platform::discord::DiscordPlatform;
+use crate::transcript::{self, ExportFormat};
+use super::string_option;
+
+pub fn register (command: &mut builder::CreateApplicationCommand) -> &mut builder::CreateApplicationCommand
+{
+    command
+        .name("export")
+        .description("Save the chat in this channel to a file, back to the latest fence")
+        .create_option(|option| {
+            option
+                .name("format")
+                .description("File format (plain text by default)")
+                .kind(CommandOptionType::String)
+                .add_string_choice("Plain text", ExportFormat::Text.name())
+                .add_string_choice("JSONL", ExportFormat::Jsonl.name())
+                .add_string_choice("SillyTavern", ExportFormat::SillyTavern.name())
+                .add_string_choice("HTML", ExportFormat::Html.name())
+                .required(false)
+        })
+        .create_option(|option| {
+            option
+                .name("from")
+                .description("ID of the first message to export, instead of the latest fence")
+                .kind(CommandOptionType::String)
+                .required(false)
+        })
+        .create_option(|option| {
+            option
+                .name("to")
+                .description("ID of the last message to export")
+                .kind(CommandOptionType::String)
+                .required(false)
+        })
+}
+
+pub async fn run (ctx: &Context, command: &ApplicationCommandInteraction, manager: &BotManager) {
+    let option = |name: &str| string_option(&command.data.options, name);
+    let format = option("format").and_then(|format| ExportFormat::parse(&format)).unwrap_or(ExportFormat::Text);
+    let (from, to) = (option("from"), option("to"));
+
+    // Fetching a long history takes longer than the 3 seconds Discord waits for an answer
+    if let Err(why) = command.create_interaction_response(&ctx.http, |response| {
+        response
+            .kind(InteractionResponseType::DeferredChannelMessageWithSource)
+            .interaction_response_data(|message| message.ephemeral(true))
+    }).await {
+        println!("Cannot respond to slash command: {}", why);
+        return;
+    }
+
+    let channel = command.channel_id.to_string();
+    let conversation = Conversation::new(&manager.api, &manager.data);
+    let result = conversation.transcript(&DiscordPlatform::new(ctx), &channel, from.as_deref(), to.as_deref()).await
+        .map_err(|err| err.to_string());
+    let messages = match result {
+        Ok(messages) if messages.is_empty() => {
+            followup(ctx, command, "There's nothing to export!", None).await;
+            return;
+        },
+        Ok(messages) => messages,
+        Err(why) => {
+            println!("Failed exporting chat: {}", why);
+            followup(ctx, command, &format!("Couldn't export the chat: {}", why), None).await;
+            return;
+        }
+    };
+
+    let character_name = conversation.persona(&channel).map(|persona| persona.name).unwrap_or_default();
+    let file = transcript::export(format, &messages, &character_name, &Timestamp::now().to_string());
+    let file_name = format.file_name(&format!("chat-{}", channel));
+    followup(ctx, command, &format!("Exported {} messages.", messages.len()), Some((file_name, file.into_bytes()))).await;
+}
+
+async fn followup(ctx: &Context, command: &ApplicationCommandInteraction, content: &str, file: Option<(String, Vec<u8>)>) {
+    if let Err(why) = command.create_followup_message(&ctx.http, |message| {
+        message.ephemeral(true).content(content);
+        if let Some((filename, data)) = file {
+            message.add_file(AttachmentType::Bytes { data: Cow::Owned(data), filename });
+        }
+        message
+    }).await {
+        println!("Cannot send slash command response: {}", why);
+    }
+}
@@ -1,14 +1,152 @@
-use serenity::{builder::{self, CreateInteractionResponseData}, model::prelude::interaction::application_command::ApplicationCommandInteraction};
+use serenity::{builder, model::prelude::{MessageId, command::CommandOptionType, interaction::{InteractionResponseType, application_command::{ApplicationCommandInteraction, CommandDataOptionValue}}}, prelude::Context};
 
-use crate::botmanager::{BotManager};
+use crate::botmanager::BotManager;
+use crate::fences::{Fence, FENCE_MESSAGE};
+use super::find_option;
 
 pub fn register (command: &mut builder::CreateApplicationCommand) -> &mut builder::CreateApplicationCommand
 {
     command
         .name("fence")
-        .description("Create a fence - Bots won't see any messages on the other side of the fence")
+        .description("Manage fences - Bots won't see any messages on the other side of a fence")
+        .create_option(|sub| {
+            sub
+                .name("add")
+                .description("Create a fence")
+                .kind(CommandOptionType::SubCommand)
+                .create_sub_option(|option| {
+                    option
+                        .name("character")
+                        .description("Only fence off this bot (every bot by default)")
+                        .kind(CommandOptionType::String)
+                        .set_autocomplete(true)
+                        .required(false)
+                })
+        })
+        .create_option(|sub| {
+            sub
+                .name("remove")
+                .description("Remove a fence")
+                .kind(CommandOptionType::SubCommand)
+                .create_sub_option(|option| {
+                    option
+                        .name("message_id")
+                        .description("ID of the fence message (the latest fence by default)")
+                        .kind(CommandOptionType::String)
+                        .required(false)
+                })
+        })
+        .create_option(|sub| {
+            sub
+                .name("list")
+                .description("List the fences in this channel")
+                .kind(CommandOptionType::SubCommand)
+        })
 }
 
-pub fn run (command: &ApplicationCommandInteraction, manager: &BotManager, msg: &mut CreateInteractionResponseData){
-    msg.content("--- Message Fence ---\nBots won't see any messages above this one!");
-}
\ No newline at end of file
+pub async fn run (ctx: &Context, command: &ApplicationCommandInteraction, manager: &BotManager) {
+    let subcommand = match command.data.options.first() {
+        Some(sub) => sub,
+        None => return
+    };
+    let channel = command.channel_id.to_string();
+
+    match subcommand.name.as_str() {
+        "add" => {
+            let character_id = match find_option(&subcommand.options, "character") {
+                Some(CommandDataOptionValue::String(id)) => Some(id.to_owned()),
+                _ => None
+            };
+            let character_name = character_id.as_ref().map(|id| {
+                let data = manager.data.lock().unwrap();
+                data.characters.get(id)
+                    .filter(|character| character.is_visible_to(command.user.id.0, command.guild_id.map(|guild| guild.0)))
+                    .map(|character| character.char_name.to_owned())
+            });
+            let content = match character_name {
+                Some(None) => {
+                    reply(ctx, command, "The selected bot ID doesn't exist!", true).await;
+                    return;
+                },
+                Some(Some(name)) => format!("{}\n(Only for {})", FENCE_MESSAGE, name),
+                None => String::from(FENCE_MESSAGE)
+            };
+            reply(ctx, command, &content, false).await;
+
+            // The fence is the response itself, its ID is only known once it's sent
+            let message_id = match command.get_interaction_response(&ctx.http).await {
+                Ok(message) => message.id.to_string(),
+                Err(why) => {
+                    println!("Failed getting fence message: {}", why);
+                    return;
+                }
+            };
+            let result = manager.data.lock().unwrap().fences.add(&channel, Fence { message_id, character_id })
+                .map_err(|why| why.to_string());
+            if let Err(why) = result {
+                println!("Failed saving fence: {}", why);
+            }
+        },
+        "remove" => {
+            let message_id = match find_option(&subcommand.options, "message_id") {
+                Some(CommandDataOptionValue::String(id)) => Some(id.trim().to_owned()),
+                _ => None
+            };
+            let result = manager.data.lock().unwrap().fences.remove(&channel, message_id.as_deref())
+                .map_err(|why| why.to_string());
+            match result {
+                Ok(Some(fence)) => {
+                    // The fence message would claim it's still there otherwise
+                    if let Ok(id) = fence.message_id.parse() {
+                        if let Err(why) = command.channel_id.delete_message(&ctx.http, MessageId(id)).await {
+                            println!("Failed deleting fence message: {}", why);
+                        }
+                    }
+                    reply(ctx, command, "Fence removed!", true).await;
+                },
+                Ok(None) => reply(ctx, command, "There is no such fence in this channel!", true).await,
+                Err(why) => {
+                    println!("Failed saving fences: {}", why);
+                    reply(ctx, command, "Failed removing the fence!", true).await;
+                }
+            }
+        },
+        "list" => {
+            let content = {
+                let data = manager.data.lock().unwrap();
+                let fences = data.fences.list(&channel);
+                if fences.is_empty() {
+                    String::from("There are no fences in this channel.")
+                }
+                else {
+                    fences.iter()
+                        .map(|fence| {
+                            let link = match fence.message_id.parse() {
+                                Ok(id) => MessageId(id).link(command.channel_id, command.guild_id),
+                                Err(_) => fence.message_id.to_owned()
+                            };
+                            let target = match &fence.character_id {
+                                Some(id) => data.characters.get(id).map_or(id.to_owned(), |character| character.char_name.to_owned()),
+                                None => String::from("every bot")
+                            };
+                            format!("{} ({}) - {}", link, fence.message_id, target)
+                        })
+                        .collect::<Vec<String>>()
+                        .join("\n")
+                }
+            };
+            reply(ctx, command, &content, true).await;
+        },
+        _ => reply(ctx, command, "Command not implemented", true).await
+    }
+}
+
+async fn reply(ctx: &Context, command: &ApplicationCommandInteraction, content: &str, ephemeral: bool) {
+    if let Err(why) = command.create_interaction_response(&ctx.http, |response| {
+        response
+            .kind(InteractionResponseType::ChannelMessageWithSource)
+            .interaction_response_data(|message| message.ephemeral(ephemeral).content(content))
+    }).await {
+        println!("Cannot respond to slash command: {}", why);
+    }
+}
@@ -0,0 +1,57 @@
+use serenity::{builder, model::prelude::{command::CommandOptionType, interaction::{InteractionResponseType, application_command::{ApplicationCommandInteraction, CommandDataOptionValue}}}, prelude::Context};
+
+use crate::botmanager::BotManager;
+use crate::conversation::Conversation;
+use crate::platform::discord::DiscordPlatform;
+use super::find_option;
+
+/// What the character is asked for without a prompt
+const DEFAULT_PROMPT: &str = "selfie";
+
+pub fn register (command: &mut builder::CreateApplicationCommand) -> &mut builder::CreateApplicationCommand
+{
+    command
+        .name("imagine")
+        .description("Ask the bot in this channel for a picture")
+        .create_option(|option| {
+            option
+                .name("prompt")
+                .description("What the picture shows (a selfie by default)")
+                .kind(CommandOptionType::String)
+                .required(false)
+        })
+}
+
+pub async fn run (ctx: &Context, command: &ApplicationCommandInteraction, manager: &BotManager) {
+    let prompt = match find_option(&command.data.options, "prompt") {
+        Some(CommandDataOptionValue::String(prompt)) => prompt.to_owned(),
+        _ => String::from(DEFAULT_PROMPT)
+    };
+
+    // Generating takes longer than the 3 seconds Discord waits for an answer
+    if let Err(why) = command.create_interaction_response(&ctx.http, |response| {
+        response
+            .kind(InteractionResponseType::DeferredChannelMessageWithSource)
+            .interaction_response_data(|message| message.ephemeral(true))
+    }).await {
+        println!("Cannot respond to slash command: {}", why);
+        return;
+    }
+
+    let channel = command.channel_id.to_string();
+    let conversation = Conversation::new(&manager.api, &manager.data);
+    let result = match conversation.persona(&channel) {
+        Some(persona) => conversation.imagine(&DiscordPlatform::new(ctx), &channel, &persona, &prompt).await,
+        None => Err(String::from("There is no active bot in this channel"))
+    };
+    let content = match result {
+        Ok(_) => String::from("Picture sent!"),
+        Err(why) => {
+            println!("Failed imagining {}: {}", prompt, why);
+            format!("Couldn't make the picture: {}", why)
+        }
+    };
+    if let Err(why) = command.edit_original_interaction_response(&ctx.http, |response| response.content(content)).await {
+        println!("Cannot edit slash command response: {}", why);
+    }
+}
@@ -0,0 +1,67 @@
+use serenity::{builder, model::prelude::{command::CommandOptionType, interaction::{InteractionResponseType, application_command::{ApplicationCommandInteraction, CommandDataOptionValue}}}, prelude::Context};
+
+use crate::botmanager::BotManager;
+use crate::conversation::Conversation;
+use crate::transcript;
+use super::find_option;
+
+/// Larger transcripts wouldn't fit in a prompt anyway
+const MAX_TRANSCRIPT_SIZE: u64 = 4 * 1024 * 1024;
+
+pub fn register (command: &mut builder::CreateApplicationCommand) -> &mut builder::CreateApplicationCommand
+{
+    command
+        .name("import")
+        .description("Continue a saved chat: bots see its messages before this channel's, until the next fence")
+        .create_option(|option| {
+            option
+                .name("transcript")
+                .description("Chat exported with /export (text, JSONL or SillyTavern)")
+                .kind(CommandOptionType::Attachment)
+                .required(true)
+        })
+}
+
+pub async fn run (ctx: &Context, command: &ApplicationCommandInteraction, manager: &BotManager) {
+    let attachment = match find_option(&command.data.options, "transcript") {
+        Some(CommandDataOptionValue::Attachment(attachment)) => attachment,
+        _ => return
+    };
+
+    // Downloading a large transcript takes longer than the 3 seconds Discord waits for an answer
+    if let Err(why) = command.create_interaction_response(&ctx.http, |response| {
+        response
+            .kind(InteractionResponseType::DeferredChannelMessageWithSource)
+            .interaction_response_data(|message| message.ephemeral(true))
+    }).await {
+        println!("Cannot respond to slash command: {}", why);
+        return;
+    }
+
+    let content = if attachment.size > MAX_TRANSCRIPT_SIZE {
+        String::from("That transcript is too large!")
+    }
+    else {
+        let result = match attachment.download().await {
+            Ok(data) => transcript::import(&String::from_utf8_lossy(&data)).map_err(|err| err.to_string()),
+            Err(why) => Err(why.to_string())
+        };
+        let result = result.and_then(|messages| {
+            let count = messages.len();
+            Conversation::new(&manager.api, &manager.data).import(&command.channel_id.to_string(), messages)
+                .map(|_| count)
+                .map_err(|err| err.to_string())
+        });
+        match result {
+            Ok(count) => format!("Imported {} messages, bots in this channel will remember them until the next fence.", count),
+            Err(why) => {
+                println!("Failed importing {}: {}", attachment.filename, why);
+                format!("Couldn't import {}: {}", attachment.filename, why)
+            }
+        }
+    };
+
+    if let Err(why) = command.edit_original_interaction_response(&ctx.http, |response| response.content(content)).await {
+        println!("Cannot respond to slash command: {}", why);
+    }
+}
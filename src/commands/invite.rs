@@ -1,6 +1,10 @@
-use serenity::{builder::{self, CreateInteractionResponseData}, model::prelude::{command::CommandOptionType, interaction::application_command::{ApplicationCommandInteraction, CommandDataOptionValue}}};
+use serenity::{builder::{self, CreateAutocompleteResponse, CreateInteractionResponseData}, model::prelude::{command::CommandOptionType, interaction::{application_command::{ApplicationCommandInteraction, CommandDataOptionValue}, autocomplete::AutocompleteInteraction}}, prelude::Context};
 
 use crate::botmanager::BotManager;
+use crate::conversation::speaker_name;
+use crate::platform::{ChatPlatform, Persona, PlatformMessage};
+use crate::platform::discord::{self, DiscordPlatform};
+use super::find_option;
 
 pub fn register (command: &mut builder::CreateApplicationCommand) -> &mut builder::CreateApplicationCommand
 {
@@ -12,14 +16,44 @@ pub fn register (command: &mut builder::CreateApplicationCommand) -> &mut builde
                 .name("id")
                 .description("The bot's ID")
                 .kind(CommandOptionType::String)
+                .set_autocomplete(true)
                 .required(true)
         })
+        .create_option(|option| {
+            option
+                .name("greeting")
+                .description("Which of the bot's greetings to start with (random by default)")
+                .kind(CommandOptionType::Integer)
+                .min_int_value(1)
+                .required(false)
+        })
+}
+
+/// Suggests the IDs of visible characters matching what the user typed so far
+pub fn autocomplete<'a> (autocomplete: &AutocompleteInteraction, manager: &BotManager, response: &'a mut CreateAutocompleteResponse) -> &'a mut CreateAutocompleteResponse {
+    // Options of subcommands (like `/fence add`) are nested in the subcommand
+    let typed = autocomplete.data.options.iter()
+        .flat_map(|option| std::iter::once(option).chain(option.options.iter()))
+        .find(|option| option.focused)
+        .and_then(|option| option.value.as_ref())
+        .and_then(|value| value.as_str())
+        .unwrap_or("");
+    let data = manager.data.lock().unwrap();
+    let typed_id = typed.to_lowercase();
+    let suggestions = data.visible_characters(autocomplete.user.id.0, autocomplete.guild_id.map(|guild| guild.0), "").into_iter()
+        .filter(|(id, character)| id.contains(&typed_id) || character.matches(typed))
+        .take(25);
+    for (id, character) in suggestions {
+        let label: String = [&character.char_name, " (", id, ")"].join("").chars().take(100).collect();
+        response.add_string_choice(label, id);
+    }
+    response
 }
 
 pub fn run (command: &ApplicationCommandInteraction, manager: &BotManager, msg: &mut CreateInteractionResponseData){
     let mut data = manager.data.lock().unwrap();
     let options = &command.data.options;
-    let character_id : &str = match options.get(0) {
+    let character_id : &str = match options.iter().find(|opt| opt.name == "id") {
         Some(opt) => {
             if let CommandDataOptionValue::String(id_str) = opt.resolved.as_ref().expect("Expected character ID field"){
                 id_str as &str
@@ -35,10 +69,18 @@ pub fn run (command: &ApplicationCommandInteraction, manager: &BotManager, msg:
         }
     };
 
-    let selected_character = data.characters.get(character_id);
+    let selected_character = data.characters.get(character_id)
+        .filter(|character| character.is_visible_to(command.user.id.0, command.guild_id.map(|guild| guild.0)));
+    let greeting = match find_option(options, "greeting") {
+        Some(CommandDataOptionValue::Integer(index)) => usize::try_from(*index).ok(),
+        _ => None
+    };
     let mut do_insert = false;
 
     match selected_character {
+        Some(char) if greeting.is_some_and(|index| index > char.greetings().len()) => {
+            msg.content(format!("{} only has {} greetings!", char.char_name, char.greetings().len()));
+        },
         Some(char) => {
             do_insert = true;
             msg.embed(|e| { e
@@ -50,6 +92,56 @@ pub fn run (command: &ApplicationCommandInteraction, manager: &BotManager, msg:
 
     };
     if do_insert {
-        data.invited_characters.insert(command.channel_id, String::from(character_id));
+        data.invited_characters.insert(command.channel_id.to_string(), String::from(character_id));
     }
-}
\ No newline at end of file
+}
+
+/// Posts the invited character's greeting, so the conversation has an opening line in the history
+pub async fn greet (ctx: &Context, command: &ApplicationCommandInteraction, manager: &BotManager) {
+    let selected = find_option(&command.data.options, "greeting");
+    let requested_id = command.data.options.iter()
+        .find(|opt| opt.name == "id")
+        .and_then(|opt| opt.value.as_ref())
+        .and_then(|value| value.as_str());
+    // Named like in the prompt, so the greeting and the replies call the user the same
+    let mut user = PlatformMessage {
+        id: command.id.to_string(),
+        author_id: command.user.id.to_string(),
+        author_name: command.user.name.to_owned(),
+        display_name: None,
+        nickname: command.member.as_ref().and_then(|member| member.nick.to_owned()),
+        content: String::new(),
+        is_bot: false,
+        is_own: false,
+        images: Vec::new(),
+        reply_to: None
+    };
+    user.display_name = discord::display_name(ctx, &user).await;
+    let server = command.guild_id.map(|guild| guild.to_string());
+    let greeting = {
+        let data = manager.data.lock().unwrap();
+        // Only greet if this invite actually went through
+        let invited = data.invited_characters.get(&command.channel_id.to_string())
+            .filter(|id| Some(id.as_str()) == requested_id);
+        let character = match invited.and_then(|id| data.characters.get(id)) {
+            Some(character) => character,
+            None => return
+        };
+        let index = match selected {
+            Some(CommandDataOptionValue::Integer(index)) => Some(*index),
+            _ => None
+        };
+        let user_name = speaker_name(&user, manager.api.history().speaker_names, &data.user_profiles, server.as_deref());
+        character.pick_greeting(index, &user_name).map(|greeting| (
+            greeting,
+            Persona { name: character.char_name.to_owned(), avatar_url: character.avatar_url.to_owned() }
+        ))
+    };
+
+    if let Some((content, persona)) = greeting {
+        let platform = DiscordPlatform::new(ctx);
+        if let Err(why) = platform.send_as_persona(&command.channel_id.to_string(), &persona, &content, None).await {
+            println!("Error sending greeting: {}", why);
+        }
+    }
+}
@@ -1,6 +1,17 @@
-use serenity::{builder::{self, CreateInteractionResponseData}, model::prelude::{command::CommandOptionType, interaction::application_command::{ApplicationCommandInteraction, CommandDataOptionValue}}};
+use serenity::{builder::{self, CreateInteractionResponseData}, model::prelude::{command::CommandOptionType, component::ButtonStyle, interaction::{application_command::{ApplicationCommandInteraction, CommandDataOptionValue}, message_component::MessageComponentInteraction}}};
 
 use crate::{botmanager::BotManager};
+use crate::textgen::character::Character;
+use super::truncate;
+
+pub const PAGE_SIZE: usize = 12;
+/// Names and descriptions are cut so a full page stays under Discord's 6000 characters per embed
+const NAME_LENGTH: usize = 80;
+const DESCRIPTION_LENGTH: usize = 300;
+/// Leaves room for the rest of the buttons' custom IDs
+const MAX_QUERY_LENGTH: u16 = 60;
+/// Discord's limit for custom IDs
+const MAX_CUSTOM_ID_LENGTH: usize = 100;
 
 pub fn register (command: &mut builder::CreateApplicationCommand) -> &mut builder::CreateApplicationCommand
 {
@@ -12,39 +23,91 @@ pub fn register (command: &mut builder::CreateApplicationCommand) -> &mut builde
                 .name("page")
                 .description("Which page of the list to display")
                 .kind(CommandOptionType::Integer)
+                .min_int_value(1)
+                .required(false)
+        })
+        .create_option(|option| {
+            option
+                .name("query")
+                .description("Only show bots whose name, description or tags contain this")
+                .kind(CommandOptionType::String)
+                .max_length(MAX_QUERY_LENGTH)
                 .required(false)
         })
 }
 
 pub fn run (command: &ApplicationCommandInteraction, manager: &BotManager, msg: &mut CreateInteractionResponseData){
-    let data = manager.data.lock().unwrap();
-    let options = &command.data.options;
-    let start_index = match options.get(0) {
-        Some(opt) => {
-            match opt.resolved.as_ref() {
-                Some(CommandDataOptionValue::Integer(val)) => val as &i64,
-                Some(_) => &0,
-                None => &0
-            }
+    let mut page = 1;
+    let mut query = "";
+    for option in &command.data.options {
+        match option.resolved.as_ref() {
+            Some(CommandDataOptionValue::Integer(val)) => page = *val,
+            Some(CommandDataOptionValue::String(val)) => query = val,
+            _ => {}
         }
-        None => &0
-    };
+    }
+
+    show_page(manager, command.user.id.0, command.guild_id.map(|guild| guild.0), query, page, msg);
+}
+
+/// Handles the previous/next buttons, whose custom IDs look like `list:<owner>:<page>:<query>`
+pub fn run_button (component: &MessageComponentInteraction, manager: &BotManager, msg: &mut CreateInteractionResponseData){
+    let mut parts = component.data.custom_id.splitn(4, ':').skip(2);
+    let page = parts.next().and_then(|page| page.parse().ok()).unwrap_or(1);
+    let query = parts.next().unwrap_or("");
+
+    show_page(manager, component.user.id.0, component.guild_id.map(|guild| guild.0), query, page, msg);
+}
+
+/// Whether the button was clicked by who ran `/list`, the only one who can turn its pages
+pub fn is_owner(component: &MessageComponentInteraction) -> bool {
+    component.data.custom_id.split(':').nth(1) == Some(component.user.id.to_string().as_str())
+}
+
+/// Custom ID of a page button, with the query cut to fit
+pub fn button_id(owner: u64, page: i64, query: &str) -> String {
+    let prefix = format!("list:{}:{}:", owner, page);
+    let query: String = query.chars().take(MAX_CUSTOM_ID_LENGTH.saturating_sub(prefix.chars().count())).collect();
+    prefix + &query
+}
+
+fn show_page(manager: &BotManager, owner: u64, guild_id: Option<u64>, query: &str, page: i64, msg: &mut CreateInteractionResponseData) {
+    let data = manager.data.lock().unwrap();
+    // The list is public, so nobody's private characters are in it
+    let characters = data.visible_characters(0, guild_id, query);
+    let page_count = characters.len().div_ceil(PAGE_SIZE).max(1) as i64;
+    let page = page.clamp(1, page_count);
 
     msg.embed(|e| { e
         .title("Bot profile List")
-        .description("Use `/invite` to invite one of these bots to the current channel!");
-        let mut index = 0;
-        let mut added = 0;
-        for (id, character) in &data.characters {
-            if added > 25 {
-                break;
-            }
-            if &index >= start_index {
-                e.field(&character.char_name, ["`/invite ", &id, "`\n", &character.char_description].join(""), true);
-                added += 1;
-            }
-            index += 1;
+        .description("Use `/invite` to invite one of these bots to the current channel!")
+        .footer(|footer| footer.text(format!("Page {}/{} - {} bots", page, page_count, characters.len())));
+        if characters.is_empty() {
+            e.field("No bots found", if query.is_empty() {"There are no bots yet."} else {"No bots match your search."}, false);
+        }
+        for (id, character) in characters.iter().skip((page - 1) as usize * PAGE_SIZE).take(PAGE_SIZE) {
+            let (name, value) = entry(id, character);
+            e.field(name, value, true);
         }
         e
     });
-}
\ No newline at end of file
+
+    msg.components(|components| components.create_action_row(|row| row
+        .create_button(|button| button
+            .custom_id(button_id(owner, page - 1, query))
+            .label("◀ Previous")
+            .style(ButtonStyle::Secondary)
+            .disabled(page <= 1))
+        .create_button(|button| button
+            .custom_id(button_id(owner, page + 1, query))
+            .label("Next ▶")
+            .style(ButtonStyle::Secondary)
+            .disabled(page >= page_count))
+    ));
+}
+
+/// Field of a character in the list: its name, then how to invite it and its description
+pub fn entry(id: &str, character: &Character) -> (String, String) {
+    let description = truncate(&character.char_description, DESCRIPTION_LENGTH);
+    (truncate(&character.char_name, NAME_LENGTH), ["`/invite ", id, "`\n", &description].join(""))
+}
@@ -1,4 +1,144 @@
+use serde_json::{Map, Value};
+use serenity::builder::CreateApplicationCommand;
+use serenity::http::Http;
+use serenity::model::prelude::GuildId;
+use serenity::model::prelude::command::Command;
+use serenity::model::prelude::interaction::application_command::{CommandDataOption, CommandDataOptionValue};
+
+pub mod callme;
+pub mod character;
+pub mod export;
 pub mod list;
 pub mod fence;
+pub mod imagine;
+pub mod import;
 pub mod invite;
-pub mod uninvite;
\ No newline at end of file
+pub mod note;
+pub mod persona;
+pub mod profile;
+pub mod uninvite;
+#[cfg(feature = "voice")]
+pub mod voice;
+
+/// Embed field values are limited to 1024 characters
+pub const MAX_FIELD_LENGTH: usize = 1024;
+
+/// The first `length` characters of a text, with an ellipsis if it's cut
+pub fn truncate(text: &str, length: usize) -> String {
+    if text.chars().count() <= length {
+        return String::from(text);
+    }
+    let mut truncated: String = text.chars().take(length - 1).collect();
+    truncated.push('…');
+    truncated
+}
+
+/// Value of the option called `name`, among the options of a command or subcommand
+pub fn find_option<'a>(options: &'a [CommandDataOption], name: &str) -> Option<&'a CommandDataOptionValue> {
+    options.iter()
+        .find(|option| option.name == name)
+        .and_then(|option| option.resolved.as_ref())
+}
+
+/// Trimmed text of a string option, `None` if it wasn't given
+pub fn string_option(options: &[CommandDataOption], name: &str) -> Option<String> {
+    match find_option(options, name) {
+        Some(CommandDataOptionValue::String(value)) => Some(value.trim().to_owned()),
+        _ => None
+    }
+}
+
+/// Every slash command of the bot
+pub fn definitions() -> Vec<CreateApplicationCommand> {
+    #[allow(unused_mut)]
+    let mut registrations: Vec<fn(&mut CreateApplicationCommand) -> &mut CreateApplicationCommand> = vec![
+        list::register,
+        invite::register,
+        uninvite::register,
+        fence::register,
+        character::register,
+        profile::register,
+        imagine::register,
+        export::register,
+        import::register,
+        callme::register,
+        persona::register,
+        note::register,
+    ];
+    #[cfg(feature = "voice")]
+    registrations.push(voice::register);
+    registrations.iter()
+        .map(|register| {
+            let mut command = CreateApplicationCommand::default();
+            register(&mut command);
+            command
+        })
+        .collect()
+}
+
+/// Registers the commands in a server, or globally for `None`, unless they're already there.
+/// With `enabled` false, removes them instead. Returns whether anything changed.
+pub async fn sync(http: &Http, guild: Option<GuildId>, enabled: bool) -> serenity::Result<bool> {
+    let desired = if enabled { definitions() } else { Vec::new() };
+    let existing = match guild {
+        Some(guild) => guild.get_application_commands(http).await?,
+        None => Command::get_global_application_commands(http).await?
+    };
+    if is_up_to_date(&desired, &existing) {
+        return Ok(false);
+    }
+
+    match guild {
+        Some(guild) => guild.set_application_commands(http, |commands| commands.set_application_commands(desired)).await?,
+        None => Command::set_global_application_commands(http, |commands| commands.set_application_commands(desired)).await?
+    };
+    Ok(true)
+}
+
+/// Whether the registered commands match the definitions, ignoring what Discord adds (IDs, versions, defaults)
+pub fn is_up_to_date(desired: &[CreateApplicationCommand], existing: &[Command]) -> bool {
+    let mut desired: Vec<Value> = desired.iter()
+        .map(|command| normalize(&Value::Object(command.0.iter().map(|(key, value)| (key.to_string(), value.clone())).collect()), true))
+        .collect();
+    let mut existing: Vec<Value> = existing.iter()
+        .map(|command| normalize(&serde_json::to_value(command).unwrap_or_default(), true))
+        .collect();
+    let by_name = |a: &Value, b: &Value| a["name"].as_str().cmp(&b["name"].as_str());
+    desired.sort_by(by_name);
+    existing.sort_by(by_name);
+    desired == existing
+}
+
+/// Keeps the fields of a command or option that matter, dropping empty and default values
+fn normalize(definition: &Value, is_command: bool) -> Value {
+    // Every command is a slash command, only options have a meaningful type
+    let keys: &[&str] = if is_command {
+        &["name", "description"]
+    } else {
+        &["type", "name", "description", "required", "autocomplete", "min_value", "max_value", "min_length", "max_length", "channel_types"]
+    };
+    let mut normalized = Map::new();
+    for key in keys {
+        match &definition[*key] {
+            Value::Null | Value::Bool(false) => {},
+            Value::Array(items) if items.is_empty() => {},
+            value => {
+                normalized.insert(key.to_string(), value.clone());
+            }
+        }
+    }
+
+    let choices: Vec<Value> = definition["choices"].as_array().into_iter().flatten()
+        .map(|choice| serde_json::json!({ "name": choice["name"], "value": choice["value"] }))
+        .collect();
+    if !choices.is_empty() {
+        normalized.insert(String::from("choices"), Value::Array(choices));
+    }
+    let options: Vec<Value> = definition["options"].as_array().into_iter().flatten()
+        .map(|option| normalize(option, false))
+        .collect();
+    if !options.is_empty() {
+        normalized.insert(String::from("options"), Value::Array(options));
+    }
+    Value::Object(normalized)
+}
@@ -0,0 +1,101 @@
+use serenity::{builder::{self, CreateInteractionResponseData}, model::prelude::{command::CommandOptionType, interaction::application_command::{ApplicationCommandInteraction, CommandDataOptionValue}}};
+
+use crate::botmanager::BotManager;
+use crate::notes::AuthorsNote;
+use super::{find_option, string_option};
+
+/// Notes go into every prompt of the channel, so they're kept short
+const MAX_NOTE_LENGTH: u16 = 500;
+
+/// Deeper than most histories are long
+const MAX_DEPTH: u64 = 50;
+
+pub fn register (command: &mut builder::CreateApplicationCommand) -> &mut builder::CreateApplicationCommand
+{
+    command
+        .name("note")
+        .description("Manage the author's note - Reminder put near the end of this channel's history")
+        .create_option(|sub| {
+            sub
+                .name("set")
+                .description("Set the author's note of this channel")
+                .kind(CommandOptionType::SubCommand)
+                .create_sub_option(|option| {
+                    option
+                        .name("text")
+                        .description("What the characters should keep in mind, e.g. [Style: short, playful replies]")
+                        .kind(CommandOptionType::String)
+                        .max_length(MAX_NOTE_LENGTH)
+                        .required(true)
+                })
+                .create_sub_option(|option| {
+                    option
+                        .name("depth")
+                        .description("How many messages from the end of the history to put it (from the config by default)")
+                        .kind(CommandOptionType::Integer)
+                        .min_int_value(0)
+                        .max_int_value(MAX_DEPTH)
+                })
+        })
+        .create_option(|sub| {
+            sub
+                .name("show")
+                .description("Show the author's note of this channel")
+                .kind(CommandOptionType::SubCommand)
+        })
+        .create_option(|sub| {
+            sub
+                .name("clear")
+                .description("Remove the author's note of this channel")
+                .kind(CommandOptionType::SubCommand)
+        })
+}
+
+pub fn run (command: &ApplicationCommandInteraction, manager: &BotManager, msg: &mut CreateInteractionResponseData){
+    msg.ephemeral(true);
+    let subcommand = match command.data.options.first() {
+        Some(sub) => sub,
+        None => return
+    };
+    let channel = command.channel_id.to_string();
+
+    let mut data = manager.data.lock().unwrap();
+    let result = match subcommand.name.as_str() {
+        "set" => {
+            let text = string_option(&subcommand.options, "text").unwrap_or_default();
+            if text.is_empty() {
+                msg.content("The note can't be empty, use /note clear to remove it!");
+                return;
+            }
+            let depth = match find_option(&subcommand.options, "depth") {
+                Some(CommandDataOptionValue::Integer(depth)) => usize::try_from(*depth).ok(),
+                _ => None
+            };
+            let content = match depth {
+                Some(depth) => format!("Author's note set, {} messages from the end of the history.", depth),
+                None => String::from("Author's note set.")
+            };
+            data.authors_notes.set(&channel, AuthorsNote { text, depth }).map(|_| content)
+        },
+        "show" => Ok(match data.authors_notes.get(&channel) {
+            Some(note) => match note.depth {
+                Some(depth) => format!("Author's note, {} messages from the end:\n>>> {}", depth, note.text),
+                None => format!("Author's note:\n>>> {}", note.text)
+            },
+            None => String::from("This channel has no author's note.")
+        }),
+        "clear" => data.authors_notes.remove(&channel).map(|removed| match removed {
+            Some(_) => String::from("Author's note cleared."),
+            None => String::from("This channel has no author's note!")
+        }),
+        _ => return
+    };
+
+    match result {
+        Ok(content) => msg.content(content),
+        Err(why) => {
+            println!("Failed saving author's notes: {}", why);
+            msg.content("Couldn't save that, try again later!")
+        }
+    };
+}
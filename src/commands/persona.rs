@@ -0,0 +1,123 @@
+use serenity::{builder::{self, CreateInteractionResponseData}, model::prelude::{command::CommandOptionType, interaction::application_command::{ApplicationCommandInteraction, CommandDataOptionValue}}};
+
+use crate::botmanager::BotManager;
+use crate::users::UserProfile;
+use super::{find_option, string_option};
+
+/// Longest name that still reads like a name in the history
+pub const MAX_NAME_LENGTH: u16 = 32;
+
+/// Descriptions go into prompts, so they're kept to a paragraph
+pub const MAX_DESCRIPTION_LENGTH: u16 = 1000;
+
+pub fn register (command: &mut builder::CreateApplicationCommand) -> &mut builder::CreateApplicationCommand
+{
+    command
+        .name("persona")
+        .description("Manage your persona - Who bots think you are")
+        .create_option(|sub| {
+            sub
+                .name("set")
+                .description("Set your persona")
+                .kind(CommandOptionType::SubCommand)
+                .create_sub_option(|option| {
+                    option
+                        .name("name")
+                        .description("Name the bots call you")
+                        .kind(CommandOptionType::String)
+                        .max_length(MAX_NAME_LENGTH)
+                        .required(true)
+                })
+                .create_sub_option(|option| {
+                    option
+                        .name("description")
+                        .description("Who you are, for prompts that use it")
+                        .kind(CommandOptionType::String)
+                        .max_length(MAX_DESCRIPTION_LENGTH)
+                })
+                .create_sub_option(|option| {
+                    option
+                        .name("server")
+                        .description("Only use it in this server (everywhere by default)")
+                        .kind(CommandOptionType::Boolean)
+                })
+        })
+        .create_option(|sub| {
+            sub
+                .name("show")
+                .description("Show the persona you have here")
+                .kind(CommandOptionType::SubCommand)
+        })
+        .create_option(|sub| {
+            sub
+                .name("clear")
+                .description("Go back to your Discord name")
+                .kind(CommandOptionType::SubCommand)
+                .create_sub_option(|option| {
+                    option
+                        .name("server")
+                        .description("Only clear the persona of this server (the global one by default)")
+                        .kind(CommandOptionType::Boolean)
+                })
+        })
+}
+
+pub fn run (command: &ApplicationCommandInteraction, manager: &BotManager, msg: &mut CreateInteractionResponseData){
+    msg.ephemeral(true);
+    let subcommand = match command.data.options.first() {
+        Some(sub) => sub,
+        None => return
+    };
+    let user_id = command.user.id.to_string();
+    let guild = command.guild_id.map(|guild| guild.to_string());
+    let server_only = matches!(find_option(&subcommand.options, "server"), Some(CommandDataOptionValue::Boolean(true)));
+    if server_only && guild.is_none() {
+        msg.content("Server personas can only be set in a server!");
+        return;
+    }
+    let scope = guild.as_deref().filter(|_| server_only);
+
+    let mut data = manager.data.lock().unwrap();
+    let result = match subcommand.name.as_str() {
+        "set" => {
+            let text = |name: &str| string_option(&subcommand.options, name).unwrap_or_default();
+            let profile = UserProfile { name: text("name"), description: text("description") };
+            let content = match scope {
+                Some(_) => format!("Bots in this server will call you {}.", profile.name),
+                None => format!("Bots will call you {} from now on.", profile.name)
+            };
+            data.user_profiles.set(&user_id, scope, profile).map(|_| content)
+        },
+        "show" => {
+            let server_profile = guild.as_deref().and_then(|guild| data.user_profiles.get_exact(&user_id, Some(guild)));
+            let content = match (server_profile, data.user_profiles.get(&user_id, None)) {
+                (Some(profile), _) => format!("In this server you're **{}** (server persona){}", profile.name, about(profile)),
+                (None, Some(profile)) if profile.name.is_empty() => format!("Bots use your Discord name{}", about(profile)),
+                (None, Some(profile)) => format!("You're **{}**{}", profile.name, about(profile)),
+                (None, None) => String::from("You have no persona, bots use your Discord name.")
+            };
+            Ok(content)
+        },
+        "clear" => data.user_profiles.remove(&user_id, scope).map(|removed| match removed {
+            Some(_) if scope.is_some() => String::from("Persona of this server cleared."),
+            Some(_) => String::from("Persona cleared, bots will call you by your Discord name again."),
+            None => String::from("You have no such persona!")
+        }),
+        _ => return
+    };
+
+    match result {
+        Ok(content) => msg.content(content),
+        Err(why) => {
+            println!("Failed saving user profiles: {}", why);
+            msg.content("Couldn't save that, try again later!")
+        }
+    };
+}
+
+fn about(profile: &UserProfile) -> String {
+    match profile.description.is_empty() {
+        true => String::from("."),
+        false => format!(":\n>>> {}", profile.description)
+    }
+}
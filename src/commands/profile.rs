@@ -0,0 +1,66 @@
+use serenity::{builder::{self, CreateInteractionResponseData}, model::prelude::{command::CommandOptionType, interaction::application_command::{ApplicationCommandInteraction, CommandDataOptionValue}}};
+
+use crate::botmanager::BotManager;
+use super::{truncate, MAX_FIELD_LENGTH};
+
+pub fn register (command: &mut builder::CreateApplicationCommand) -> &mut builder::CreateApplicationCommand
+{
+    command
+        .name("profile")
+        .description("Show a bot's profile")
+        .create_option(|option| {
+            option
+                .name("id")
+                .description("The bot's ID")
+                .kind(CommandOptionType::String)
+                .set_autocomplete(true)
+                .required(true)
+        })
+}
+
+pub fn run (command: &ApplicationCommandInteraction, manager: &BotManager, msg: &mut CreateInteractionResponseData){
+    let data = manager.data.lock().unwrap();
+    let character_id = match command.data.options.first().and_then(|opt| opt.resolved.as_ref()) {
+        Some(CommandDataOptionValue::String(id_str)) => id_str as &str,
+        _ => {
+            msg.content("Expected bot ID!");
+            return;
+        }
+    };
+
+    let character = match data.characters.get(character_id)
+        .filter(|character| character.is_visible_to(command.user.id.0, command.guild_id.map(|guild| guild.0))) {
+        Some(character) => character,
+        None => {
+            msg.content("The selected bot ID doesn't exist!");
+            return;
+        }
+    };
+
+    msg.embed(|e| {
+        e.title(&character.char_name)
+            .description(&character.char_description)
+            .thumbnail(&character.avatar_url)
+            .field("Invite", ["`/invite ", character_id, "`"].join(""), true);
+        if !character.author.is_empty() {
+            e.field("Author", &character.author, true);
+        }
+        if !character.version.is_empty() {
+            e.field("Version", &character.version, true);
+        }
+        if !character.tags.is_empty() {
+            e.field("Tags", character.tags.join(", "), false);
+        }
+        if !character.scenario.is_empty() {
+            e.field("Scenario", truncate(&character.scenario, MAX_FIELD_LENGTH), false);
+        }
+        let greetings = character.greetings();
+        if !greetings.is_empty() {
+            e.field(format!("Greetings ({})", greetings.len()), truncate(greetings[0], MAX_FIELD_LENGTH), false);
+        }
+        if !character.creator_notes.is_empty() {
+            e.field("Creator notes", truncate(&character.creator_notes, MAX_FIELD_LENGTH), false);
+        }
+        e
+    });
+}
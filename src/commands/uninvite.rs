@@ -12,11 +12,12 @@ pub fn register (command: &mut builder::CreateApplicationCommand) -> &mut builde
 pub fn run (command: &ApplicationCommandInteraction, manager: &BotManager, msg: &mut CreateInteractionResponseData){
     let mut data = manager.data.lock().unwrap();
 
-    let selected_character = data.invited_characters.get(&command.channel_id);
+    let channel = command.channel_id.to_string();
+    let selected_character = data.invited_characters.get(&channel);
 
     match selected_character {
         Some(_) => {
-            data.invited_characters.remove(&command.channel_id);
+            data.invited_characters.remove(&channel);
             msg.content("Bot uninvited!");
         }
         None => {msg.content("There is no active bot in this channel!");}
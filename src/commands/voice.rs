@@ -0,0 +1,112 @@
+use serenity::{builder, model::prelude::{ChannelType, Mentionable, command::CommandOptionType, interaction::{InteractionResponseType, application_command::{ApplicationCommandInteraction, CommandDataOptionValue}}}, prelude::Context};
+
+use crate::botmanager::BotManager;
+use crate::voice;
+use crate::voice::discord::Listener;
+use super::find_option;
+
+pub fn register (command: &mut builder::CreateApplicationCommand) -> &mut builder::CreateApplicationCommand
+{
+    command
+        .name("voice")
+        .description("Have the bot in this channel speak its replies in a voice channel")
+        .dm_permission(false)
+        .create_option(|sub| {
+            sub
+                .name("join")
+                .description("Join a voice channel and speak replies sent to this channel")
+                .kind(CommandOptionType::SubCommand)
+                .create_sub_option(|option| {
+                    option
+                        .name("channel")
+                        .description("Voice channel to join (the one you're in by default)")
+                        .kind(CommandOptionType::Channel)
+                        .channel_types(&[ChannelType::Voice, ChannelType::Stage])
+                        .required(false)
+                })
+                .create_sub_option(|option| {
+                    option
+                        .name("listen")
+                        .description("Also listen, and reply to what people say in the voice channel")
+                        .kind(CommandOptionType::Boolean)
+                        .required(false)
+                })
+        })
+        .create_option(|sub| {
+            sub
+                .name("leave")
+                .description("Leave the voice channel")
+                .kind(CommandOptionType::SubCommand)
+        })
+}
+
+pub async fn run (ctx: &Context, command: &ApplicationCommandInteraction, manager: &BotManager) {
+    let (subcommand, guild) = match (command.data.options.first(), command.guild_id) {
+        (Some(sub), Some(guild)) => (sub, guild),
+        _ => return
+    };
+
+    // Connecting can take longer than the 3 seconds Discord waits for an answer
+    if let Err(why) = command.create_interaction_response(&ctx.http, |response| {
+        response
+            .kind(InteractionResponseType::DeferredChannelMessageWithSource)
+            .interaction_response_data(|message| message.ephemeral(true))
+    }).await {
+        println!("Cannot respond to slash command: {}", why);
+        return;
+    }
+
+    let text_channel = command.channel_id.to_string();
+    let content = match subcommand.name.as_str() {
+        "join" => {
+            let voice_channel = match find_option(&subcommand.options, "channel") {
+                Some(CommandDataOptionValue::Channel(channel)) => Some(channel.id),
+                _ => guild.to_guild_cached(&ctx.cache)
+                    .and_then(|guild| guild.voice_states.get(&command.user.id).and_then(|state| state.channel_id))
+            };
+            let listen = matches!(find_option(&subcommand.options, "listen"), Some(CommandDataOptionValue::Boolean(true)));
+            let listener = match listen {
+                true => Listener::new(ctx, manager.api.clone(), manager.data.clone(), command.channel_id),
+                false => None
+            };
+            match voice_channel {
+                _ if listen && listener.is_none() => String::from("Listening needs a speech-to-text server, set `stt_url` in the config!"),
+                Some(voice_channel) => match voice::discord::join(ctx, guild, voice_channel, listener).await.map_err(|err| err.to_string()) {
+                    Ok(previous) => {
+                        let mut data = manager.data.lock().unwrap();
+                        // The bot can only be in one voice channel per server
+                        if let Some(previous) = previous {
+                            data.voice_channels.retain(|_, channel| *channel != previous.to_string());
+                        }
+                        data.voice_channels.insert(text_channel, voice_channel.to_string());
+                        match listen {
+                            true => format!("Joined {}, I'll reply here to what's said there and speak the replies.", voice_channel.mention()),
+                            false => format!("Joined {}, replies in this channel will be spoken there.", voice_channel.mention())
+                        }
+                    },
+                    Err(why) => {
+                        println!("Failed joining voice channel: {}", why);
+                        format!("Couldn't join {}: {}", voice_channel.mention(), why)
+                    }
+                },
+                None => String::from("Join a voice channel first, or pick one!")
+            }
+        },
+        "leave" => match voice::discord::leave(ctx, guild).await.map_err(|err| err.to_string()) {
+            Ok(Some(left)) => {
+                manager.data.lock().unwrap().voice_channels.retain(|_, channel| *channel != left.to_string());
+                String::from("Left the voice channel.")
+            },
+            Ok(None) => String::from("I'm not in a voice channel!"),
+            Err(why) => {
+                println!("Failed leaving voice channel: {}", why);
+                format!("Couldn't leave the voice channel: {}", why)
+            }
+        },
+        _ => String::from("Command not implemented")
+    };
+
+    if let Err(why) = command.edit_original_interaction_response(&ctx.http, |response| response.content(content)).await {
+        println!("Cannot edit slash command response: {}", why);
+    }
+}
@@ -0,0 +1,614 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::matrixbot::MatrixConfig;
+use crate::textgen::backend::{CircuitBreakerPolicy, Endpoint, RetryPolicy};
+
+/// Prefix of the environment variables overriding config values, e.g. `UC207_SAMPLING_TEMPERATURE`
+pub const ENV_PREFIX: &str = "UC207_";
+
+/// Config file names looked for in the data directory, in order
+pub const CONFIG_FILE_NAMES: [&str; 4] = ["config.toml", "config.yaml", "config.yml", "config.json"];
+
+/// Settings of the whole bot. Every field has a default, so a config file only needs what differs from them.
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub discord: DiscordConfig,
+    pub backend: BackendConfig,
+    pub sampling: SamplingConfig,
+    pub limits: LimitsConfig,
+    pub history: HistoryConfig,
+    pub format: FormatConfig,
+    pub storage: StorageConfig,
+    pub logging: LoggingConfig,
+    pub vision: VisionConfig,
+    pub image_generation: ImageGenerationConfig,
+    pub voice: VoiceConfig,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matrix: Option<MatrixConfig>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscordConfig {
+    /// Prefer `token_file` or the `DISCORD_TOKEN` environment variable to keep the token out of the config
+    pub token: Option<String>,
+    pub token_file: Option<String>,
+    pub command_scope: CommandScope,
+}
+
+/// Where slash commands are registered
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum CommandScope {
+    /// In every server separately, updates show up instantly
+    #[default]
+    Guild,
+    /// Once for all servers, updates can take a while to show up
+    Global,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackendConfig {
+    pub endpoints: Vec<Endpoint>,
+    /// Single endpoint from before `endpoints` existed, used together with `model_url`
+    pub textgen_url: Option<String>,
+    pub model_url: Option<String>,
+    pub retry: RetryPolicy,
+    pub circuit_breaker: CircuitBreakerPolicy,
+    pub prompt_template: String,
+}
+
+impl Default for BackendConfig {
+    fn default() -> Self {
+        BackendConfig {
+            endpoints: Vec::new(),
+            textgen_url: None,
+            model_url: None,
+            retry: RetryPolicy::default(),
+            circuit_breaker: CircuitBreakerPolicy::default(),
+            prompt_template: String::from("prompt_template.txt"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SamplingConfig {
+    pub temperature: f32,
+    pub top_p: f32,
+    pub typical_p: f32,
+    pub repetition_penalty: f32,
+    pub encoder_repetition_penalty: f32,
+    pub top_k: f32,
+    pub min_length: i32,
+    pub no_repeat_ngram_size: i32,
+    pub num_beams: i32,
+    pub penalty_alpha: f32,
+    pub length_penalty: f32,
+    /// For `openai_chat` endpoints, textgen ones use `repetition_penalty`
+    pub presence_penalty: f32,
+    pub frequency_penalty: f32,
+    /// Strings that end the reply when generated (OpenAI takes up to 4)
+    pub stop: Vec<String>,
+}
+
+impl Default for SamplingConfig {
+    fn default() -> Self {
+        SamplingConfig {
+            temperature: 0.72,
+            top_p: 0.73,
+            typical_p: 1.0,
+            repetition_penalty: 1.1,
+            encoder_repetition_penalty: 0.9,
+            top_k: 0.0,
+            min_length: 0,
+            no_repeat_ngram_size: 0,
+            num_beams: 1,
+            penalty_alpha: 0.0,
+            length_penalty: 1.0,
+            presence_penalty: 0.0,
+            frequency_penalty: 0.0,
+            stop: Vec::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// Messages fetched to build the chat history
+    pub history_messages: u64,
+    pub max_new_tokens: u32,
+    /// Prompt length in tokens above which the backend cuts the beginning
+    pub truncation_length: u32,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig { history_messages: 10, max_new_tokens: 200, truncation_length: 2000 }
+    }
+}
+
+/// How the prompt is laid out for the model: a plain transcript, instruct turns or chat messages
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct FormatConfig {
+    pub mode: PromptFormat,
+    /// Whose messages are the model's own turns
+    pub assistant: AssistantSpeakers,
+    /// Start every turn with `Name: `, so the model can tell the speakers of a group chat apart
+    pub include_names: bool,
+    /// Turn markers for `mode = "custom"`
+    pub user_prefix: String,
+    pub user_suffix: String,
+    pub assistant_prefix: String,
+    pub assistant_suffix: String,
+}
+
+impl Default for FormatConfig {
+    fn default() -> Self {
+        FormatConfig {
+            mode: PromptFormat::Transcript,
+            assistant: AssistantSpeakers::Character,
+            include_names: true,
+            user_prefix: String::new(),
+            user_suffix: String::new(),
+            assistant_prefix: String::new(),
+            assistant_suffix: String::new()
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum PromptFormat {
+    /// `Speaker: content` lines
+    #[default]
+    Transcript,
+    #[serde(rename = "chatml")]
+    ChatMl,
+    /// `[INST] ... [/INST]`
+    Llama2,
+    /// `USER: ... ASSISTANT: ...`
+    Vicuna,
+    /// Turns wrapped in the configured prefixes and suffixes
+    Custom,
+    /// System, user and assistant messages, for chat-completion endpoints
+    Chat,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum AssistantSpeakers {
+    /// Only the messages of the character replying
+    #[default]
+    Character,
+    /// Messages of every bot and character, users get the other turns
+    Bots,
+}
+
+/// How the chat history reads in the prompt
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    /// Which of their names users appear under. Persona names always win.
+    pub speaker_names: SpeakerNames,
+    /// Start replies with a quote of the message they reply to
+    pub reply_context: bool,
+    /// Quotes are cut to this many characters
+    pub quote_length: usize,
+    /// How many messages from the end of the history the channel's author's note goes, unless the note says otherwise
+    pub authors_note_depth: usize,
+    /// How many messages from the end of the history characters' post-history instructions go
+    pub instructions_depth: usize,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        HistoryConfig {
+            speaker_names: SpeakerNames::Nickname,
+            reply_context: true,
+            quote_length: 100,
+            authors_note_depth: 4,
+            instructions_depth: 0
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SpeakerNames {
+    /// Server nickname, else display name, else username
+    #[default]
+    Nickname,
+    /// Display name, else username
+    DisplayName,
+    Username,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub characters_dir: String,
+    /// Where message fences are saved
+    pub fences_file: String,
+    /// Where user personas are saved
+    pub users_file: String,
+    /// Where author's notes are saved
+    pub notes_file: String,
+    /// Where chats imported with `/import` are saved
+    pub imports_file: String,
+    /// Where avatars uploaded with `/character` are saved
+    pub avatars_dir: String,
+    /// Public URL `avatars_dir` is served at, uploaded avatars keep their expiring Discord link without it
+    pub avatars_url: String,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            characters_dir: String::from("characters"),
+            fences_file: String::from("fences.json"),
+            users_file: String::from("users.json"),
+            notes_file: String::from("notes.json"),
+            imports_file: String::from("imports.json"),
+            avatars_dir: String::from("avatars"),
+            avatars_url: String::new()
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// Print the body of every textgen request, prompt included, and of its response
+    pub log_requests: bool,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig { log_requests: true }
+    }
+}
+
+/// How images posted in the chat reach the model
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct VisionConfig {
+    /// Multimodal endpoint replies are generated with when the history has images
+    pub multimodal_url: Option<String>,
+    pub multimodal_format: MultimodalFormat,
+    /// Model name sent to OpenAI-compatible endpoints
+    pub model: String,
+    /// Captioning endpoint, used when there's no multimodal endpoint. Captions end up in the history as `[image: caption]`.
+    pub caption_url: Option<String>,
+    /// Only the most recent images are sent or captioned
+    pub max_images: usize,
+}
+
+impl Default for VisionConfig {
+    fn default() -> Self {
+        VisionConfig {
+            multimodal_url: None,
+            multimodal_format: MultimodalFormat::LlamaCpp,
+            model: String::new(),
+            caption_url: None,
+            max_images: 4
+        }
+    }
+}
+
+/// Request format of the multimodal endpoint
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum MultimodalFormat {
+    /// llama.cpp server's `/completion` with `image_data`, for llava and similar models
+    #[default]
+    #[serde(rename = "llama_cpp")]
+    LlamaCpp,
+    /// OpenAI-compatible `/v1/chat/completions` with `image_url` content parts
+    #[serde(rename = "openai")]
+    OpenAi,
+}
+
+/// txt2img settings for `/imagine` and `[picture: prompt]` tags in replies
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ImageGenerationConfig {
+    /// Base URL of an AUTOMATIC1111-compatible API (`/sdapi/v1/txt2img`), images aren't generated without it
+    pub url: Option<String>,
+    /// Added to the negative prompt of every image, before the character's own
+    pub negative_prompt: String,
+    pub steps: u32,
+    pub width: u32,
+    pub height: u32,
+    pub cfg_scale: f64,
+    pub sampler_name: String,
+    pub timeout_secs: u64,
+    /// Most `[picture: prompt]` tags turned into images per reply, the rest are just removed
+    pub max_images_per_reply: usize,
+}
+
+impl Default for ImageGenerationConfig {
+    fn default() -> Self {
+        ImageGenerationConfig {
+            url: None,
+            negative_prompt: String::new(),
+            steps: 20,
+            width: 512,
+            height: 512,
+            cfg_scale: 7.0,
+            sampler_name: String::from("Euler a"),
+            timeout_secs: 120,
+            max_images_per_reply: 1
+        }
+    }
+}
+
+/// Voice channel settings for `/voice`, text-to-speech and speech-to-text
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct VoiceConfig {
+    /// Text-to-speech endpoint answering `GET ?text=...` with a WAV file, like Coqui's `/api/tts` or Piper's HTTP
+    /// server. Replies aren't spoken without it.
+    pub tts_url: Option<String>,
+    /// Query parameter the voice is sent as: `speaker_id` for Coqui, `voice` for Piper
+    pub tts_voice_param: String,
+    /// Voice of characters that don't have their own, the server's default voice if empty
+    pub default_voice: String,
+    /// Whisper-compatible speech-to-text endpoint, like OpenAI's `/v1/audio/transcriptions` or whisper.cpp's
+    /// `/inference`. The bot can't listen without it.
+    pub stt_url: Option<String>,
+    pub stt_model: String,
+    /// Language spoken in voice channels, detected by the model if empty
+    pub stt_language: String,
+    /// How long a pause ends what someone says
+    pub silence_ms: u64,
+    /// Anything shorter is ignored
+    pub min_utterance_ms: u64,
+    pub timeout_secs: u64,
+}
+
+impl Default for VoiceConfig {
+    fn default() -> Self {
+        VoiceConfig {
+            tts_url: None,
+            tts_voice_param: String::from("speaker_id"),
+            default_voice: String::new(),
+            stt_url: None,
+            stt_model: String::from("whisper-1"),
+            stt_language: String::new(),
+            silence_ms: 1000,
+            min_utterance_ms: 400,
+            timeout_secs: 60
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConfigFormat {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl ConfigFormat {
+    /// Guessed from the extension, JSON if there's none
+    pub fn of(path: &str) -> ConfigFormat {
+        match Path::new(path).extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => ConfigFormat::Toml,
+            Some("yaml") | Some("yml") => ConfigFormat::Yaml,
+            _ => ConfigFormat::Json
+        }
+    }
+}
+
+/// A syntax error in a config file, with the line it's on
+pub struct SyntaxError {
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+/// Parses a config file of any supported format into a JSON value, before any checks on its content
+pub fn parse_value(text: &str, format: ConfigFormat) -> Result<Value, SyntaxError> {
+    match format {
+        ConfigFormat::Toml => toml::from_str(text).map_err(|err| SyntaxError {
+            line: err.span().map(|span| text[..span.start].matches('\n').count() + 1),
+            message: String::from(err.message())
+        }),
+        ConfigFormat::Yaml => serde_yaml::from_str(text).map_err(|err| SyntaxError {
+            line: err.location().map(|location| location.line()),
+            message: err.to_string()
+        }),
+        ConfigFormat::Json => serde_json::from_str(text).map_err(|err| {
+            let position = format!(" at line {} column {}", err.line(), err.column());
+            SyntaxError { line: Some(err.line()), message: err.to_string().trim_end_matches(&position).to_owned() }
+        })
+    }
+}
+
+/// Settings that used to be at the top level of `config.json`, and the section they're in now
+const LEGACY_KEYS: [(&str, &str); 17] = [
+    ("endpoints", "backend"),
+    ("textgen_url", "backend"),
+    ("model_url", "backend"),
+    ("retry", "backend"),
+    ("circuit_breaker", "backend"),
+    ("prompt_template", "backend"),
+    ("temperature", "sampling"),
+    ("top_p", "sampling"),
+    ("typical_p", "sampling"),
+    ("repetition_penalty", "sampling"),
+    ("encoder_repetition_penalty", "sampling"),
+    ("top_k", "sampling"),
+    ("min_length", "sampling"),
+    ("no_repeat_ngram_size", "sampling"),
+    ("num_beams", "sampling"),
+    ("penalty_alpha", "sampling"),
+    ("length_penalty", "sampling"),
+];
+
+/// Moves settings of the old flat `config.json` into their sections. Returns whether anything was moved.
+pub fn migrate_legacy(value: &mut Value) -> bool {
+    let object = match value.as_object_mut() {
+        Some(object) => object,
+        None => return false
+    };
+    let mut migrated = false;
+    for (key, section) in LEGACY_KEYS {
+        if let Some(setting) = object.remove(key) {
+            let section = object.entry(section).or_insert_with(|| Value::Object(Map::new()));
+            if let Some(section) = section.as_object_mut() {
+                section.entry(key).or_insert(setting);
+            }
+            migrated = true;
+        }
+    }
+    migrated
+}
+
+/// Every setting that can be overridden, as paths like `["backend", "retry", "max_attempts"]`, with its default
+fn settings() -> Vec<(Vec<String>, Value)> {
+    let mut shape = serde_json::to_value(Config::default()).expect("Config can't be serialized");
+    shape["matrix"] = serde_json::to_value(MatrixConfig::default()).expect("Config can't be serialized");
+
+    fn walk(value: &Value, path: &mut Vec<String>, settings: &mut Vec<(Vec<String>, Value)>) {
+        match value.as_object() {
+            Some(object) => for (key, child) in object {
+                path.push(key.to_owned());
+                walk(child, path, settings);
+                path.pop();
+            },
+            None => settings.push((path.clone(), value.clone()))
+        }
+    }
+    let mut settings = Vec::new();
+    walk(&shape, &mut Vec::new(), &mut settings);
+    settings
+}
+
+/// Sets the value at `path`, creating the sections on the way. String settings are taken as is,
+/// anything else is parsed as JSON (numbers, booleans, arrays of endpoints...).
+fn set(value: &mut Value, path: &[String], default: &Value, raw: &str) -> Result<(), Box<dyn Error>> {
+    let setting = if default.is_string() || default.is_null() {
+        Value::from(raw)
+    }
+    else {
+        serde_json::from_str(raw).map_err(|why| string_error::into_err(format!("Invalid value for {}: {}", path.join("."), why)))?
+    };
+
+    let mut target = value;
+    for key in &path[..path.len() - 1] {
+        if !target[key.as_str()].is_object() {
+            target[key.as_str()] = Value::Object(Map::new());
+        }
+        target = &mut target[key.as_str()];
+    }
+    target[path[path.len() - 1].as_str()] = setting;
+    Ok(())
+}
+
+/// Applies `UC207_<SECTION>_<SETTING>` variables, e.g. `UC207_BACKEND_RETRY_MAX_ATTEMPTS=5`
+pub fn apply_env(value: &mut Value, vars: impl IntoIterator<Item = (String, String)>) -> Result<(), Box<dyn Error>> {
+    let settings = settings();
+    for (name, raw) in vars {
+        let found = settings.iter().find(|(path, _)| name == [ENV_PREFIX, &path.join("_").to_uppercase()].join(""));
+        if let Some((path, default)) = found {
+            set(value, path, default, &raw)?;
+        }
+    }
+    Ok(())
+}
+
+/// Applies `section.setting=value` overrides, e.g. from `--set sampling.temperature=0.5`
+pub fn apply_overrides(value: &mut Value, overrides: &[String]) -> Result<(), Box<dyn Error>> {
+    let settings = settings();
+    for setting in overrides {
+        let (key, raw) = setting.split_once('=')
+            .ok_or_else(|| string_error::into_err(format!("Expected section.setting=value, got {}", setting)))?;
+        let (path, default) = settings.iter().find(|(path, _)| path.join(".") == key.trim())
+            .ok_or_else(|| string_error::into_err(format!("Unknown setting {}", key)))?;
+        set(value, path, default, raw)?;
+    }
+    Ok(())
+}
+
+impl Config {
+    /// Builds the config from an already parsed file, reporting the setting at fault on errors
+    pub fn from_value(mut value: Value) -> Result<Config, serde_path_to_error::Error<serde_json::Error>> {
+        // Lets JSON configs point editors to the schema
+        if let Some(object) = value.as_object_mut() {
+            object.remove("$schema");
+        }
+        serde_path_to_error::deserialize(value)
+    }
+
+    /// Parses a config file's content on its own, without environment overrides
+    pub fn parse(text: &str, format: ConfigFormat) -> Result<Config, Box<dyn Error>> {
+        let mut value = parse_value(text, format).map_err(|err| string_error::into_err(err.message))?;
+        migrate_legacy(&mut value);
+        Ok(Config::from_value(value)?)
+    }
+
+    /// Loads the config in layers: defaults, then the file, then `UC207_*` environment variables, then `overrides`.
+    /// Relative paths are relative to the config file's directory.
+    pub fn load(path: &str, overrides: &[String]) -> Result<Config, Box<dyn Error>> {
+        let text = fs::read_to_string(path)
+            .map_err(|why| string_error::into_err(format!("Can't read {}: {}", path, why)))?;
+        let mut value = parse_value(&text, ConfigFormat::of(path)).map_err(|err| match err.line {
+            Some(line) => string_error::into_err(format!("{}:{}: {}", path, line, err.message)),
+            None => string_error::into_err(format!("{}: {}", path, err.message))
+        })?;
+        if migrate_legacy(&mut value) {
+            println!("{} uses the old flat format, run `uc207 validate` to check it and see the readme for the sections", path);
+        }
+        apply_env(&mut value, std::env::vars())?;
+        apply_overrides(&mut value, overrides)?;
+
+        let mut config = Config::from_value(value).map_err(|err| string_error::into_err(format!("{}: {}", path, err)))?;
+        // The environment's token wins over the file's token and token file
+        if let Some(token) = std::env::var("DISCORD_TOKEN").ok().filter(|token| !token.is_empty()) {
+            config.discord.token = Some(token);
+        }
+        if let Some(config_dir) = Path::new(path).parent() {
+            config.resolve_paths(config_dir);
+        }
+        Ok(config)
+    }
+
+    /// Makes relative paths relative to `base` instead of the working directory
+    pub fn resolve_paths(&mut self, base: &Path) {
+        let resolve = |path: &mut String| *path = base.join(&*path).to_string_lossy().into_owned();
+        resolve(&mut self.backend.prompt_template);
+        resolve(&mut self.storage.characters_dir);
+        resolve(&mut self.storage.fences_file);
+        resolve(&mut self.storage.users_file);
+        resolve(&mut self.storage.notes_file);
+        resolve(&mut self.storage.imports_file);
+        resolve(&mut self.storage.avatars_dir);
+        if let Some(token_file) = &mut self.discord.token_file {
+            resolve(token_file);
+        }
+    }
+
+    /// The Discord token, else the content of the token file. `DISCORD_TOKEN` and `--token-file` are already layered in by then.
+    pub fn discord_token(&self) -> Result<Option<String>, Box<dyn Error>> {
+        if let Some(token) = self.discord.token.as_ref().filter(|token| !token.is_empty()) {
+            return Ok(Some(token.to_owned()));
+        }
+        match &self.discord.token_file {
+            Some(path) => {
+                let token = fs::read_to_string(path)
+                    .map_err(|why| string_error::into_err(format!("Can't read token file {}: {}", path, why)))?;
+                Ok(Some(token.trim().to_owned()))
+            },
+            None => Ok(None)
+        }
+    }
+}
@@ -0,0 +1,507 @@
+use std::error::Error;
+use std::sync::Mutex;
+
+use crate::botmanager::BotManagerData;
+use crate::config::SpeakerNames;
+use crate::platform::{ChatPlatform, Persona, PlatformMessage, PlatformResult};
+use crate::swipes::{SwipeAction, SwipeState};
+use crate::textgen::api::{Message, PromptExtras, TextgenApi};
+use crate::textgen::format::Prompt;
+use crate::textgen::imagegen;
+use crate::textgen::vision::{self, Image};
+use crate::transcript::{ImportedHistory, MAX_EXPORT_MESSAGES};
+use crate::users::UserProfiles;
+use crate::voice::tts;
+
+/// Reaction added to a user's message when no backend could answer it
+pub const BACKEND_UNAVAILABLE_REACTION: char = '🔌';
+
+/// Platform independent conversation logic: building the history, generating replies and handling swipes
+pub struct Conversation<'a> {
+    pub api: &'a TextgenApi,
+    pub data: &'a Mutex<BotManagerData>,
+}
+
+/// Turns platform messages (newest first) into the history seen by the model, oldest first.
+/// Skips messages without text.
+pub fn build_history(messages: &[PlatformMessage]) -> Vec<Message> {
+    let mut history: Vec<Message> = messages.iter()
+        .filter(|message| !message.content.is_empty())
+        .map(|message| Message {
+            speaker: String::from(&message.author_name),
+            content: String::from(&message.content)
+        })
+        .collect();
+    history.reverse();
+    history
+}
+
+/// Name the author of a message goes by in the history: their persona's name in `server`, else the one `names` picks.
+/// Bots and characters keep theirs.
+pub fn speaker_name(message: &PlatformMessage, names: SpeakerNames, profiles: &UserProfiles, server: Option<&str>) -> String {
+    if message.is_bot {
+        return message.author_name.to_owned();
+    }
+    if let Some(profile) = profiles.get(&message.author_id, server).filter(|profile| !profile.name.is_empty()) {
+        return profile.name.to_owned();
+    }
+    let name = match names {
+        SpeakerNames::Nickname => message.nickname.as_ref().or(message.display_name.as_ref()),
+        SpeakerNames::DisplayName => message.display_name.as_ref(),
+        SpeakerNames::Username => None
+    };
+    name.unwrap_or(&message.author_name).to_owned()
+}
+
+/// First `length` characters of a message on one line, with an ellipsis if it's cut
+fn quote(content: &str, length: usize) -> String {
+    let line = content.split_whitespace().collect::<Vec<&str>>().join(" ");
+    match line.char_indices().nth(length) {
+        Some((end, _)) => format!("{}…", line[..end].trim_end()),
+        None => line
+    }
+}
+
+impl<'a> Conversation<'a> {
+    pub fn new(api: &'a TextgenApi, data: &'a Mutex<BotManagerData>) -> Conversation<'a> {
+        Conversation { api, data }
+    }
+
+    /// Replies as the character invited to the channel, if any
+    pub async fn on_message(&self, platform: &dyn ChatPlatform, channel: &str, message: &PlatformMessage) {
+        if message.is_bot {
+            return; // No infinite loops pls
+        }
+        self.reply(platform, channel, &message.id).await;
+    }
+
+    /// Posts what someone said in a voice channel to the channel as them, so it's part of the history like any
+    /// other message of theirs, and replies to it as the character invited to the channel, if any
+    pub async fn on_voice_message(&self, platform: &dyn ChatPlatform, channel: &str, speaker_id: &str, speaker: &Persona, text: &str) {
+        if text.is_empty() || self.persona(channel).is_none() {
+            return;
+        }
+        let message_id = match platform.send_as_persona(channel, speaker, text, None).await {
+            Ok(message_id) => message_id,
+            Err(why) => {
+                println!("Failed posting transcript: {}", why);
+                return;
+            }
+        };
+        {
+            let mut data = self.data.lock().unwrap();
+            let transcripts = data.voice_transcripts.entry(String::from(channel)).or_default();
+            transcripts.push((message_id.to_owned(), String::from(speaker_id)));
+            // Older transcripts are out of reach of histories and exports
+            transcripts.drain(..transcripts.len().saturating_sub(MAX_EXPORT_MESSAGES as usize));
+        }
+        self.reply(platform, channel, &message_id).await;
+    }
+
+    /// Generates and sends the next reply in the channel, reacting to `trigger` if no backend answers
+    async fn reply(&self, platform: &dyn ChatPlatform, channel: &str, trigger: &str) {
+        let (prompt, images, persona) = match self.prepare_prompt(platform, channel).await {
+            Some(prompt) => prompt,
+            None => return
+        };
+
+        if let Err(why) = platform.start_typing(channel).await {
+            println!("Failed saying I'm typing: {}", why);
+        }
+        let result = self.api.request_with_images(prompt.clone(), &images).await.map_err(|err| err.to_string());
+        if let Err(why) = platform.stop_typing(channel).await {
+            println!("Failed saying I'm no longer typing: {}", why);
+        }
+
+        match result {
+            Ok(reply) => {
+                if let Err(why) = self.send_reply(platform, channel, &persona, prompt, images, reply).await {
+                    println!("Error sending message: {}", why);
+                }
+            },
+            Err(err) => {
+                println!("Received no response from API: {}", err);
+                if let Err(why) = platform.react(channel, trigger, BACKEND_UNAVAILABLE_REACTION).await {
+                    println!("Failed reacting to message: {}", why);
+                }
+            }
+        }
+    }
+
+    /// Builds the prompt for the next reply in the channel, along with who's replying. `None` if no character is invited.
+    pub async fn prompt_for(&self, platform: &dyn ChatPlatform, channel: &str) -> Option<(String, Persona)> {
+        self.prepare_prompt(platform, channel).await.map(|(prompt, _, persona)| (prompt.to_string(), persona))
+    }
+
+    /// Like `prompt_for`, with the images the prompt refers to for multimodal backends
+    async fn prepare_prompt(&self, platform: &dyn ChatPlatform, channel: &str) -> Option<(Prompt, Vec<Image>, Persona)> {
+        self.persona(channel)?;
+
+        let fence = {
+            let data = self.data.lock().unwrap();
+            data.invited_characters.get(channel)
+                .and_then(|id| data.fences.latest(channel, id))
+                .map(|fence| fence.message_id.to_owned())
+        };
+        let mut messages = match platform.fetch_history(channel, self.api.history_limit(), fence.as_deref()).await {
+            Ok(messages) => messages,
+            Err(why) => {
+                println!("Failed getting message history: {}", why);
+                return None;
+            }
+        };
+        let images = self.describe_images(platform, &mut messages).await;
+        self.attribute_transcripts(channel, &mut messages);
+        let server = platform.server(channel);
+        self.name_speakers(&mut messages, server.as_deref());
+        let extras = self.prompt_extras(&messages, channel, server.as_deref());
+        let mut history = build_history(&messages);
+        if (messages.len() as u64) < self.api.history_limit() {
+            let mut imported = self.imported_history(channel);
+            let room = self.api.history_limit() as usize - messages.len();
+            imported.drain(..imported.len().saturating_sub(room));
+            history.splice(0..0, imported);
+        }
+
+        let data = self.data.lock().unwrap();
+        let character = data.invited_characters.get(channel).and_then(|id| data.characters.get(id))?;
+        match self.api.make_prompt(character, &history, &extras) {
+            Ok(prompt) => Some((prompt, images, Persona { name: character.char_name.to_owned(), avatar_url: character.avatar_url.to_owned() })),
+            Err(why) => {
+                println!("Failed making prompt: {}", why);
+                None
+            }
+        }
+    }
+
+    /// Seeds the channel's history with messages from elsewhere, seen by the characters before the channel's own messages
+    /// until the next fence. Replaces anything imported before.
+    pub fn import(&self, channel: &str, messages: Vec<Message>) -> Result<(), Box<dyn Error>> {
+        let mut data = self.data.lock().unwrap();
+        let fence = data.fences.list(channel).last().map(|fence| fence.message_id.to_owned());
+        data.imported_history.set(channel, ImportedHistory { fence, messages })
+    }
+
+    /// Messages imported into the channel, unless a fence was added since
+    fn imported_history(&self, channel: &str) -> Vec<Message> {
+        let data = self.data.lock().unwrap();
+        let fence = data.fences.list(channel).last().map(|fence| fence.message_id.as_str());
+        data.imported_history.get(channel)
+            .filter(|imported| imported.fence.as_deref() == fence)
+            .map(|imported| imported.messages.clone())
+            .unwrap_or_default()
+    }
+
+    /// The channel's history, oldest first, for exports: back to the latest fence (with what was imported before it),
+    /// or from the message `from` on. With `to`, stops at that message.
+    pub async fn transcript(&self, platform: &dyn ChatPlatform, channel: &str, from: Option<&str>, to: Option<&str>) -> PlatformResult<Vec<Message>> {
+        let fence = {
+            let data = self.data.lock().unwrap();
+            let latest = data.invited_characters.get(channel)
+                .and_then(|id| data.fences.latest(channel, id))
+                .or_else(|| data.fences.list(channel).last());
+            latest.map(|fence| fence.message_id.to_owned())
+        };
+        let after = if from.is_some() { None } else { fence.as_deref() };
+        let mut messages = platform.fetch_history(channel, MAX_EXPORT_MESSAGES, after).await?;
+
+        if let Some(from) = from {
+            let index = messages.iter().position(|message| message.id == from)
+                .ok_or_else(|| format!("Message {} isn't among the last {} messages", from, MAX_EXPORT_MESSAGES))?;
+            messages.truncate(index + 1);
+        }
+        if let Some(to) = to {
+            let index = messages.iter().position(|message| message.id == to)
+                .ok_or_else(|| format!("Message {} isn't in the exported range", to))?;
+            messages.drain(..index);
+        }
+
+        self.attribute_transcripts(channel, &mut messages);
+        self.name_speakers(&mut messages, platform.server(channel).as_deref());
+        let mut history = build_history(&messages);
+        if from.is_none() {
+            history.splice(0..0, self.imported_history(channel));
+        }
+        Ok(history)
+    }
+
+    /// Voice transcripts are posted by the bot, they're turned back into messages from their speakers
+    fn attribute_transcripts(&self, channel: &str, messages: &mut [PlatformMessage]) {
+        let data = self.data.lock().unwrap();
+        let transcripts = match data.voice_transcripts.get(channel) {
+            Some(transcripts) => transcripts,
+            None => return
+        };
+        for message in messages.iter_mut() {
+            if let Some((_, speaker_id)) = transcripts.iter().find(|(message_id, _)| *message_id == message.id) {
+                message.author_id = speaker_id.to_owned();
+                message.is_bot = false;
+                message.is_own = false;
+            }
+        }
+    }
+
+    /// Names the authors of messages as the `history` config says, and starts replies with a quote of what they reply to
+    fn name_speakers(&self, messages: &mut [PlatformMessage], server: Option<&str>) {
+        let config = self.api.history();
+        let data = self.data.lock().unwrap();
+        for message in messages.iter_mut() {
+            let context = message.reply_to.as_deref()
+                .filter(|_| config.reply_context && !message.content.is_empty())
+                .map(|reply_to| {
+                    let name = speaker_name(reply_to, config.speaker_names, &data.user_profiles, server);
+                    match quote(&reply_to.content, config.quote_length) {
+                        quoted if quoted.is_empty() => format!("(replying to {})", name),
+                        quoted => format!("(replying to {}: \"{}\")", name, quoted)
+                    }
+                });
+            message.author_name = speaker_name(message, config.speaker_names, &data.user_profiles, server);
+            if let Some(context) = context {
+                message.content = format!("{} {}", context, message.content);
+            }
+        }
+    }
+
+    /// Who's in the named history: the bots, the personas of everyone who described themselves, oldest first, and the
+    /// author of the latest message from a user, who the character replies to. Also the channel's author's note.
+    fn prompt_extras(&self, messages: &[PlatformMessage], channel: &str, server: Option<&str>) -> PromptExtras {
+        let data = self.data.lock().unwrap();
+        let description = |message: &PlatformMessage| data.user_profiles.get(&message.author_id, server)
+            .map(|profile| profile.description.to_owned())
+            .unwrap_or_default();
+        let mut extras = PromptExtras {
+            authors_note: data.authors_notes.get(channel).cloned(),
+            ..PromptExtras::default()
+        };
+        for message in messages.iter().rev().filter(|message| message.is_bot && !message.is_own) {
+            if !extras.bots.contains(&message.author_name) {
+                extras.bots.push(message.author_name.to_owned());
+            }
+        }
+        if let Some(latest) = messages.iter().find(|message| !message.is_bot) {
+            extras.user = latest.author_name.to_owned();
+            extras.user_persona = description(latest);
+        }
+
+        let mut seen = Vec::new();
+        for message in messages.iter().rev().filter(|message| !message.is_bot) {
+            if seen.contains(&&message.author_id) {
+                continue;
+            }
+            seen.push(&message.author_id);
+            let description = description(message);
+            if !description.is_empty() {
+                extras.users.push((message.author_name.to_owned(), description));
+            }
+        }
+        extras
+    }
+
+    /// Makes the most recent images visible to the model: with a multimodal backend they're referenced by markers in the
+    /// text and returned in the order of the markers, otherwise their captions are added to the text when possible.
+    async fn describe_images(&self, platform: &dyn ChatPlatform, messages: &mut [PlatformMessage]) -> Vec<Image> {
+        let vision = self.api.vision();
+        let multimodal = vision.multimodal_url.is_some();
+        if !multimodal && vision.caption_url.is_none() {
+            return Vec::new();
+        }
+
+        // Messages are newest first, the images of the oldest ones are left out
+        let mut selected: Vec<(usize, String)> = messages.iter().enumerate()
+            .flat_map(|(index, message)| message.images.iter().rev().map(move |url| (index, url.to_owned())))
+            .take(vision.max_images)
+            .collect();
+        selected.reverse();
+
+        let mut images = Vec::new();
+        for (index, url) in selected {
+            let caption = self.api.cached_caption(&url).filter(|_| !multimodal);
+            let description = match caption {
+                Some(caption) => format!("[image: {}]", caption),
+                None => {
+                    let image = match platform.download(&url).await {
+                        Ok(data) => Image { url, data },
+                        Err(why) => {
+                            println!("Failed downloading image {}: {}", url, why);
+                            continue;
+                        }
+                    };
+                    if multimodal {
+                        images.push(image);
+                        vision::marker(vision.multimodal_format, images.len())
+                    }
+                    else {
+                        match self.api.caption(&image).await.map_err(|err| err.to_string()) {
+                            Ok(Some(caption)) => format!("[image: {}]", caption),
+                            Ok(None) => continue,
+                            Err(why) => {
+                                println!("Failed captioning image {}: {}", image.url, why);
+                                continue;
+                            }
+                        }
+                    }
+                }
+            };
+            let content = &mut messages[index].content;
+            *content = if content.is_empty() {description} else {[content.as_str(), " ", &description].join("")};
+        }
+        images
+    }
+
+    /// Name and avatar of the character invited to the channel
+    pub fn persona(&self, channel: &str) -> Option<Persona> {
+        let data = self.data.lock().unwrap();
+        data.invited_characters.get(channel)
+            .and_then(|id| data.characters.get(id))
+            .map(|character| Persona { name: character.char_name.to_owned(), avatar_url: character.avatar_url.to_owned() })
+    }
+
+    /// Sends a generated reply with swipe controls, removing them from the previous reply, then the pictures it asks for
+    pub async fn send_reply(&self, platform: &dyn ChatPlatform, channel: &str, persona: &Persona, prompt: Prompt, images: Vec<Image>, content: String) -> PlatformResult<()> {
+        let (content, pictures) = self.take_picture_requests(content);
+        let mut state = SwipeState {
+            message_id: String::new(),
+            prompt,
+            images,
+            candidates: vec![content.to_owned()],
+            current: 0
+        };
+        state.message_id = platform.send_as_persona(channel, persona, &state.candidates[0], Some(state.controls())).await?;
+
+        let previous = self.data.lock().unwrap().swipes.insert(String::from(channel), state);
+        if let Some(previous) = previous {
+            platform.edit_message(channel, &previous.message_id, previous.shown(), None).await?;
+        }
+        self.speak(platform, channel, &content).await;
+        self.send_pictures(platform, channel, persona, pictures).await;
+        Ok(())
+    }
+
+    /// Reads a reply out in the voice channel linked to the channel, if any, with the voice of the character invited to it
+    pub async fn speak(&self, platform: &dyn ChatPlatform, channel: &str, text: &str) {
+        let speech = match self.api.speech() {
+            Some(speech) => speech,
+            None => return
+        };
+        let voice = {
+            let data = self.data.lock().unwrap();
+            if !data.voice_channels.contains_key(channel) {
+                return;
+            }
+            match data.invited_characters.get(channel).and_then(|id| data.characters.get(id)) {
+                Some(character) => speech.voice_for(character).to_owned(),
+                None => return
+            }
+        };
+        let text = tts::spoken_text(text);
+        if text.is_empty() {
+            return;
+        }
+
+        let audio = match speech.synthesize(&text, &voice).await.map_err(|err| err.to_string()) {
+            Ok(audio) => audio,
+            Err(why) => {
+                println!("Failed synthesising speech: {}", why);
+                return;
+            }
+        };
+        if let Err(why) = platform.play_audio(channel, &audio).await {
+            println!("Failed playing speech: {}", why);
+        }
+    }
+
+    /// Splits `[picture: prompt]` tags off a reply when images can be generated. A reply with nothing but tags is kept as is.
+    fn take_picture_requests(&self, reply: String) -> (String, Vec<String>) {
+        let generator = match self.api.image_generator() {
+            Some(generator) => generator,
+            None => return (reply, Vec::new())
+        };
+        let (text, mut prompts) = imagegen::extract_image_tags(&reply);
+        prompts.truncate(generator.max_images_per_reply());
+        if text.trim().is_empty() {
+            (reply, prompts)
+        }
+        else {
+            (text, prompts)
+        }
+    }
+
+    /// Generates pictures of the character invited to the channel and sends them as the character
+    async fn send_pictures(&self, platform: &dyn ChatPlatform, channel: &str, persona: &Persona, prompts: Vec<String>) {
+        for prompt in prompts {
+            if let Err(why) = self.imagine(platform, channel, persona, &prompt).await {
+                println!("Failed sending picture: {}", why);
+            }
+        }
+    }
+
+    /// Generates a picture of the character invited to the channel, based on its image prompt, and sends it as `persona`
+    pub async fn imagine(&self, platform: &dyn ChatPlatform, channel: &str, persona: &Persona, prompt: &str) -> Result<String, String> {
+        let generator = self.api.image_generator().ok_or("Image generation isn't configured")?;
+        let (prompt, negative_prompt) = {
+            let data = self.data.lock().unwrap();
+            let character = data.invited_characters.get(channel)
+                .and_then(|id| data.characters.get(id))
+                .ok_or("There is no active bot in this channel")?;
+            generator.prompts(character, prompt)
+        };
+        let image = generator.generate(&prompt, &negative_prompt).await.map_err(|err| err.to_string())?;
+        platform.send_image(channel, persona, "", &image).await.map_err(|err| err.to_string())
+    }
+
+    /// Switches to another candidate of the latest reply, generating a new one for `SwipeAction::Regenerate`
+    pub async fn swipe(&self, platform: &dyn ChatPlatform, channel: &str, message_id: &str, action: SwipeAction) -> PlatformResult<()> {
+        let prompt = {
+            let mut data = self.data.lock().unwrap();
+            let state = match data.swipes.get_mut(channel).filter(|state| state.message_id == message_id) {
+                Some(state) => state,
+                None => return Ok(())
+            };
+            match action {
+                SwipeAction::Previous => {
+                    state.current = state.current.saturating_sub(1);
+                    None
+                },
+                SwipeAction::Next => {
+                    state.current = (state.current + 1).min(state.candidates.len() - 1);
+                    None
+                },
+                SwipeAction::Regenerate => Some((state.prompt.clone(), state.images.clone()))
+            }
+        };
+
+        let mut pictures = Vec::new();
+        let mut spoken = None;
+        if let Some((prompt, images)) = prompt {
+            let candidate = self.api.request_with_images(prompt, &images).await.map_err(|err| err.to_string())?;
+            let (candidate, requested) = self.take_picture_requests(candidate);
+            pictures = requested;
+            spoken = Some(candidate.to_owned());
+            let mut data = self.data.lock().unwrap();
+            if let Some(state) = data.swipes.get_mut(channel).filter(|state| state.message_id == message_id) {
+                state.candidates.push(candidate);
+                state.current = state.candidates.len() - 1;
+            }
+        }
+
+        let update = {
+            let data = self.data.lock().unwrap();
+            data.swipes.get(channel)
+                .filter(|state| state.message_id == message_id)
+                .map(|state| (state.shown().to_owned(), state.controls()))
+        };
+        let (content, controls) = match update {
+            Some(update) => update,
+            // A newer reply came in while regenerating, the candidate is dropped along with this reply's swipes
+            None => return Ok(())
+        };
+        platform.edit_message(channel, message_id, &content, Some(controls)).await?;
+        if let Some(spoken) = spoken {
+            self.speak(platform, channel, &spoken).await;
+        }
+        if let Some(persona) = self.persona(channel).filter(|_| !pictures.is_empty()) {
+            self.send_pictures(platform, channel, &persona, pictures).await;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,65 @@
+use std::error::Error;
+
+use serde::{Deserialize, Serialize};
+
+use crate::store::Store;
+
+/// Text of the messages marking a fence. Only shown to users, fences are found by message ID.
+pub const FENCE_MESSAGE: &str = "--- Message Fence ---\nBots won't see any messages above this one!";
+
+/// Characters don't see any message before the fence message
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Fence {
+    pub message_id: String,
+    /// Character the fence applies to, every character if `None`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub character_id: Option<String>,
+}
+
+/// Fences of every channel, oldest first, saved to a file on every change
+#[derive(Default)]
+pub struct Fences {
+    channels: Store<Vec<Fence>>,
+}
+
+impl Fences {
+    /// Loads the fences saved at `path`, or starts without any if the file doesn't exist yet
+    pub fn load(path: &str) -> Result<Fences, Box<dyn Error>> {
+        Ok(Fences { channels: Store::load(path)? })
+    }
+
+    pub fn list(&self, channel: &str) -> &[Fence] {
+        self.channels.get(channel).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Most recent fence a character has to respect in the channel
+    pub fn latest(&self, channel: &str, character_id: &str) -> Option<&Fence> {
+        self.list(channel).iter()
+            .rev()
+            .find(|fence| fence.character_id.as_deref().is_none_or(|id| id == character_id))
+    }
+
+    pub fn add(&mut self, channel: &str, fence: Fence) -> Result<(), Box<dyn Error>> {
+        self.channels.update(channel, |fences| fences.get_or_insert_with(Vec::new).push(fence))
+    }
+
+    /// Removes the fence on `message_id`, or the latest one if `None`
+    pub fn remove(&mut self, channel: &str, message_id: Option<&str>) -> Result<Option<Fence>, Box<dyn Error>> {
+        let index = match message_id {
+            Some(message_id) => self.list(channel).iter().position(|fence| fence.message_id == message_id),
+            None => self.list(channel).len().checked_sub(1)
+        };
+        let index = match index {
+            Some(index) => index,
+            None => return Ok(None)
+        };
+        self.channels.update(channel, |fences| {
+            let list = fences.as_mut()?;
+            let removed = list.remove(index);
+            if list.is_empty() {
+                *fences = None;
+            }
+            Some(removed)
+        })
+    }
+}
@@ -0,0 +1,17 @@
+pub mod botmanager;
+pub mod cli;
+pub mod commands;
+pub mod config;
+pub mod conversation;
+pub mod fences;
+pub mod matrixbot;
+pub mod notes;
+pub mod platform;
+pub mod repl;
+pub mod store;
+pub mod swipes;
+pub mod textgen;
+pub mod transcript;
+pub mod users;
+pub mod validation;
+pub mod voice;
\ No newline at end of file
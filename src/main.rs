@@ -1,49 +1,134 @@
-mod botmanager;
-mod commands;
-mod textgen;
+use std::error::Error;
+use std::process::ExitCode;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
 
-use std::collections::HashMap;
-use std::sync::Mutex;
-
-use botmanager::{BotManagerData};
+use clap::Parser;
 use serenity::prelude::{GatewayIntents};
 use serenity::{Client};
-use textgen::api::TextgenApi;
-use textgen::character::Character;
+use uc207::botmanager::{self, BotManagerData};
+use uc207::cli::{self, Cli, CliCommand};
+use uc207::config::Config;
+use uc207::fences::Fences;
+use uc207::matrixbot::MatrixBot;
+use uc207::notes::AuthorsNotes;
+use uc207::store::Store;
+use uc207::textgen::api::TextgenApi;
+use uc207::textgen::character::Character;
+use uc207::users::UserProfiles;
 
 #[tokio::main]
-async fn main() {
-    let token = match std::env::var("DISCORD_TOKEN"){
-        Ok(str) => str,
-        Err(_) => panic!("Missing DISCORD_TOKEN environment variable")
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    let result = match cli.command.as_ref().unwrap_or(&CliCommand::Run) {
+        CliCommand::Run => run(&cli).await,
+        CliCommand::Validate => {
+            if !cli::validate(&cli) {
+                return ExitCode::FAILURE;
+            }
+            Ok(())
+        },
+        CliCommand::Chat { character_id } => chat(&cli, character_id).await,
+        CliCommand::ListCharacters => load_config(&cli).and_then(|config| cli::list_characters(&config.storage.characters_dir)
+            .map_err(|why| failure("Error loading characters", why))),
+        CliCommand::Import { file, id, force } => load_config(&cli)
+            .and_then(|config| cli::import(&config.storage.characters_dir, file, id.as_deref(), *force)
+                .map_err(|why| failure("Import failed", why)))
+            .map(|id| println!("Imported character {}", id)),
+        CliCommand::RegisterCommands { guild } => register_commands(&cli, *guild).await
     };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(why) => {
+            println!("{}", why);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn failure(what: &str, why: impl std::fmt::Display) -> Box<dyn Error> {
+    string_error::into_err(format!("{}: {}", what, why))
+}
+
+fn load_config(cli: &Cli) -> Result<Config, Box<dyn Error>> {
+    cli.load_config().map_err(|why| failure("Invalid config", why))
+}
+
+fn discord_token(config: &Config) -> Result<Option<String>, Box<dyn Error>> {
+    config.discord_token().map_err(|why| failure("Unable to read Discord token", why))
+}
+
+const MISSING_TOKEN: &str = "Missing DISCORD_TOKEN environment variable, discord.token or discord.token_file";
+
+async fn chat(cli: &Cli, character_id: &str) -> Result<(), Box<dyn Error>> {
+    let config = load_config(cli)?;
+    let api = TextgenApi::new(&config).map_err(|why| failure("Unable to initialize textgen API", why))?;
+    let characters = Character::load_all(&config.storage.characters_dir).map_err(|why| failure("Error loading characters", why))?;
+    uc207::repl::chat(&api, BotManagerData::new(characters), character_id).await
+}
 
-    let api = TextgenApi::init("config.json").expect("Unable to initialize textgn API");
-    let characters = Character::load_all("characters").expect("Error loading characters");
-    let invited_characters = HashMap::new();
+async fn register_commands(cli: &Cli, guild: Option<u64>) -> Result<(), Box<dyn Error>> {
+    let config = load_config(cli)?;
+    let token = discord_token(&config)?.ok_or(MISSING_TOKEN)?;
+    cli::register_commands(&token, config.discord.command_scope, guild).await
+        .map_err(|why| failure("Failed registering commands", why))
+}
 
-    let manager_data = BotManagerData {
-        characters: characters,
-        invited_characters: invited_characters
+async fn run(cli: &Cli) -> Result<(), Box<dyn Error>> {
+    let config = load_config(cli)?;
+    let token = discord_token(&config)?;
+    if token.is_none() && config.matrix.is_none() {
+        return Err(MISSING_TOKEN.into());
+    }
+
+    let api = Arc::new(TextgenApi::new(&config).map_err(|why| failure("Unable to initialize textgen API", why))?);
+    let characters = Character::load_all(&config.storage.characters_dir).map_err(|why| failure("Error loading characters", why))?;
+    let mut manager_data = BotManagerData::new(characters);
+    manager_data.fences = Fences::load(&config.storage.fences_file).map_err(|why| failure("Error loading fences", why))?;
+    manager_data.user_profiles = UserProfiles::load(&config.storage.users_file).map_err(|why| failure("Error loading user profiles", why))?;
+    manager_data.authors_notes = AuthorsNotes::load(&config.storage.notes_file).map_err(|why| failure("Error loading author's notes", why))?;
+    manager_data.imported_history = Store::load(&config.storage.imports_file).map_err(|why| failure("Error loading imported chats", why))?;
+    let manager_data = Arc::new(Mutex::new(manager_data));
+
+    let matrix = config.matrix.as_ref().map(|config| {
+        let bot = Arc::new(MatrixBot::new(config, api.clone(), manager_data.clone()));
+        tokio::spawn(bot.run())
+    });
+
+    let token = match token {
+        Some(token) => token,
+        None => {
+            // Matrix only
+            if let Some(matrix) = matrix {
+                matrix.await.map_err(|why| failure("Matrix bot crashed", why))?;
+            }
+            return Ok(());
+        }
     };
 
-    let mut client = Client::builder(&token, 
+    let builder = Client::builder(&token, 
             GatewayIntents::MESSAGE_CONTENT |
             GatewayIntents::DIRECT_MESSAGES |
             GatewayIntents::GUILD_MEMBERS |
             GatewayIntents::GUILD_MESSAGES |
             GatewayIntents::GUILD_WEBHOOKS |
+            GatewayIntents::GUILD_VOICE_STATES |
             GatewayIntents::GUILDS
         )
         .event_handler(botmanager::BotManager
             {
-                api: api,
-                data: Mutex::new(manager_data)
+                api,
+                data: manager_data,
+                characters_dir: config.storage.characters_dir.to_owned(),
+                avatars_dir: config.storage.avatars_dir.to_owned(),
+                avatars_url: config.storage.avatars_url.to_owned(),
+                command_scope: config.discord.command_scope,
+                health_check_started: AtomicBool::new(false)
             }
-        )
-        .await.expect("Error creating client");
-
-    if let Err(error) = client.start().await {
-        panic!("Error starting client: {:?}", error);
-    }
-}
\ No newline at end of file
+        );
+    #[cfg(feature = "voice")]
+    let builder = songbird::SerenityInit::register_songbird_from_config(builder, uc207::voice::discord::songbird_config());
+    let mut client = builder.await.map_err(|why| failure("Error creating client", why))?;
+    client.start().await.map_err(|why| failure("Error starting client", why))
+}
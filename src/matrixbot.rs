@@ -0,0 +1,335 @@
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::botmanager::BotManagerData;
+use crate::conversation::Conversation;
+use crate::fences::{Fence, FENCE_MESSAGE};
+use crate::platform::matrix::{self, MatrixPlatform};
+use crate::platform::{ChatPlatform, Persona};
+use crate::swipes::SwipeAction;
+use crate::textgen::api::TextgenApi;
+
+/// The `matrix` section of the config
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct MatrixConfig {
+    pub homeserver_url: String,
+    pub access_token: String,
+    pub user_id: String,
+    #[serde(default = "default_command_prefix")]
+    pub command_prefix: String,
+    /// Room IDs (`!room:server`) or servers (`server`) of inviting users the bot accepts invites from, any if empty
+    #[serde(default)]
+    pub allowed_rooms: Vec<String>,
+}
+
+fn default_command_prefix() -> String {
+    String::from("!")
+}
+
+/// A command sent as a room message, e.g. `!invite alice`
+#[derive(Debug, PartialEq)]
+pub enum MatrixCommand {
+    List(String),
+    Invite(String),
+    Uninvite,
+    /// Adds a fence, for one character only if given
+    Fence(Option<String>),
+    /// Removes the fence on the given event, or the latest fence if empty
+    RemoveFence(String),
+    ListFences,
+    /// Asks the invited character for a picture
+    Imagine(String),
+    Help,
+}
+
+pub fn parse_command(body: &str, prefix: &str) -> Option<MatrixCommand> {
+    let command = body.strip_prefix(prefix)?;
+    let (name, argument) = match command.split_once(char::is_whitespace) {
+        Some((name, argument)) => (name, argument.trim()),
+        None => (command, "")
+    };
+    match name {
+        "list" => Some(MatrixCommand::List(String::from(argument))),
+        "invite" if !argument.is_empty() => Some(MatrixCommand::Invite(String::from(argument))),
+        "uninvite" => Some(MatrixCommand::Uninvite),
+        "fence" => match argument.split_once(char::is_whitespace).unwrap_or((argument, "")) {
+            ("", _) => Some(MatrixCommand::Fence(None)),
+            ("remove", event_id) => Some(MatrixCommand::RemoveFence(String::from(event_id.trim()))),
+            ("list", _) => Some(MatrixCommand::ListFences),
+            (character_id, _) => Some(MatrixCommand::Fence(Some(String::from(character_id))))
+        },
+        "imagine" => Some(MatrixCommand::Imagine(String::from(argument))),
+        "help" | "invite" => Some(MatrixCommand::Help),
+        _ => None
+    }
+}
+
+/// Whether an invite to `room` from `inviter` is allowed by the `allowed_rooms` setting
+pub fn is_allowed_room(allowed_rooms: &[String], room: &str, inviter: Option<&str>) -> bool {
+    let inviter_server = inviter.and_then(|inviter| inviter.split_once(':')).map(|(_, server)| server);
+    allowed_rooms.is_empty() || allowed_rooms.iter().any(|allowed| allowed == room || Some(allowed.as_str()) == inviter_server)
+}
+
+/// Matrix frontend: syncs with the homeserver, joins the allowed rooms it's invited to and answers like the Discord bot does
+pub struct MatrixBot {
+    pub platform: Arc<MatrixPlatform>,
+    pub api: Arc<TextgenApi>,
+    pub data: Arc<Mutex<BotManagerData>>,
+    pub command_prefix: String,
+    pub allowed_rooms: Vec<String>,
+}
+
+impl MatrixBot {
+    pub fn new(config: &MatrixConfig, api: Arc<TextgenApi>, data: Arc<Mutex<BotManagerData>>) -> MatrixBot {
+        MatrixBot {
+            platform: Arc::new(MatrixPlatform::new(&config.homeserver_url, &config.access_token, &config.user_id)),
+            api,
+            data,
+            command_prefix: config.command_prefix.to_owned(),
+            allowed_rooms: config.allowed_rooms.to_owned(),
+        }
+    }
+
+    pub async fn run(self: Arc<Self>) {
+        println!("{} is connecting to Matrix", self.platform.user_id);
+        let mut since: Option<String> = None;
+        loop {
+            let response = match self.sync(since.as_deref()).await {
+                Ok(response) => response,
+                Err(why) => {
+                    println!("Matrix sync failed: {}", why);
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    continue;
+                }
+            };
+
+            if let Some(rooms) = response["rooms"]["invite"].as_object() {
+                for (room, invite) in rooms {
+                    // The invite state has our own membership event, sent by whoever invited us
+                    let inviter = invite["invite_state"]["events"].as_array().into_iter().flatten()
+                        .find(|event| event["type"] == "m.room.member" && event["state_key"].as_str() == Some(self.platform.user_id.as_str()))
+                        .and_then(|event| event["sender"].as_str());
+                    if is_allowed_room(&self.allowed_rooms, room, inviter) {
+                        self.membership(room, "join").await;
+                    }
+                    else {
+                        println!("Rejecting invite to {} from {}", room, inviter.unwrap_or("unknown user"));
+                        self.membership(room, "leave").await;
+                    }
+                }
+            }
+
+            // The first sync only tells us where we are, don't answer old messages
+            if since.is_some() {
+                if let Some(rooms) = response["rooms"]["join"].as_object() {
+                    for (room, room_data) in rooms {
+                        for event in room_data["timeline"]["events"].as_array().into_iter().flatten() {
+                            self.handle_event(room, event).await;
+                        }
+                    }
+                }
+            }
+
+            since = response["next_batch"].as_str().map(String::from).or(since);
+        }
+    }
+
+    async fn sync(&self, since: Option<&str>) -> Result<Value, Box<dyn Error + Send + Sync>> {
+        let mut url = self.platform.url(&["sync"])?;
+        {
+            let mut query = url.query_pairs_mut();
+            query.append_pair("filter", &json!({ "room": { "timeline": { "limit": 50 } } }).to_string());
+            match since {
+                Some(since) => {query.append_pair("since", since).append_pair("timeout", "30000");},
+                None => {query.append_pair("timeout", "0");}
+            };
+        }
+        self.platform.call(Method::GET, url, None).await
+    }
+
+    /// Joins `room`, or rejects its invite with `leave`
+    async fn membership(&self, room: &str, action: &str) {
+        if action == "join" {
+            println!("Joining Matrix room {}", room);
+        }
+        let result = match self.platform.url(&["rooms", room, action]) {
+            Ok(url) => self.platform.call(Method::POST, url, Some(json!({}))).await,
+            Err(why) => Err(why)
+        };
+        if let Err(why) = result {
+            println!("Failed to {} {}: {}", action, room, why);
+        }
+    }
+
+    async fn handle_event(self: &Arc<Self>, room: &str, event: &Value) {
+        if event["sender"].as_str() == Some(self.platform.user_id.as_str()) {
+            return;
+        }
+
+        match event["type"].as_str() {
+            Some("m.room.message") => {
+                let body = event["content"]["body"].as_str().unwrap_or("");
+                if let Some(command) = parse_command(body, &self.command_prefix) {
+                    // Commands like `!imagine` take a while, don't hold up the sync loop
+                    let bot = self.clone();
+                    let room = String::from(room);
+                    tokio::spawn(async move { bot.run_command(&room, command).await });
+                    return;
+                }
+                let message = match matrix::parse_message(event, &event["content"], &self.platform.user_id) {
+                    Some(message) => message,
+                    None => return
+                };
+                let bot = self.clone();
+                let room = String::from(room);
+                tokio::spawn(async move {
+                    Conversation::new(&bot.api, &bot.data).on_message(bot.platform.as_ref(), &room, &message).await;
+                });
+            },
+            Some("m.reaction") => {
+                let relation = &event["content"]["m.relates_to"];
+                let action = match relation["key"].as_str() {
+                    Some("◀") | Some("◀️") => SwipeAction::Previous,
+                    Some("▶") | Some("▶️") => SwipeAction::Next,
+                    Some("🔄") => SwipeAction::Regenerate,
+                    _ => return
+                };
+                let message_id = String::from(relation["event_id"].as_str().unwrap_or(""));
+                let bot = self.clone();
+                let room = String::from(room);
+                tokio::spawn(async move {
+                    let result = Conversation::new(&bot.api, &bot.data).swipe(bot.platform.as_ref(), &room, &message_id, action).await
+                        .map_err(|err| err.to_string());
+                    if let Err(why) = result {
+                        println!("Failed swiping: {}", why);
+                    }
+                });
+            },
+            _ => {}
+        }
+    }
+
+    async fn run_command(&self, room: &str, command: MatrixCommand) {
+        let mut greeting = None;
+        // Character the fence being added applies to, the fence itself is the notice
+        let mut fence = None;
+        let reply = match command {
+            MatrixCommand::List(query) => {
+                // Matrix users aren't tied to a Discord account or server, so only global characters are listed
+                let data = self.data.lock().unwrap();
+                let characters = data.visible_characters(0, None, &query);
+                if characters.is_empty() {
+                    String::from("No bots found.")
+                }
+                else {
+                    characters.iter()
+                        .map(|(id, character)| format!("{} ({}) - {}", character.char_name, id, character.char_description))
+                        .collect::<Vec<String>>()
+                        .join("\n")
+                }
+            },
+            MatrixCommand::Invite(id) => {
+                let mut data = self.data.lock().unwrap();
+                match data.characters.get(&id).filter(|character| character.is_visible_to(0, None)) {
+                    Some(character) => {
+                        greeting = character.pick_greeting(None, "everyone").map(|content| (
+                            content,
+                            Persona { name: character.char_name.to_owned(), avatar_url: character.avatar_url.to_owned() }
+                        ));
+                        let reply = [&character.char_name, " will now respond in this room!"].join("");
+                        data.invited_characters.insert(String::from(room), id);
+                        reply
+                    },
+                    None => String::from("The selected bot ID doesn't exist!")
+                }
+            },
+            MatrixCommand::Uninvite => {
+                match self.data.lock().unwrap().invited_characters.remove(room) {
+                    Some(_) => String::from("Bot uninvited!"),
+                    None => String::from("There is no active bot in this room!")
+                }
+            },
+            MatrixCommand::Fence(character_id) => {
+                match &character_id {
+                    Some(id) => match self.data.lock().unwrap().characters.get(id).filter(|character| character.is_visible_to(0, None)) {
+                        Some(character) => {
+                            let reply = format!("{}\n(Only for {})", FENCE_MESSAGE, character.char_name);
+                            fence = Some(character_id);
+                            reply
+                        },
+                        None => String::from("The selected bot ID doesn't exist!")
+                    },
+                    None => {
+                        fence = Some(character_id);
+                        String::from(FENCE_MESSAGE)
+                    }
+                }
+            },
+            MatrixCommand::RemoveFence(event_id) => {
+                let event_id = Some(event_id.as_str()).filter(|id| !id.is_empty());
+                match self.data.lock().unwrap().fences.remove(room, event_id) {
+                    Ok(Some(_)) => String::from("Fence removed!"),
+                    Ok(None) => String::from("There is no such fence in this room!"),
+                    Err(why) => {
+                        println!("Failed saving fences: {}", why);
+                        String::from("Failed removing the fence!")
+                    }
+                }
+            },
+            MatrixCommand::ListFences => {
+                let data = self.data.lock().unwrap();
+                let fences = data.fences.list(room);
+                if fences.is_empty() {
+                    String::from("There are no fences in this room.")
+                }
+                else {
+                    fences.iter()
+                        .map(|fence| format!("{} - {}", fence.message_id, fence.character_id.as_deref().unwrap_or("every bot")))
+                        .collect::<Vec<String>>()
+                        .join("\n")
+                }
+            },
+            MatrixCommand::Imagine(prompt) => {
+                let prompt = if prompt.is_empty() {"selfie"} else {prompt.as_str()};
+                let conversation = Conversation::new(&self.api, &self.data);
+                let result = match conversation.persona(room) {
+                    Some(persona) => conversation.imagine(self.platform.as_ref(), room, &persona, prompt).await,
+                    None => Err(String::from("There is no active bot in this room"))
+                };
+                match result {
+                    // The picture is the answer
+                    Ok(_) => return,
+                    Err(why) => format!("Couldn't make the picture: {}", why)
+                }
+            },
+            MatrixCommand::Help => format!(
+                "Commands: {0}list [query], {0}invite <id>, {0}uninvite, {0}fence [id | remove [event id] | list], {0}imagine [prompt]. React to a reply with ◀ ▶ or 🔄 to swipe.",
+                self.command_prefix
+            )
+        };
+
+        match self.platform.send_notice(room, &reply).await {
+            Ok(event_id) if fence.is_some() => {
+                let character_id = fence.flatten();
+                let result = self.data.lock().unwrap().fences.add(room, Fence { message_id: event_id, character_id })
+                    .map_err(|why| why.to_string());
+                if let Err(why) = result {
+                    println!("Failed saving fence: {}", why);
+                }
+            },
+            Ok(_) => {},
+            Err(why) => println!("Failed answering command in {}: {}", room, why)
+        }
+        if let Some((content, persona)) = greeting {
+            if let Err(why) = self.platform.send_as_persona(room, &persona, &content, None).await {
+                println!("Error sending greeting: {}", why);
+            }
+        }
+    }
+}
@@ -0,0 +1,15 @@
+use serde::{Deserialize, Serialize};
+
+use crate::store::Store;
+
+/// Text put in a channel's history for every character, a few messages from its end, to steer the conversation
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AuthorsNote {
+    pub text: String,
+    /// Messages from the end of the history, `history.authors_note_depth` if `None`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub depth: Option<usize>,
+}
+
+/// Author's notes of every channel, by channel ID
+pub type AuthorsNotes = Store<AuthorsNote>;
@@ -0,0 +1,302 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use serde_json::Value;
+use serenity::async_trait;
+use serenity::builder::CreateComponents;
+use serenity::http::request::RequestBuilder;
+use serenity::http::routing::RouteInfo;
+use serenity::http::Typing;
+use serenity::model::prelude::component::ButtonStyle;
+use serenity::model::prelude::{AttachmentType, Channel, ChannelId, GuildId, Message, MessageId, ReactionType, UserId};
+use serenity::model::webhook::Webhook;
+use serenity::prelude::{Context, TypeMapKey};
+
+use super::{ChatPlatform, Persona, PlatformMessage, PlatformResult, SwipeControls};
+
+const WEBHOOK_NAME: &str = "Uc207_Bot";
+
+/// Name generated images are attached with
+const IMAGE_FILE_NAME: &str = "image.png";
+
+/// Most messages Discord returns for one history request
+const MAX_PAGE_SIZE: u64 = 100;
+
+/// Discord implementation, impersonating characters through a per-channel webhook
+pub struct DiscordPlatform {
+    context: Context,
+    typing: Mutex<HashMap<String, Typing>>,
+}
+
+impl DiscordPlatform {
+    pub fn new(context: &Context) -> DiscordPlatform {
+        DiscordPlatform {
+            context: context.clone(),
+            typing: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn get_webhook(&self, channel: &ChannelId) -> PlatformResult<Option<Webhook>> {
+        let webhooks = channel.webhooks(&self.context.http).await?;
+        Ok(webhooks.into_iter().find(|webhook| webhook.name.as_deref() == Some(WEBHOOK_NAME)))
+    }
+
+    pub async fn ensure_webhook(&self, channel: &ChannelId) -> PlatformResult<Webhook> {
+        match self.get_webhook(channel).await? {
+            Some(webhook) => Ok(webhook),
+            None => Ok(channel.create_webhook(&self.context.http, WEBHOOK_NAME).await?)
+        }
+    }
+
+    pub async fn delete_webhook(&self, channel: &ChannelId) -> PlatformResult<()> {
+        if let Some(webhook) = self.get_webhook(channel).await? {
+            webhook.delete(&self.context.http).await?;
+        }
+        Ok(())
+    }
+
+    /// Fetches a page of history, raw so the authors' display names it has can be remembered for `display_name`
+    async fn fetch_page(&self, channel: ChannelId, page_size: u64, before: Option<MessageId>) -> PlatformResult<Vec<Message>> {
+        let query = match before {
+            Some(before) => format!("?limit={}&before={}", page_size, before),
+            None => format!("?limit={}", page_size)
+        };
+        let request = RequestBuilder::new(RouteInfo::GetMessages { channel_id: channel.0, query }).build();
+        let page: Vec<Value> = self.context.http.request(request).await?.json().await?;
+        remember_display_names(&self.context, &page).await;
+        Ok(page.into_iter().map(serde_json::from_value).collect::<Result<Vec<Message>, _>>()?)
+    }
+}
+
+pub fn channel_id(channel: &str) -> PlatformResult<ChannelId> {
+    Ok(ChannelId(channel.parse()?))
+}
+
+fn message_id(message: &str) -> PlatformResult<MessageId> {
+    Ok(MessageId(message.parse()?))
+}
+
+/// Users' display names (`global_name`), which serenity doesn't know about, fetched once per user
+struct DisplayNames;
+
+impl TypeMapKey for DisplayNames {
+    type Value = HashMap<UserId, Option<String>>;
+}
+
+/// Turns a Discord message into a platform message, with the author's nickname in the message's server and the message
+/// it replies to. Display names need a request, see `display_name`.
+pub fn platform_message(context: &Context, message: &Message) -> PlatformMessage {
+    let guild = message.guild_id.or_else(|| guild_of(context, message.channel_id));
+    let mut converted = convert_message(context, message, guild);
+    converted.reply_to = message.referenced_message.as_ref()
+        .map(|referenced| Box::new(convert_message(context, referenced, guild)));
+    converted
+}
+
+fn convert_message(context: &Context, message: &Message, guild: Option<GuildId>) -> PlatformMessage {
+    let nickname = message.member.as_ref()
+        .and_then(|member| member.nick.to_owned())
+        .or_else(|| guild.and_then(|guild| context.cache.member(guild, message.author.id)).and_then(|member| member.nick));
+    PlatformMessage {
+        id: message.id.to_string(),
+        author_id: message.author.id.to_string(),
+        author_name: String::from(&message.author.name),
+        display_name: None,
+        nickname,
+        content: message.content_safe(&context.cache),
+        is_bot: message.author.bot,
+        is_own: message.is_own(&context.cache),
+        images: image_urls(message),
+        reply_to: None
+    }
+}
+
+/// Server of a channel the bot can see
+pub fn guild_of(context: &Context, channel: ChannelId) -> Option<GuildId> {
+    match channel.to_channel_cached(&context.cache) {
+        Some(Channel::Guild(channel)) => Some(channel.guild_id),
+        _ => None
+    }
+}
+
+/// Remembers the display names of the authors of raw messages, and of the messages they reply to
+async fn remember_display_names(context: &Context, messages: &[Value]) {
+    let mut data = context.data.write().await;
+    let names = data.entry::<DisplayNames>().or_default();
+    for author in messages.iter().flat_map(|message| [&message["author"], &message["referenced_message"]["author"]]) {
+        if let Some(user) = author["id"].as_str().and_then(|id| id.parse().ok()) {
+            names.insert(UserId(user), author["global_name"].as_str().map(String::from));
+        }
+    }
+}
+
+/// Display name of the author of a message, remembered once fetched or seen in a history page. `None` for bots, users without one, or if it couldn't be fetched.
+pub async fn display_name(context: &Context, message: &PlatformMessage) -> Option<String> {
+    let user = UserId(message.author_id.parse().ok().filter(|_| !message.is_bot)?);
+    if let Some(known) = context.data.read().await.get::<DisplayNames>().and_then(|names| names.get(&user)) {
+        return known.to_owned();
+    }
+    let request = RequestBuilder::new(RouteInfo::GetUser { user_id: user.0 }).build();
+    let response = context.http.request(request).await.map_err(|err| err.to_string());
+    let json = match response {
+        Ok(response) => response.json::<Value>().await.map_err(|err| err.to_string()),
+        Err(why) => Err(why)
+    };
+    match json {
+        Ok(json) => {
+            let name = json["global_name"].as_str().map(String::from);
+            context.data.write().await.entry::<DisplayNames>().or_default().insert(user, name.to_owned());
+            name
+        },
+        Err(why) => {
+            println!("Failed getting display name of {}: {}", user, why);
+            None
+        }
+    }
+}
+
+/// URLs of the images attached to a message
+pub fn image_urls(message: &Message) -> Vec<String> {
+    message.attachments.iter()
+        .filter(|attachment| attachment.content_type.as_deref().is_some_and(|content_type| content_type.starts_with("image/")))
+        .map(|attachment| attachment.url.to_owned())
+        .collect()
+}
+
+/// ◀ n/m ▶ 🔄 buttons, or no buttons at all
+pub fn swipe_components(controls: Option<SwipeControls>) -> CreateComponents {
+    let mut components = CreateComponents::default();
+    if let Some(controls) = controls {
+        components.create_action_row(|row| row
+            .create_button(|button| button
+                .custom_id("swipe:prev")
+                .label("◀")
+                .style(ButtonStyle::Secondary)
+                .disabled(controls.current == 0))
+            .create_button(|button| button
+                .custom_id("swipe:count")
+                .label(format!("{}/{}", controls.current + 1, controls.total))
+                .style(ButtonStyle::Secondary)
+                .disabled(true))
+            .create_button(|button| button
+                .custom_id("swipe:next")
+                .label("▶")
+                .style(ButtonStyle::Secondary)
+                .disabled(controls.current + 1 >= controls.total))
+            .create_button(|button| button
+                .custom_id("swipe:regenerate")
+                .label("🔄")
+                .style(ButtonStyle::Secondary))
+        );
+    }
+    components
+}
+
+#[async_trait]
+impl ChatPlatform for DiscordPlatform {
+    async fn fetch_history(&self, channel: &str, limit: u64, after: Option<&str>) -> PlatformResult<Vec<PlatformMessage>> {
+        let channel = channel_id(channel)?;
+        let after = after.map(message_id).transpose()?;
+        // Discord returns at most 100 messages per request, page back with `before` until the limit or the fence
+        let mut messages = Vec::new();
+        while (messages.len() as u64) < limit {
+            let page_size = (limit - messages.len() as u64).min(MAX_PAGE_SIZE);
+            let before = messages.last().map(|message: &Message| message.id);
+            let page = self.fetch_page(channel, page_size, before).await?;
+            let page_len = page.len() as u64;
+            let reached_fence = page.iter().any(|message| after.is_some_and(|after| message.id <= after));
+            messages.extend(page.into_iter().take_while(|message| after.is_none_or(|after| message.id > after)));
+            if reached_fence || page_len < page_size {
+                break;
+            }
+        }
+        let mut converted: Vec<PlatformMessage> = messages.iter()
+            .map(|discord_msg| platform_message(&self.context, discord_msg))
+            .collect();
+        for message in &mut converted {
+            message.display_name = display_name(&self.context, message).await;
+            if let Some(reply_to) = message.reply_to.as_deref_mut() {
+                reply_to.display_name = display_name(&self.context, reply_to).await;
+            }
+        }
+        Ok(converted)
+    }
+
+    async fn send_as_persona(&self, channel: &str, persona: &Persona, content: &str, controls: Option<SwipeControls>) -> PlatformResult<String> {
+        let webhook = self.ensure_webhook(&channel_id(channel)?).await?;
+        let message = webhook.execute(&self.context.http, true, |hook| hook
+            .content(content)
+            .username(&persona.name)
+            .avatar_url(&persona.avatar_url)
+            .set_components(swipe_components(controls))
+        ).await?;
+        Ok(message.map(|message| message.id.to_string()).unwrap_or_default())
+    }
+
+    async fn send_image(&self, channel: &str, persona: &Persona, content: &str, image: &[u8]) -> PlatformResult<String> {
+        let webhook = self.ensure_webhook(&channel_id(channel)?).await?;
+        let message = webhook.execute(&self.context.http, true, |hook| hook
+            .content(content)
+            .username(&persona.name)
+            .avatar_url(&persona.avatar_url)
+            .add_file(AttachmentType::Bytes { data: Cow::Borrowed(image), filename: String::from(IMAGE_FILE_NAME) })
+        ).await?;
+        Ok(message.map(|message| message.id.to_string()).unwrap_or_default())
+    }
+
+    async fn edit_message(&self, channel: &str, message: &str, content: &str, controls: Option<SwipeControls>) -> PlatformResult<()> {
+        let webhook = self.ensure_webhook(&channel_id(channel)?).await?;
+        webhook.edit_message(&self.context.http, message_id(message)?, |edit| edit
+            .content(content)
+            .components(|c| {*c = swipe_components(controls); c})
+        ).await?;
+        Ok(())
+    }
+
+    async fn delete_message(&self, channel: &str, message: &str) -> PlatformResult<()> {
+        channel_id(channel)?.delete_message(&self.context.http, message_id(message)?).await?;
+        Ok(())
+    }
+
+    async fn start_typing(&self, channel: &str) -> PlatformResult<()> {
+        let typing = channel_id(channel)?.start_typing(&self.context.http)?;
+        self.typing.lock().unwrap().insert(String::from(channel), typing);
+        Ok(())
+    }
+
+    async fn stop_typing(&self, channel: &str) -> PlatformResult<()> {
+        let typing = self.typing.lock().unwrap().remove(channel);
+        if let Some(typing) = typing {
+            typing.stop().ok_or("Typing was already stopped")?;
+        }
+        Ok(())
+    }
+
+    async fn react(&self, channel: &str, message: &str, emoji: char) -> PlatformResult<()> {
+        self.context.http.create_reaction(channel_id(channel)?.0, message_id(message)?.0, &ReactionType::Unicode(emoji.to_string())).await?;
+        Ok(())
+    }
+
+    async fn download(&self, url: &str) -> PlatformResult<Vec<u8>> {
+        // Attachment URLs are on Discord's CDN and don't need the bot token
+        Ok(reqwest::get(url).await?.error_for_status()?.bytes().await?.to_vec())
+    }
+
+    async fn play_audio(&self, channel: &str, audio: &[u8]) -> PlatformResult<()> {
+        #[cfg(feature = "voice")]
+        {
+            let guild = guild_of(&self.context, channel_id(channel)?).ok_or("Voice channels are only available in servers")?;
+            crate::voice::discord::play(&self.context, guild, audio).await
+        }
+        #[cfg(not(feature = "voice"))]
+        {
+            let _ = (channel, audio);
+            Err("Voice support isn't compiled in, build with the voice feature".into())
+        }
+    }
+
+    fn server(&self, channel: &str) -> Option<String> {
+        guild_of(&self.context, channel_id(channel).ok()?).map(|guild| guild.to_string())
+    }
+}
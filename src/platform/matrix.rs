@@ -0,0 +1,343 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use reqwest::{Client, Method, Url};
+use serde_json::{json, Value};
+use serenity::async_trait;
+
+use super::{ChatPlatform, Persona, PlatformMessage, PlatformResult, SwipeControls};
+
+/// MSC4144 per-message profile, which clients supporting it display instead of the sender's profile
+pub const PER_MESSAGE_PROFILE: &str = "com.beeper.per_message_profile";
+
+/// Matrix implementation using a regular bot account. Characters are impersonated with per-message profiles,
+/// with a `Name: ` prefix as fallback for clients that don't support them.
+pub struct MatrixPlatform {
+    client: Client,
+    homeserver_url: String,
+    access_token: String,
+    pub user_id: String,
+    transaction: AtomicU64,
+    /// mxc:// URIs of avatars already uploaded, by original URL
+    avatars: Mutex<HashMap<String, String>>,
+}
+
+impl MatrixPlatform {
+    pub fn new(homeserver_url: &str, access_token: &str, user_id: &str) -> MatrixPlatform {
+        MatrixPlatform {
+            client: Client::new(),
+            homeserver_url: String::from(homeserver_url.trim_end_matches('/')),
+            access_token: String::from(access_token),
+            user_id: String::from(user_id),
+            transaction: AtomicU64::new(0),
+            avatars: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Builds a client-server API URL, percent-encoding every path segment (room and event IDs contain `!`, `$` and `:`)
+    pub fn url(&self, segments: &[&str]) -> PlatformResult<Url> {
+        let mut url = Url::parse(&self.homeserver_url)?;
+        url.path_segments_mut()
+            .map_err(|_| "Homeserver URL can't be a base")?
+            .extend(["_matrix", "client", "v3"])
+            .extend(segments);
+        Ok(url)
+    }
+
+    pub async fn call(&self, method: Method, url: Url, body: Option<Value>) -> PlatformResult<Value> {
+        let mut request = self.client.request(method, url).bearer_auth(&self.access_token);
+        if let Some(body) = body {
+            request = request.json(&body);
+        }
+        let response = request.send().await?.error_for_status()?;
+        let text = response.text().await?;
+        if text.is_empty() {
+            return Ok(Value::Null);
+        }
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    fn next_transaction(&self) -> String {
+        format!("uc207-{}-{}", std::process::id(), self.transaction.fetch_add(1, Ordering::SeqCst))
+    }
+
+    async fn send_event(&self, room: &str, event_type: &str, content: Value) -> PlatformResult<String> {
+        let url = self.url(&["rooms", room, "send", event_type, &self.next_transaction()])?;
+        let response = self.call(Method::PUT, url, Some(content)).await?;
+        Ok(response["event_id"].as_str().unwrap_or_default().to_owned())
+    }
+
+    /// Sends a plain message from the bot account itself
+    pub async fn send_notice(&self, room: &str, content: &str) -> PlatformResult<String> {
+        self.send_event(room, "m.room.message", json!({ "msgtype": "m.notice", "body": content })).await
+    }
+
+    /// Uploads a file to the homeserver's media repository, returning its mxc:// URI
+    async fn upload(&self, content_type: &str, data: Vec<u8>) -> Result<Option<String>, reqwest::Error> {
+        let url = format!("{}/_matrix/media/v3/upload", self.homeserver_url);
+        let response: Value = self.client.post(url)
+            .bearer_auth(&self.access_token)
+            .header(reqwest::header::CONTENT_TYPE, content_type)
+            .body(data)
+            .send().await?
+            .error_for_status()?
+            .json().await?;
+        Ok(response["content_uri"].as_str().map(String::from))
+    }
+
+    /// Uploads an http(s) avatar to the homeserver's media repository, since Matrix profiles need mxc:// URIs
+    async fn avatar_mxc(&self, avatar_url: &str) -> Option<String> {
+        if avatar_url.is_empty() || avatar_url.starts_with("mxc://") {
+            return Some(String::from(avatar_url)).filter(|url| !url.is_empty());
+        }
+        if let Some(mxc) = self.avatars.lock().unwrap().get(avatar_url) {
+            return Some(mxc.to_owned());
+        }
+
+        let upload = async {
+            let image = self.client.get(avatar_url).send().await?.error_for_status()?;
+            let content_type = image.headers().get(reqwest::header::CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or("image/png")
+                .to_owned();
+            let bytes = image.bytes().await?;
+            self.upload(&content_type, bytes.to_vec()).await
+        };
+
+        match upload.await {
+            Ok(Some(mxc)) => {
+                self.avatars.lock().unwrap().insert(String::from(avatar_url), mxc.to_owned());
+                Some(mxc)
+            },
+            Ok(None) => None,
+            Err(why) => {
+                println!("Failed uploading avatar {}: {}", avatar_url, why);
+                None
+            }
+        }
+    }
+}
+
+/// Content of a message impersonating a character
+pub fn persona_content(persona: &Persona, avatar_mxc: Option<&str>, content: &str) -> Value {
+    let mut profile = json!({ "id": persona.name, "displayname": persona.name, "has_fallback": true });
+    if let Some(mxc) = avatar_mxc {
+        profile["avatar_url"] = Value::from(mxc);
+    }
+    json!({
+        "msgtype": "m.text",
+        "body": format!("{}: {}", persona.name, content),
+        PER_MESSAGE_PROFILE: profile
+    })
+}
+
+/// Turns a `/messages` chunk (newest first) into platform messages, applying edits and resolving per-message profiles
+pub fn parse_history(events: &[Value], own_user_id: &str) -> Vec<PlatformMessage> {
+    let mut replacements: HashMap<&str, &Value> = HashMap::new();
+    for event in events {
+        let relation = &event["content"]["m.relates_to"];
+        if relation["rel_type"] == "m.replace" {
+            if let Some(target) = relation["event_id"].as_str() {
+                // Newest edit wins, and events are newest first
+                replacements.entry(target).or_insert(&event["content"]["m.new_content"]);
+            }
+        }
+    }
+
+    let messages: Vec<PlatformMessage> = events.iter()
+        .filter(|event| event["type"] == "m.room.message")
+        .filter(|event| event["content"]["m.relates_to"]["rel_type"] != "m.replace")
+        .filter_map(|event| {
+            let id = event["event_id"].as_str()?;
+            let content = replacements.get(id).copied().unwrap_or(&event["content"]);
+            parse_message(event, content, own_user_id)
+        })
+        .collect();
+
+    // Replies are only linked to messages in the same chunk
+    let replies: HashMap<&str, &str> = events.iter()
+        .filter_map(|event| Some((event["event_id"].as_str()?, reply_target(&event["content"])?)))
+        .collect();
+    messages.iter()
+        .map(|message| {
+            let reply_to = replies.get(message.id.as_str())
+                .and_then(|target| messages.iter().find(|candidate| candidate.id == *target))
+                .map(|target| Box::new(target.clone()));
+            PlatformMessage { reply_to, ..message.clone() }
+        })
+        .collect()
+}
+
+/// Event a message replies to
+fn reply_target(content: &Value) -> Option<&str> {
+    content["m.relates_to"]["m.in_reply_to"]["event_id"].as_str()
+}
+
+/// Builds a platform message from a timeline event, using `content` instead of the event's own content
+pub fn parse_message(event: &Value, content: &Value, own_user_id: &str) -> Option<PlatformMessage> {
+    let sender = event["sender"].as_str()?;
+    let mut body = content["body"].as_str().unwrap_or("").to_owned();
+    if reply_target(&event["content"]).is_some() {
+        // Replies start with a quote of the message they reply to, for clients without reply support
+        body = body.lines()
+            .skip_while(|line| line.starts_with("> ") || *line == ">")
+            .skip_while(|line| line.is_empty())
+            .collect::<Vec<&str>>()
+            .join("\n");
+    }
+    let profile_name = event["content"][PER_MESSAGE_PROFILE]["displayname"].as_str();
+    let author_name = match profile_name {
+        Some(name) => {
+            if let Some(stripped) = body.strip_prefix(&format!("{}: ", name)) {
+                body = String::from(stripped);
+            }
+            String::from(name)
+        },
+        None => localpart(sender)
+    };
+    let is_own_account = sender == own_user_id;
+    // The body of an image is just its file name
+    let images: Vec<String> = match (content["msgtype"].as_str(), content["url"].as_str()) {
+        (Some("m.image"), Some(url)) => vec![String::from(url)],
+        _ => Vec::new()
+    };
+    if !images.is_empty() {
+        body.clear();
+    }
+
+    Some(PlatformMessage {
+        id: String::from(event["event_id"].as_str()?),
+        author_id: String::from(sender),
+        author_name,
+        display_name: None,
+        nickname: None,
+        content: body,
+        is_bot: is_own_account,
+        is_own: is_own_account && profile_name.is_none(),
+        images,
+        reply_to: None
+    })
+}
+
+/// `@alice:example.org` -> `alice`
+pub fn localpart(user_id: &str) -> String {
+    user_id.trim_start_matches('@').split(':').next().unwrap_or(user_id).to_owned()
+}
+
+#[async_trait]
+impl ChatPlatform for MatrixPlatform {
+    async fn fetch_history(&self, channel: &str, limit: u64, after: Option<&str>) -> PlatformResult<Vec<PlatformMessage>> {
+        // Page back from the latest event until there are enough messages or the fence is reached
+        let mut events: Vec<Value> = Vec::new();
+        let mut from: Option<String> = None;
+        // Messages the events will turn into, edits don't count
+        let mut message_count = 0;
+        loop {
+            let mut url = self.url(&["rooms", channel, "messages"])?;
+            {
+                // Edits are separate events, fetch extra so the visible messages still fill the limit
+                let mut query = url.query_pairs_mut();
+                query.append_pair("dir", "b").append_pair("limit", &(limit * 2).to_string());
+                if let Some(from) = &from {
+                    query.append_pair("from", from);
+                }
+            }
+            let response = self.call(Method::GET, url, None).await?;
+            let chunk = response["chunk"].as_array().cloned().unwrap_or_default();
+            let fence = chunk.iter().position(|event| after.is_some() && event["event_id"].as_str() == after);
+            let reached_fence = fence.is_some();
+            let chunk: Vec<Value> = chunk.into_iter().take(fence.unwrap_or(usize::MAX)).collect();
+            message_count += chunk.iter()
+                .filter(|event| event["type"] == "m.room.message" && event["content"]["m.relates_to"]["rel_type"] != "m.replace")
+                .count() as u64;
+            events.extend(chunk);
+            from = response["end"].as_str().map(String::from);
+            if reached_fence || from.is_none() || message_count >= limit {
+                break;
+            }
+        }
+        let mut messages = parse_history(&events, &self.user_id);
+        messages.truncate(limit as usize);
+        Ok(messages)
+    }
+
+    async fn send_as_persona(&self, channel: &str, persona: &Persona, content: &str, _controls: Option<SwipeControls>) -> PlatformResult<String> {
+        let avatar = self.avatar_mxc(&persona.avatar_url).await;
+        self.send_event(channel, "m.room.message", persona_content(persona, avatar.as_deref(), content)).await
+    }
+
+    async fn send_image(&self, channel: &str, persona: &Persona, content: &str, image: &[u8]) -> PlatformResult<String> {
+        let avatar = self.avatar_mxc(&persona.avatar_url).await;
+        if !content.is_empty() {
+            self.send_event(channel, "m.room.message", persona_content(persona, avatar.as_deref(), content)).await?;
+        }
+        let mxc = self.upload("image/png", image.to_vec()).await?.ok_or("Homeserver returned no content URI")?;
+        let mut event = persona_content(persona, avatar.as_deref(), "image.png");
+        event["msgtype"] = Value::from("m.image");
+        event["url"] = Value::from(mxc);
+        event["info"] = json!({ "mimetype": "image/png", "size": image.len() });
+        self.send_event(channel, "m.room.message", event).await
+    }
+
+    async fn edit_message(&self, channel: &str, message_id: &str, content: &str, _controls: Option<SwipeControls>) -> PlatformResult<()> {
+        // Keep the original persona, edits replace the whole content
+        let url = self.url(&["rooms", channel, "event", message_id])?;
+        let original = self.call(Method::GET, url, None).await?;
+        let name = original["content"][PER_MESSAGE_PROFILE]["displayname"].as_str().unwrap_or("").to_owned();
+        let mut new_content = original["content"].clone();
+        new_content["body"] = Value::from(if name.is_empty() {String::from(content)} else {format!("{}: {}", name, content)});
+
+        let mut edit = new_content.clone();
+        edit["body"] = Value::from(["* ", new_content["body"].as_str().unwrap_or("")].join(""));
+        edit["m.new_content"] = new_content;
+        edit["m.relates_to"] = json!({ "rel_type": "m.replace", "event_id": message_id });
+        self.send_event(channel, "m.room.message", edit).await?;
+        Ok(())
+    }
+
+    async fn delete_message(&self, channel: &str, message_id: &str) -> PlatformResult<()> {
+        let url = self.url(&["rooms", channel, "redact", message_id, &self.next_transaction()])?;
+        self.call(Method::PUT, url, Some(json!({}))).await?;
+        Ok(())
+    }
+
+    async fn download(&self, url: &str) -> PlatformResult<Vec<u8>> {
+        let (server, media_id) = url.strip_prefix("mxc://").and_then(|media| media.split_once('/')).ok_or("Not an mxc:// URI")?;
+        // Authenticated media endpoint, the unauthenticated one is disabled on recent homeservers
+        let mut download = Url::parse(&self.homeserver_url)?;
+        download.path_segments_mut()
+            .map_err(|_| "Homeserver URL can't be a base")?
+            .extend(["_matrix", "client", "v1", "media", "download", server, media_id]);
+        let response = self.client.get(download).bearer_auth(&self.access_token).send().await?.error_for_status()?;
+        Ok(response.bytes().await?.to_vec())
+    }
+
+    async fn play_audio(&self, _channel: &str, _audio: &[u8]) -> PlatformResult<()> {
+        Err("Matrix rooms have no voice channels".into())
+    }
+
+    fn server(&self, _channel: &str) -> Option<String> {
+        // Rooms aren't grouped into servers, personas are always the global ones
+        None
+    }
+
+    async fn start_typing(&self, channel: &str) -> PlatformResult<()> {
+        let url = self.url(&["rooms", channel, "typing", &self.user_id])?;
+        self.call(Method::PUT, url, Some(json!({ "typing": true, "timeout": 30000 }))).await?;
+        Ok(())
+    }
+
+    async fn stop_typing(&self, channel: &str) -> PlatformResult<()> {
+        let url = self.url(&["rooms", channel, "typing", &self.user_id])?;
+        self.call(Method::PUT, url, Some(json!({ "typing": false }))).await?;
+        Ok(())
+    }
+
+    async fn react(&self, channel: &str, message_id: &str, emoji: char) -> PlatformResult<()> {
+        self.send_event(channel, "m.reaction", json!({
+            "m.relates_to": { "rel_type": "m.annotation", "event_id": message_id, "key": emoji.to_string() }
+        })).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use serenity::async_trait;
+
+use super::{ChatPlatform, Persona, PlatformMessage, PlatformResult, SwipeControls};
+
+/// A message stored by `MemoryPlatform`, with how it was sent
+#[derive(Clone, Debug)]
+pub struct StoredMessage {
+    pub message: PlatformMessage,
+    pub persona: Option<Persona>,
+    pub controls: Option<SwipeControls>,
+    /// Image sent with `send_image`
+    pub image: Option<Vec<u8>>,
+}
+
+#[derive(Default)]
+struct MemoryState {
+    channels: HashMap<String, Vec<StoredMessage>>,
+    typing: HashMap<String, bool>,
+    typing_count: u32,
+    reactions: Vec<(String, char)>,
+    images: HashMap<String, Vec<u8>>,
+    audio: Vec<(String, Vec<u8>)>,
+    servers: HashMap<String, String>,
+    next_id: u64,
+}
+
+/// In-memory chat platform, so conversation logic can be exercised without Discord
+#[derive(Default)]
+pub struct MemoryPlatform {
+    state: Mutex<MemoryState>,
+}
+
+impl MemoryPlatform {
+    pub fn new() -> MemoryPlatform {
+        MemoryPlatform::default()
+    }
+
+    /// Posts a message from a human user and returns it
+    pub fn post_user(&self, channel: &str, author: &str, content: &str) -> PlatformMessage {
+        self.post(channel, author, content, false, false, None, None)
+    }
+
+    /// Posts a message from a human user with images attached, and makes them downloadable
+    pub fn post_user_with_images(&self, channel: &str, author: &str, content: &str, images: Vec<(&str, Vec<u8>)>) -> PlatformMessage {
+        let mut state = self.state.lock().unwrap();
+        for (url, data) in &images {
+            state.images.insert(String::from(*url), data.to_owned());
+        }
+        drop(state);
+        let mut message = self.post(channel, author, content, false, false, None, None);
+        message.images = images.iter().map(|(url, _)| String::from(*url)).collect();
+        self.find(channel, &message.id, |messages, index| messages[index].message.images = message.images.clone())
+            .expect("Message was just posted");
+        message
+    }
+
+    /// Posts a message from a human user as is, giving it the next ID, and returns it.
+    /// Its `reply_to` is looked up in the channel by ID.
+    pub fn post_message(&self, channel: &str, mut message: PlatformMessage) -> PlatformMessage {
+        let reply_to = message.reply_to.take()
+            .and_then(|reply_to| self.messages(channel).into_iter().find(|stored| stored.message.id == reply_to.id))
+            .map(|stored| Box::new(PlatformMessage { reply_to: None, ..stored.message }));
+        let posted = self.post(channel, &message.author_name, &message.content, message.is_bot, message.is_own, None, None);
+        message.id = posted.id;
+        message.reply_to = reply_to;
+        self.find(channel, &message.id, |messages, index| messages[index].message = message.clone())
+            .expect("Message was just posted");
+        message
+    }
+
+    /// Posts a message from the bot's own account, like slash command responses
+    pub fn post_own(&self, channel: &str, content: &str) -> PlatformMessage {
+        self.post(channel, "Uc207", content, true, true, None, None)
+    }
+
+    /// Posts a message from another bot
+    pub fn post_bot(&self, channel: &str, author: &str, content: &str) -> PlatformMessage {
+        self.post(channel, author, content, true, false, None, None)
+    }
+
+    /// Puts the channel in a server, channels aren't in any by default
+    pub fn set_server(&self, channel: &str, server: &str) {
+        self.state.lock().unwrap().servers.insert(String::from(channel), String::from(server));
+    }
+
+    /// Every message in the channel, oldest first
+    pub fn messages(&self, channel: &str) -> Vec<StoredMessage> {
+        self.state.lock().unwrap().channels.get(channel).cloned().unwrap_or_default()
+    }
+
+    pub fn is_typing(&self, channel: &str) -> bool {
+        self.state.lock().unwrap().typing.get(channel).copied().unwrap_or(false)
+    }
+
+    /// How many times typing was started, in any channel
+    pub fn typing_count(&self) -> u32 {
+        self.state.lock().unwrap().typing_count
+    }
+
+    /// Reactions added so far, as message ID and emoji
+    pub fn reactions(&self) -> Vec<(String, char)> {
+        self.state.lock().unwrap().reactions.clone()
+    }
+
+    /// Audio played so far, as channel and WAV file
+    pub fn played_audio(&self) -> Vec<(String, Vec<u8>)> {
+        self.state.lock().unwrap().audio.clone()
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn post(&self, channel: &str, author: &str, content: &str, is_bot: bool, is_own: bool, persona: Option<Persona>, controls: Option<SwipeControls>) -> PlatformMessage {
+        let mut state = self.state.lock().unwrap();
+        state.next_id += 1;
+        let message = PlatformMessage {
+            id: state.next_id.to_string(),
+            author_id: String::from(author),
+            author_name: String::from(author),
+            display_name: None,
+            nickname: None,
+            content: String::from(content),
+            is_bot,
+            is_own,
+            images: Vec::new(),
+            reply_to: None
+        };
+        state.channels.entry(String::from(channel)).or_default().push(StoredMessage {
+            message: message.clone(),
+            persona,
+            controls,
+            image: None
+        });
+        message
+    }
+
+    fn find<T>(&self, channel: &str, message_id: &str, f: impl FnOnce(&mut Vec<StoredMessage>, usize) -> T) -> PlatformResult<T> {
+        let mut state = self.state.lock().unwrap();
+        let messages = state.channels.get_mut(channel).ok_or("Unknown channel")?;
+        let index = messages.iter().position(|stored| stored.message.id == message_id).ok_or("Unknown message")?;
+        Ok(f(messages, index))
+    }
+}
+
+#[async_trait]
+impl ChatPlatform for MemoryPlatform {
+    async fn fetch_history(&self, channel: &str, limit: u64, after: Option<&str>) -> PlatformResult<Vec<PlatformMessage>> {
+        // IDs grow like Discord's, so the history still ends at the right place if the `after` message was deleted
+        let after = after.map(str::parse::<u64>).transpose()?;
+        Ok(self.messages(channel).into_iter()
+            .rev()
+            .take_while(|stored| after.is_none_or(|after| stored.message.id.parse().is_ok_and(|id: u64| id > after)))
+            .take(limit as usize)
+            .map(|stored| stored.message)
+            .collect())
+    }
+
+    async fn send_as_persona(&self, channel: &str, persona: &Persona, content: &str, controls: Option<SwipeControls>) -> PlatformResult<String> {
+        Ok(self.post(channel, &persona.name, content, true, false, Some(persona.clone()), controls).id)
+    }
+
+    async fn send_image(&self, channel: &str, persona: &Persona, content: &str, image: &[u8]) -> PlatformResult<String> {
+        let message = self.post(channel, &persona.name, content, true, false, Some(persona.clone()), None);
+        self.find(channel, &message.id, |messages, index| messages[index].image = Some(image.to_vec()))?;
+        Ok(message.id)
+    }
+
+    async fn edit_message(&self, channel: &str, message_id: &str, content: &str, controls: Option<SwipeControls>) -> PlatformResult<()> {
+        self.find(channel, message_id, |messages, index| {
+            messages[index].message.content = String::from(content);
+            messages[index].controls = controls;
+        })
+    }
+
+    async fn delete_message(&self, channel: &str, message_id: &str) -> PlatformResult<()> {
+        self.find(channel, message_id, |messages, index| {
+            messages.remove(index);
+        })
+    }
+
+    async fn start_typing(&self, channel: &str) -> PlatformResult<()> {
+        let mut state = self.state.lock().unwrap();
+        state.typing.insert(String::from(channel), true);
+        state.typing_count += 1;
+        Ok(())
+    }
+
+    async fn stop_typing(&self, channel: &str) -> PlatformResult<()> {
+        self.state.lock().unwrap().typing.insert(String::from(channel), false);
+        Ok(())
+    }
+
+    async fn react(&self, _channel: &str, message_id: &str, emoji: char) -> PlatformResult<()> {
+        self.state.lock().unwrap().reactions.push((String::from(message_id), emoji));
+        Ok(())
+    }
+
+    async fn download(&self, url: &str) -> PlatformResult<Vec<u8>> {
+        Ok(self.state.lock().unwrap().images.get(url).cloned().ok_or("Unknown image")?)
+    }
+
+    async fn play_audio(&self, channel: &str, audio: &[u8]) -> PlatformResult<()> {
+        self.state.lock().unwrap().audio.push((String::from(channel), audio.to_vec()));
+        Ok(())
+    }
+
+    fn server(&self, channel: &str) -> Option<String> {
+        self.state.lock().unwrap().servers.get(channel).cloned()
+    }
+}
@@ -0,0 +1,79 @@
+pub mod discord;
+pub mod matrix;
+pub mod memory;
+
+use std::error::Error;
+
+use serenity::async_trait;
+
+pub type PlatformResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+/// A chat message as seen by the conversation logic, independent of the chat platform
+#[derive(Clone, Debug)]
+pub struct PlatformMessage {
+    pub id: String,
+    pub author_id: String,
+    /// Account name, unique on the platform
+    pub author_name: String,
+    /// Name shown instead of the account name everywhere, if the user picked one
+    pub display_name: Option<String>,
+    /// Name shown in this server or room only
+    pub nickname: Option<String>,
+    pub content: String,
+    /// Sent by a bot or webhook, including our own characters
+    pub is_bot: bool,
+    /// Sent by this bot's own account (not its characters)
+    pub is_own: bool,
+    /// Image attachments, as URLs `ChatPlatform::download` understands
+    pub images: Vec<String>,
+    /// Message this one replies to, without its own `reply_to`
+    pub reply_to: Option<Box<PlatformMessage>>,
+}
+
+/// Name and avatar a character speaks with
+#[derive(Clone, Debug, PartialEq)]
+pub struct Persona {
+    pub name: String,
+    pub avatar_url: String,
+}
+
+/// Which swipe is shown on a reply, for platforms that can display swipe buttons
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SwipeControls {
+    pub current: usize,
+    pub total: usize,
+}
+
+/// Everything the conversation logic needs from a chat platform. Channels and messages are identified by strings
+/// so that platforms with non-numeric IDs fit in.
+#[async_trait]
+pub trait ChatPlatform: Send + Sync {
+    /// Most recent messages in the channel, newest first. With `after`, only the messages sent after that message.
+    async fn fetch_history(&self, channel: &str, limit: u64, after: Option<&str>) -> PlatformResult<Vec<PlatformMessage>>;
+
+    /// Sends a message impersonating a character, returning the new message's ID
+    async fn send_as_persona(&self, channel: &str, persona: &Persona, content: &str, controls: Option<SwipeControls>) -> PlatformResult<String>;
+
+    /// Sends a PNG image as a character, returning the new message's ID
+    async fn send_image(&self, channel: &str, persona: &Persona, content: &str, image: &[u8]) -> PlatformResult<String>;
+
+    /// Replaces the content of a message previously sent with `send_as_persona`
+    async fn edit_message(&self, channel: &str, message_id: &str, content: &str, controls: Option<SwipeControls>) -> PlatformResult<()>;
+
+    async fn delete_message(&self, channel: &str, message_id: &str) -> PlatformResult<()>;
+
+    async fn start_typing(&self, channel: &str) -> PlatformResult<()>;
+
+    async fn stop_typing(&self, channel: &str) -> PlatformResult<()>;
+
+    async fn react(&self, channel: &str, message_id: &str, emoji: char) -> PlatformResult<()>;
+
+    /// Downloads an image attached to a message
+    async fn download(&self, url: &str) -> PlatformResult<Vec<u8>>;
+
+    /// Queues a WAV file in the voice channel the bot joined for this channel
+    async fn play_audio(&self, channel: &str, audio: &[u8]) -> PlatformResult<()>;
+
+    /// Server the channel belongs to, `None` for direct messages and platforms without servers
+    fn server(&self, channel: &str) -> Option<String>;
+}
@@ -0,0 +1,183 @@
+use std::error::Error;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::sync::Mutex;
+
+use serenity::model::Timestamp;
+
+use crate::botmanager::BotManagerData;
+use crate::conversation::Conversation;
+use crate::fences::{Fence, FENCE_MESSAGE};
+use crate::platform::memory::MemoryPlatform;
+use crate::platform::{ChatPlatform, Persona};
+use crate::swipes::SwipeAction;
+use crate::textgen::api::TextgenApi;
+use crate::transcript::{self, ExportFormat};
+
+const CHANNEL: &str = "terminal";
+
+const HELP: &str = "Commands:
+  /fence            Hide everything above from the character
+  /regenerate       Generate another version of the last reply
+  /prev, /next      Switch between versions of the last reply
+  /prompt           Print the exact prompt the next reply would use
+  /imagine [prompt] Ask for a picture, saved in the current directory
+  /save <file>      Save the transcript, as JSONL, SillyTavern JSONL or HTML
+                    for .jsonl, -sillytavern.jsonl or .html files
+  /import <file>    Continue a saved chat
+  /help             Show this help
+  /quit             Exit";
+
+/// Chats with a character in the terminal, through the same conversation logic as the chat platforms
+pub async fn chat(api: &TextgenApi, data: BotManagerData, character_id: &str) -> Result<(), Box<dyn Error>> {
+    let data = Mutex::new(data);
+    let persona = {
+        let mut data = data.lock().unwrap();
+        let character = data.characters.get(character_id)
+            .ok_or_else(|| string_error::into_err(format!("Unknown character ID: {}", character_id)))?;
+        let persona = Persona { name: character.char_name.to_owned(), avatar_url: character.avatar_url.to_owned() };
+        let greeting = character.pick_greeting(None, &user_name());
+        data.invited_characters.insert(String::from(CHANNEL), String::from(character_id));
+        (persona, greeting)
+    };
+    let (persona, greeting) = persona;
+
+    let platform = MemoryPlatform::new();
+    let conversation = Conversation::new(api, &data);
+    println!("Chatting with {}. Type /help for commands.", persona.name);
+    if let Some(greeting) = greeting {
+        platform.send_as_persona(CHANNEL, &persona, &greeting, None).await.map_err(|why| why.to_string())?;
+        print_reply(&platform);
+    }
+
+    let stdin = io::stdin();
+    loop {
+        print!("> ");
+        io::stdout().flush()?;
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        let line = line.trim();
+
+        match line.split_once(' ').map(|(command, argument)| (command, argument.trim())).unwrap_or((line, "")) {
+            ("", _) => {},
+            ("/quit", _) | ("/exit", _) => return Ok(()),
+            ("/help", _) => println!("{}", HELP),
+            ("/fence", _) => {
+                let message = platform.post_own(CHANNEL, FENCE_MESSAGE);
+                data.lock().unwrap().fences.add(CHANNEL, Fence { message_id: message.id, character_id: None })?;
+                println!("{}", FENCE_MESSAGE);
+            },
+            ("/prompt", _) => match conversation.prompt_for(&platform, CHANNEL).await {
+                Some((prompt, _)) => println!("{}", prompt),
+                None => println!("Couldn't build the prompt")
+            },
+            ("/imagine", prompt) => {
+                let sent = platform.messages(CHANNEL).len();
+                let prompt = if prompt.is_empty() {"selfie"} else {prompt};
+                match conversation.imagine(&platform, CHANNEL, &persona, prompt).await {
+                    Ok(_) => save_pictures(&platform, sent),
+                    Err(why) => println!("Couldn't make the picture: {}", why)
+                }
+            },
+            ("/regenerate", _) => swipe(&conversation, &platform, SwipeAction::Regenerate).await,
+            ("/prev", _) => swipe(&conversation, &platform, SwipeAction::Previous).await,
+            ("/next", _) => swipe(&conversation, &platform, SwipeAction::Next).await,
+            ("/save", path) => {
+                if path.is_empty() {
+                    println!("Usage: /save <file>");
+                    continue;
+                }
+                let messages = match conversation.transcript(&platform, CHANNEL, None, None).await.map_err(|why| why.to_string()) {
+                    Ok(messages) => messages,
+                    Err(why) => {
+                        println!("Failed getting transcript: {}", why);
+                        continue;
+                    }
+                };
+                let date = Timestamp::now().to_string();
+                match fs::write(path, transcript::export(ExportFormat::of_file(path), &messages, &persona.name, &date)) {
+                    Ok(()) => println!("Transcript saved to {}", path),
+                    Err(why) => println!("Failed saving transcript: {}", why)
+                }
+            },
+            ("/import", path) => {
+                if path.is_empty() {
+                    println!("Usage: /import <file>");
+                    continue;
+                }
+                match fs::read_to_string(path).map_err(|why| why.to_string()).and_then(|contents| transcript::import(&contents).map_err(|why| why.to_string())) {
+                    Ok(messages) => {
+                        let count = messages.len();
+                        match conversation.import(CHANNEL, messages) {
+                            Ok(()) => println!("Imported {} messages", count),
+                            Err(why) => println!("Failed saving imported messages: {}", why)
+                        }
+                    },
+                    Err(why) => println!("Failed importing transcript: {}", why)
+                }
+            },
+            (command, _) if command.starts_with('/') => println!("Unknown command {}, type /help for commands", command),
+            _ => {
+                let message = platform.post_user(CHANNEL, &user_name(), line);
+                let sent = platform.messages(CHANNEL).len();
+                let failures = platform.reactions().len();
+                conversation.on_message(&platform, CHANNEL, &message).await;
+                if platform.reactions().len() == failures {
+                    print_reply(&platform);
+                    save_pictures(&platform, sent);
+                }
+                else {
+                    println!("(no reply, the textgen backend is unavailable)");
+                }
+            }
+        }
+    }
+}
+
+async fn swipe(conversation: &Conversation<'_>, platform: &MemoryPlatform, action: SwipeAction) {
+    let reply_id = match platform.messages(CHANNEL).iter().rev().find(|stored| stored.controls.is_some()) {
+        Some(stored) => stored.message.id.to_owned(),
+        None => {
+            println!("There's no reply to change yet");
+            return;
+        }
+    };
+    let sent = platform.messages(CHANNEL).len();
+    let result = conversation.swipe(platform, CHANNEL, &reply_id, action).await.map_err(|why| why.to_string());
+    match result {
+        Ok(()) => {
+            print_reply(platform);
+            save_pictures(platform, sent);
+        },
+        Err(why) => println!("Failed swiping: {}", why)
+    }
+}
+
+/// Prints the character's latest message with its swipe position
+fn print_reply(platform: &MemoryPlatform) {
+    if let Some(reply) = platform.messages(CHANNEL).iter().rev().find(|stored| stored.persona.is_some() && stored.image.is_none()) {
+        let position = reply.controls
+            .map(|controls| format!(" [{}/{}]", controls.current + 1, controls.total))
+            .unwrap_or_default();
+        println!("{}{}: {}", reply.message.author_name, position, reply.message.content.trim());
+    }
+}
+
+/// Writes the pictures sent since the first `sent` messages to files, since the terminal can't show them
+fn save_pictures(platform: &MemoryPlatform, sent: usize) {
+    for stored in platform.messages(CHANNEL).iter().skip(sent) {
+        if let Some(image) = &stored.image {
+            let path = format!("picture-{}.png", stored.message.id);
+            match fs::write(&path, image) {
+                Ok(()) => println!("{} sent a picture, saved to {}", stored.message.author_name, path),
+                Err(why) => println!("Failed saving picture: {}", why)
+            }
+        }
+    }
+}
+
+fn user_name() -> String {
+    std::env::var("USER").unwrap_or_else(|_| String::from("You"))
+}
@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use serde::{Serialize, de::DeserializeOwned};
+
+/// Values by key (channel, user...), saved as a JSON object to a file on every change.
+/// Without a file, as by default, changes are only kept in memory.
+pub struct Store<V> {
+    path: Option<String>,
+    entries: HashMap<String, V>,
+}
+
+impl<V> Default for Store<V> {
+    fn default() -> Self {
+        Store { path: None, entries: HashMap::new() }
+    }
+}
+
+impl<V: Serialize + DeserializeOwned> Store<V> {
+    /// Loads the values saved at `path`, or starts without any if the file doesn't exist yet
+    pub fn load(path: &str) -> Result<Store<V>, Box<dyn Error>> {
+        let entries = match Path::new(path).exists() {
+            true => serde_json::from_str(&fs::read_to_string(path)?)?,
+            false => HashMap::new()
+        };
+        Ok(Store { path: Some(String::from(path)), entries })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&V> {
+        self.entries.get(key)
+    }
+
+    pub fn set(&mut self, key: &str, value: V) -> Result<(), Box<dyn Error>> {
+        self.entries.insert(String::from(key), value);
+        self.save()
+    }
+
+    pub fn remove(&mut self, key: &str) -> Result<Option<V>, Box<dyn Error>> {
+        let removed = self.entries.remove(key);
+        if removed.is_some() {
+            self.save()?;
+        }
+        Ok(removed)
+    }
+
+    /// Changes the value of `key`, `None` if it has none, and saves it. Leaving `None` removes it.
+    pub fn update<R>(&mut self, key: &str, change: impl FnOnce(&mut Option<V>) -> R) -> Result<R, Box<dyn Error>> {
+        let mut value = self.entries.remove(key);
+        let result = change(&mut value);
+        if let Some(value) = value {
+            self.entries.insert(String::from(key), value);
+        }
+        self.save()?;
+        Ok(result)
+    }
+
+    fn save(&self) -> Result<(), Box<dyn Error>> {
+        if let Some(path) = &self.path {
+            fs::write(path, serde_json::to_string_pretty(&self.entries)?)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,70 @@
+use serenity::model::prelude::interaction::InteractionResponseType;
+use serenity::model::prelude::interaction::message_component::MessageComponentInteraction;
+use serenity::prelude::Context;
+
+use crate::botmanager::BotManager;
+use crate::conversation::Conversation;
+use crate::platform::SwipeControls;
+use crate::platform::discord::DiscordPlatform;
+use crate::textgen::format::Prompt;
+use crate::textgen::vision::Image;
+
+/// Alternative replies for the latest character message in a channel. Only the shown candidate is
+/// in the chat message, so it's the only one that ends up in the history.
+pub struct SwipeState {
+    pub message_id: String,
+    pub prompt: Prompt,
+    /// Images the prompt refers to, sent again when regenerating
+    pub images: Vec<Image>,
+    pub candidates: Vec<String>,
+    pub current: usize
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SwipeAction {
+    Previous,
+    Next,
+    Regenerate
+}
+
+impl SwipeState {
+    pub fn controls(&self) -> SwipeControls {
+        SwipeControls { current: self.current, total: self.candidates.len() }
+    }
+
+    /// The candidate currently displayed
+    pub fn shown(&self) -> &str {
+        &self.candidates[self.current]
+    }
+}
+
+/// Handles the ◀ ▶ and 🔄 buttons on a character reply
+pub async fn run_button(ctx: &Context, component: &MessageComponentInteraction, manager: &BotManager) {
+    if let Err(why) = component.create_interaction_response(&ctx.http, |response| {
+        response.kind(InteractionResponseType::DeferredUpdateMessage)
+    }).await {
+        println!("Cannot respond to button: {}", why);
+        return;
+    }
+
+    let action = match component.data.custom_id.as_str() {
+        "swipe:prev" => SwipeAction::Previous,
+        "swipe:next" => SwipeAction::Next,
+        "swipe:regenerate" => SwipeAction::Regenerate,
+        _ => return
+    };
+
+    let platform = DiscordPlatform::new(ctx);
+    let conversation = Conversation::new(&manager.api, &manager.data);
+    let result = conversation.swipe(&platform, &component.channel_id.to_string(), &component.message.id.to_string(), action).await
+        .map_err(|err| err.to_string());
+    if let Err(err) = result {
+        println!("Failed swiping: {}", err);
+        if let Err(why) = component.create_followup_message(&ctx.http, |message| message
+            .ephemeral(true)
+            .content("The text generation backend is unavailable, try again later!")
+        ).await {
+            println!("Cannot send followup message: {}", why);
+        }
+    }
+}
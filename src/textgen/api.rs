@@ -1,99 +1,421 @@
 use reqwest::Client;
 use serde::{Serialize, Deserialize};
 use serde_json::{Value, json};
-use std::{fs, error::Error, time::Duration};
+use std::{collections::HashMap, fs, error::Error, sync::Mutex, time::Duration};
 
+use crate::config::{AssistantSpeakers, Config, FormatConfig, HistoryConfig, LimitsConfig, PromptFormat, SamplingConfig, VisionConfig};
+use crate::notes::AuthorsNote;
+use crate::voice::stt::Transcriber;
+use crate::voice::tts::SpeechSynthesizer;
+use super::backend::{self, CircuitBreakerPolicy, Endpoint, EndpointApi, EndpointState, RetryPolicy};
 use super::character::Character;
+use super::format::{self, ChatMessage, Prompt, Role};
+use super::imagegen::ImageGenerator;
+use super::vision::{self, Image};
 
-#[derive(Serialize, Deserialize)]
+pub const BACKEND_UNAVAILABLE: &str = "No textgen backend available";
+
+/// Placeholders replaced in the prompt template. `{{char}}` and `{{user}}` are the macros of greetings and character cards.
+pub const PLACEHOLDERS: [&str; 12] = [
+    "[[NAME]]",
+    "[[PERSONA]]",
+    "[[EXAMPLE]]",
+    "[[CONTEXT]]",
+    "[[DESCRIPTION]]",
+    "[[SCENARIO]]",
+    "[[TAGS]]",
+    "[[USERS]]",
+    "[[USER]]",
+    "[[USER_PERSONA]]",
+    "{{char}}",
+    "{{user}}"
+];
+
+/// Placeholders a template can't work without: the chat history and who's replying
+pub const REQUIRED_PLACEHOLDERS: [&str; 2] = ["[[CONTEXT]]", "[[NAME]]"];
+
+/// Client for the textgen backends, and the image generation, text-to-speech and speech-to-text ones if configured. Settings come from
+/// the `backend`, `sampling`, `limits`, `history`, `format`, `logging`, `vision`, `image_generation` and `voice` config sections.
 pub struct TextgenApi {
-    #[serde(skip)]
     client: Client,
-    textgen_url: String,
-    model_url: String,
-    temperature: f32,
-    top_p: f32,
-    typical_p: f32,
-    repetition_penalty: f32,
-    encoder_repetition_penalty: f32,
-    top_k: f32,
-    min_length: i32,
-    no_repeat_ngram_size: i32,
-    num_beams: i32,
-    penalty_alpha: f32,
-    length_penalty: f32,
+    backends: Vec<EndpointState>,
+    retry: RetryPolicy,
+    circuit_breaker: CircuitBreakerPolicy,
+    prompt_template: String,
+    sampling: SamplingConfig,
+    limits: LimitsConfig,
+    history: HistoryConfig,
+    format: FormatConfig,
+    log_requests: bool,
+    vision: VisionConfig,
+    /// The multimodal endpoint and its circuit breaker, if there's one
+    multimodal: Option<EndpointState>,
+    /// Captions of images already captioned, by URL without its query string
+    captions: Mutex<HashMap<String, String>>,
+    image_generator: Option<ImageGenerator>,
+    speech: Option<SpeechSynthesizer>,
+    transcriber: Option<Transcriber>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct Message {
     pub speaker: String,
     pub content: String
 }
 
+/// What goes into the prompt besides the character and the history
+#[derive(Clone, Debug, Default)]
+pub struct PromptExtras {
+    /// Users in the history and what they told about themselves, for `[[USERS]]`
+    pub users: Vec<(String, String)>,
+    /// Who the character is replying to, for `[[USER]]`
+    pub user: String,
+    /// Description of their persona, for `[[USER_PERSONA]]`
+    pub user_persona: String,
+    /// Names of the bots and characters in the history, the assistant's turns with `format.assistant = "bots"`
+    pub bots: Vec<String>,
+    /// The channel's author's note, put in the history at its depth
+    pub authors_note: Option<AuthorsNote>,
+}
+
 impl TextgenApi{
-    pub fn init(config_path: &str) -> Result<TextgenApi, Box<dyn Error>> {
-        let json = fs::read_to_string(config_path)?;
-        let mut textgen_api: TextgenApi = serde_json::from_str(&json)?;
-        textgen_api.client = reqwest::Client::new();
-        
-        return Ok(textgen_api);
-    }
-
-    pub fn make_prompt(&self, character: &Character, history: &Vec<Message>) -> Result<String, Box<dyn Error>> {
-        let patterns = &[
-            "[[NAME]]",
-            "[[PERSONA]]",
-            "[[EXAMPLE]]",
-            "[[CONTEXT]]"
-        ];
+    pub fn new(config: &Config) -> Result<TextgenApi, Box<dyn Error>> {
+        let backend = &config.backend;
+        let mut endpoints = backend.endpoints.clone();
+        if let (Some(textgen_url), Some(model_url)) = (&backend.textgen_url, &backend.model_url) {
+            endpoints.push(Endpoint {
+                textgen_url: textgen_url.to_owned(),
+                model_url: model_url.to_owned(),
+                priority: 0,
+                api: EndpointApi::Textgen,
+                model: String::new()
+            });
+        }
+        if endpoints.is_empty() {
+            return Err(string_error::new_err("No textgen endpoints configured"));
+        }
+
+        Ok(TextgenApi {
+            client: Client::new(),
+            backends: backend::sort_endpoints(endpoints),
+            retry: backend.retry.clone(),
+            circuit_breaker: backend.circuit_breaker.clone(),
+            prompt_template: backend.prompt_template.to_owned(),
+            sampling: config.sampling.clone(),
+            limits: config.limits.clone(),
+            history: config.history.clone(),
+            format: config.format.clone(),
+            log_requests: config.logging.log_requests,
+            vision: config.vision.clone(),
+            multimodal: config.vision.multimodal_url.as_ref().map(|url| EndpointState::new(Endpoint {
+                textgen_url: url.to_owned(),
+                model_url: String::new(),
+                priority: 0,
+                api: EndpointApi::Textgen,
+                model: config.vision.model.to_owned()
+            })),
+            captions: Mutex::new(HashMap::new()),
+            image_generator: ImageGenerator::new(&config.image_generation),
+            speech: SpeechSynthesizer::new(&config.voice),
+            transcriber: Transcriber::new(&config.voice)
+        })
+    }
+
+    pub fn prompt_template(&self) -> &str {
+        &self.prompt_template
+    }
+
+    /// How many messages of the chat are fetched to build the prompt
+    pub fn history_limit(&self) -> u64 {
+        self.limits.history_messages
+    }
+
+    pub fn history(&self) -> &HistoryConfig {
+        &self.history
+    }
+
+    pub fn vision(&self) -> &VisionConfig {
+        &self.vision
+    }
+
+    pub fn image_generator(&self) -> Option<&ImageGenerator> {
+        self.image_generator.as_ref()
+    }
+
+    pub fn speech(&self) -> Option<&SpeechSynthesizer> {
+        self.speech.as_ref()
+    }
+
+    pub fn transcriber(&self) -> Option<&Transcriber> {
+        self.transcriber.as_ref()
+    }
+
+    pub fn health_check_interval(&self) -> Duration {
+        Duration::from_secs(self.circuit_breaker.health_check_interval_secs)
+    }
+
+    /// Fills the prompt template. With the `chat` format the filled template is the system message, followed by the history
+    /// as user and assistant messages, and `[[CONTEXT]]` is left empty.
+    pub fn make_prompt(&self, character: &Character, history: &[Message], extras: &PromptExtras) -> Result<Prompt, Box<dyn Error>> {
+        let history = &self.inject_notes(character, history, extras);
+        let is_assistant = |message: &Message| message.speaker == character.char_name
+            || (self.format.assistant == AssistantSpeakers::Bots && extras.bots.contains(&message.speaker));
+        let turns = format::turns(history, self.format.include_names, is_assistant);
+        let context = match format::markers(&self.format) {
+            Some(markers) => {
+                let reply_start = if self.format.include_names {format!("{}:", character.char_name)} else {String::new()};
+                format::instruct_context(&turns, &markers, &reply_start)
+            },
+            None if self.format.mode == PromptFormat::Chat => String::new(),
+            None => Message::format_conversation(history)
+        };
+        let users = extras.users.iter()
+            .map(|(name, description)| format!("{}: {}", name, description))
+            .collect::<Vec<String>>()
+            .join("\n");
+        let fill_names = |text: &str| character.fill_names(text, &extras.user);
+        // Same order as PLACEHOLDERS
         let replace = &[
             &character.char_name,
-            &character.char_persona,
-            &Message::format_conversation(&character.example_dialogue),
-            &Message::format_conversation(&history)
+            &fill_names(&character.char_persona),
+            &fill_names(&Message::format_conversation(&character.example_dialogue)),
+            &context,
+            &fill_names(&character.char_description),
+            &fill_names(&character.scenario),
+            &character.tags.join(", "),
+            &users,
+            &extras.user,
+            &extras.user_persona,
+            &character.char_name,
+            &extras.user
         ];
-        let template = fs::read_to_string("prompt_template.txt")?;
+        let template = fs::read_to_string(&self.prompt_template)?;
 
-        let filled_template = aho_corasick::AhoCorasick::new(patterns)
+        let filled_template = aho_corasick::AhoCorasick::new(PLACEHOLDERS)
             .replace_all(&template, replace);
 
-        Ok(filled_template)
+        if self.format.mode != PromptFormat::Chat {
+            return Ok(Prompt::Text(filled_template));
+        }
+        let system = filled_template.trim();
+        let mut messages = Vec::new();
+        if !system.is_empty() {
+            messages.push(ChatMessage { role: Role::System, content: String::from(system) });
+        }
+        messages.extend(turns);
+        Ok(Prompt::Chat(messages))
     }
 
+    /// The history with the author's note and the character's post-history instructions put in as speakerless messages,
+    /// each that many messages from the end of the history, the note first if both are at the same depth
+    fn inject_notes(&self, character: &Character, history: &[Message], extras: &PromptExtras) -> Vec<Message> {
+        let position = |depth: usize| history.len().saturating_sub(depth);
+        let mut notes = Vec::new();
+        if let Some(note) = extras.authors_note.as_ref().filter(|note| !note.text.trim().is_empty()) {
+            notes.push((position(note.depth.unwrap_or(self.history.authors_note_depth)), note.text.trim()));
+        }
+        if !character.post_history_instructions.trim().is_empty() {
+            notes.push((position(self.history.instructions_depth), character.post_history_instructions.trim()));
+        }
+        // Earliest first, each shifted by the notes already put before it
+        notes.sort_by_key(|(index, _)| *index);
+        let mut history = history.to_vec();
+        for (inserted, (index, text)) in notes.into_iter().enumerate() {
+            history.insert(index + inserted, Message { speaker: String::new(), content: String::from(text) });
+        }
+        history
+    }
+
+    /// Returns the model loaded by the first healthy endpoint, without panicking if none are reachable
     pub async fn check_model(&self) -> Option<String> {
-        let url = &self.model_url;
-        let response = match self.client.get(url).send().await{
-            Ok(resp) => resp,
-            Err(err) => {
-                println!("Couldn't connect to textgen endpoint: {:?}", err);
-                return None;
+        for backend in self.backends.iter().filter(|backend| backend.is_available()) {
+            match self.fetch_model(&backend.endpoint).await {
+                Ok(model) => {
+                    backend.record_success();
+                    return Some(model);
+                },
+                Err(err) => {
+                    println!("Couldn't get model from {}: {:?}", backend.endpoint.model_url, err);
+                    backend.record_failure(&self.circuit_breaker);
+                }
+            }
+        }
+        None
+    }
+
+    /// Pings every endpoint, including those with an open circuit, so recovered backends are picked up again early
+    pub async fn health_check(&self) -> Option<String> {
+        let mut model = None;
+        for backend in &self.backends {
+            match self.fetch_model(&backend.endpoint).await {
+                Ok(endpoint_model) => {
+                    backend.record_success();
+                    model.get_or_insert(endpoint_model);
+                },
+                Err(err) => {
+                    println!("Health check failed for {}: {:?}", backend.endpoint.model_url, err);
+                    backend.record_failure(&self.circuit_breaker);
+                }
             }
+        }
+        model
+    }
+
+    async fn fetch_model(&self, endpoint: &Endpoint) -> Result<String, Box<dyn Error>> {
+        let response = self.client.get(&endpoint.model_url)
+            .timeout(Duration::from_secs(self.retry.timeout_secs))
+            .send()
+            .await?;
+        let text = response.text().await?;
+        let json: Value = serde_json::from_str(&text)?;
+        // OpenAI-compatible endpoints list their models instead
+        match json["result"].as_str().or_else(|| json["data"][0]["id"].as_str()) {
+            Some(model) => Ok(String::from(model)),
+            None => Err(string_error::new_err("Result wasn't a string"))
+        }
+    }
+
+    /// Tries every available endpoint in priority order, retrying each with exponential backoff before failing over
+    pub async fn request(&self, prompt: impl Into<Prompt>) -> Result<String, Box<dyn Error>> {
+        let prompt = prompt.into();
+        for backend in self.backends.iter().filter(|backend| backend.is_available()) {
+            let body = self.make_body(&backend.endpoint, &prompt);
+            if self.log_requests {
+                println!("Sending API request: {}", body);
+            }
+            for attempt in 0..self.retry.max_attempts.max(1) {
+                if attempt > 0 {
+                    tokio::time::sleep(self.retry.backoff(attempt)).await;
+                }
+                match self.send_request(&backend.endpoint, &body, &prompt).await {
+                    Ok(response) => {
+                        backend.record_success();
+                        return Ok(response);
+                    },
+                    Err(err) => println!("Request to {} failed (attempt {}): {:?}", backend.endpoint.textgen_url, attempt + 1, err)
+                }
+            }
+            // Retries are part of the same request, the breaker counts failed requests
+            backend.record_failure(&self.circuit_breaker);
+        }
+
+        Err(string_error::new_err(BACKEND_UNAVAILABLE))
+    }
+
+    /// Generates with the multimodal endpoint when there are images and one is configured, like `request` otherwise.
+    /// Multimodal endpoints get chat prompts as text. If the multimodal endpoint fails or its circuit is open, the
+    /// textgen endpoints answer without the images.
+    pub async fn request_with_images(&self, prompt: Prompt, images: &[Image]) -> Result<String, Box<dyn Error>> {
+        let backend = match &self.multimodal {
+            Some(backend) if !images.is_empty() => backend,
+            _ => return self.request(prompt).await
         };
-        let text = match response.text().await {
-            Ok(text) => {text},
-            Err(err) => {
-                println!("Couldn't get text from textgen endpoint: {:?}", err);
-                return None;
+        if backend.is_available() {
+            match self.request_multimodal(backend, &prompt.to_string(), images).await {
+                Some(reply) => {
+                    backend.record_success();
+                    return Ok(reply);
+                },
+                None => backend.record_failure(&self.circuit_breaker)
+            }
+        }
+        println!("Multimodal endpoint {} unavailable, replying without the images", backend.endpoint.textgen_url);
+        self.request(prompt).await
+    }
+
+    /// Sends a multimodal request, retrying with exponential backoff. `None` if every attempt failed.
+    async fn request_multimodal(&self, backend: &EndpointState, prompt: &str, images: &[Image]) -> Option<String> {
+        let url = &backend.endpoint.textgen_url;
+        let format = self.vision.multimodal_format;
+        let body = vision::request_body(format, &backend.endpoint.model, prompt, images, &self.sampling, &self.limits);
+        if self.log_requests {
+            println!("Sending multimodal request with {} images: {}", images.len(), prompt);
+        }
+
+        for attempt in 0..self.retry.max_attempts.max(1) {
+            if attempt > 0 {
+                tokio::time::sleep(self.retry.backoff(attempt)).await;
+            }
+            let response = self.client.post(url)
+                .timeout(Duration::from_secs(self.retry.timeout_secs))
+                .json(&body)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            let reply = match response {
+                Ok(response) => response.json::<Value>().await.map(|json| vision::parse_reply(format, &json)),
+                Err(err) => Err(err)
+            };
+            match reply {
+                Ok(Some(reply)) => return Some(reply),
+                Ok(None) => println!("Multimodal endpoint {} returned no result (attempt {})", url, attempt + 1),
+                Err(err) => println!("Request to {} failed (attempt {}): {:?}", url, attempt + 1, err)
             }
+        }
+        None
+    }
+
+    /// Describes an image with the captioning endpoint, if there's one. Captions are cached.
+    pub async fn caption(&self, image: &Image) -> Result<Option<String>, Box<dyn Error>> {
+        let url = match &self.vision.caption_url {
+            Some(url) => url,
+            None => return Ok(None)
         };
-        let json: Value = serde_json::from_str(&text).expect("Couldn't deserialize response from textgen endpoint");
-        Some(String::from(json["result"].as_str().expect("Result wasn't a string")))
+        if let Some(caption) = self.cached_caption(&image.url) {
+            return Ok(Some(caption));
+        }
+
+        let response = self.client.post(url)
+            .timeout(Duration::from_secs(self.retry.timeout_secs))
+            .json(&vision::caption_body(image))
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        let caption = vision::parse_caption(&response).ok_or("Captioning endpoint returned no caption")?;
+        self.captions.lock().unwrap().insert(caption_key(&image.url), caption.to_owned());
+        Ok(Some(caption))
     }
 
-    pub async fn request(&self, prompt: String) -> Result<String, Box<dyn Error>> {
+    pub fn cached_caption(&self, image_url: &str) -> Option<String> {
+        self.captions.lock().unwrap().get(&caption_key(image_url)).cloned()
+    }
+
+    /// OpenAI's API has no equivalent of `typical_p`, `repetition_penalty`, `encoder_repetition_penalty`, `top_k`, `min_length`,
+    /// `no_repeat_ngram_size`, `num_beams`, `penalty_alpha`, `length_penalty` and `truncation_length`, `openai_chat` endpoints don't get them.
+    /// Textgen endpoints don't get `presence_penalty` and `frequency_penalty`.
+    fn make_body(&self, endpoint: &Endpoint, prompt: &Prompt) -> String {
+        if endpoint.api == EndpointApi::OpenAiChat {
+            let mut body = json!({
+                "model": endpoint.model,
+                "messages": prompt.messages(),
+                "max_tokens": self.limits.max_new_tokens,
+                "temperature": self.sampling.temperature,
+                "top_p": self.sampling.top_p,
+                "presence_penalty": self.sampling.presence_penalty,
+                "frequency_penalty": self.sampling.frequency_penalty
+            });
+            // Some servers refuse an empty list
+            if !self.sampling.stop.is_empty() {
+                body["stop"] = json!(self.sampling.stop);
+            }
+            return body.to_string();
+        }
+
         /*
         let body = json!({
-            "temperature": self.temperature,
-            "top_p": self.top_p,
-            "typical": self.typical_p,
-            "rep_pen": self.repetition_penalty,
-            "encoder_repetition_penalty": self.encoder_repetition_penalty,
-            "top_k": self.top_k,
-            "min_length": self.min_length,
-            "no_repeat_ngram_size": self.no_repeat_ngram_size,
-            "num_beams": self.num_beams,
-            "penalty_alpha": self.penalty_alpha,
-            "length_penalty": self.length_penalty,
+            "temperature": self.sampling.temperature,
+            "top_p": self.sampling.top_p,
+            "typical": self.sampling.typical_p,
+            "rep_pen": self.sampling.repetition_penalty,
+            "encoder_repetition_penalty": self.sampling.encoder_repetition_penalty,
+            "top_k": self.sampling.top_k,
+            "min_length": self.sampling.min_length,
+            "no_repeat_ngram_size": self.sampling.no_repeat_ngram_size,
+            "num_beams": self.sampling.num_beams,
+            "penalty_alpha": self.sampling.penalty_alpha,
+            "length_penalty": self.sampling.length_penalty,
             "seed": rand::random::<i64>(),
             "prompt": prompt
         }).to_string();
@@ -101,69 +423,101 @@ impl TextgenApi{
 
         let cursed_inner_json_string = json!(
             [ 
-                prompt,
+                prompt.to_string(),
                 { 
-                    "max_new_tokens": 200, 
+                    "max_new_tokens": self.limits.max_new_tokens, 
                     "do_sample": true, 
-                    "temperature": self.temperature, 
-                    "top_p": self.top_p, 
-                    "typical_p": self.typical_p, 
-                    "repetition_penalty": self.repetition_penalty, 
-                    "encoder_repetition_penalty": self.encoder_repetition_penalty, 
-                    "top_k": self.top_k, 
-                    "min_length": self.min_length, 
-                    "no_repeat_ngram_size": self.no_repeat_ngram_size, 
-                    "num_beams": self.num_beams, 
-                    "penalty_alpha": self.penalty_alpha, 
-                    "length_penalty": self.length_penalty, 
+                    "temperature": self.sampling.temperature, 
+                    "top_p": self.sampling.top_p, 
+                    "typical_p": self.sampling.typical_p, 
+                    "repetition_penalty": self.sampling.repetition_penalty, 
+                    "encoder_repetition_penalty": self.sampling.encoder_repetition_penalty, 
+                    "top_k": self.sampling.top_k, 
+                    "min_length": self.sampling.min_length, 
+                    "no_repeat_ngram_size": self.sampling.no_repeat_ngram_size, 
+                    "num_beams": self.sampling.num_beams, 
+                    "penalty_alpha": self.sampling.penalty_alpha, 
+                    "length_penalty": self.sampling.length_penalty, 
                     "early_stopping": false, 
                     "seed": -1,
                     "add_bos_token":false,
-                    "truncation_length":2000,
-                    "custom_stopping_strings":[],
+                    "truncation_length": self.limits.truncation_length,
+                    "custom_stopping_strings": self.sampling.stop,
                     "ban_eos_token":true
                 } 
             ] 
         ).to_string();
 
-        let body = json!(
+        json!(
             {
                 "data": [cursed_inner_json_string]
             }
-        ).to_string();
+        ).to_string()
+    }
 
-        println!("Sending API request: {}", body);
+    async fn send_request(&self, endpoint: &Endpoint, body: &str, prompt: &Prompt) -> Result<String, Box<dyn Error>> {
+        let mut request = self.client.post(&endpoint.textgen_url)
+            .timeout(Duration::from_secs(self.retry.timeout_secs))
+            .body(body.to_owned());
+        if endpoint.api == EndpointApi::OpenAiChat {
+            request = request.header(reqwest::header::CONTENT_TYPE, "application/json");
+        }
+        let response = request.send().await?.error_for_status()?;
 
-        let url = &self.textgen_url;
-        let response = self.client.post(url)
-            .timeout(Duration::from_secs(20))
-            .body(body)
-            .send()
-            .await?;
-
-        let response_text = response.text().await.expect("No text?");
-        println!("{}", response_text);
-        let json: Value = serde_json::from_str(&response_text).expect("Couldn't deserialize response");
+        let response_text = response.text().await?;
+        if self.log_requests {
+            println!("API response: {}", response_text);
+        }
+        let json: Value = serde_json::from_str(&response_text)?;
+        if endpoint.api == EndpointApi::OpenAiChat {
+            return match json["choices"][0]["message"]["content"].as_str() {
+                Some(text) => Ok(String::from(text)),
+                None => Err(string_error::new_err("API returned no result"))
+            };
+        }
         let text = match json["data"][0].as_str() {
             Some(value) => value,
             None => return Err(string_error::new_err("API returned no result"))
         };
 
-        let response_only = text.replace(&prompt, "");
+        Ok(text.replace(&prompt.to_string(), ""))
+    }
+}
+
+/// Discord signs attachment URLs with expiring query parameters, the rest of the URL identifies the image
+fn caption_key(image_url: &str) -> String {
+    image_url.split('?').next().unwrap_or(image_url).to_owned()
+}
 
-        return Ok(String::from(response_only));
+impl std::fmt::Display for Message {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+    {
+        match self.speaker.is_empty() {
+            true => write!(f, "{}", self.content),
+            false => write!(f, "{}: {}", self.speaker, self.content)
+        }
     }
 }
 
 impl Message {
-    pub fn to_string(&self) -> String
-    {
-        [&self.speaker, ": ", &self.content].join("")
+    /// Parses `Speaker: content` lines, the inverse of `format_conversation`
+    pub fn parse_conversation(text: &str) -> Result<Vec<Message>, Box<dyn Error>> {
+        let mut messages = Vec::new();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            match line.split_once(':') {
+                Some((speaker, content)) => messages.push(Message {
+                    speaker: String::from(speaker.trim()),
+                    content: String::from(content.trim())
+                }),
+                None => return Err(string_error::into_err(format!("Line \"{}\" should look like \"Speaker: message\"", line)))
+            }
+        }
+        Ok(messages)
     }
 
-    pub fn format_conversation(messages: &Vec<Message>) -> String
+    pub fn format_conversation(messages: &[Message]) -> String
     {
-        let collection: Vec<String> = messages.into_iter()
+        let collection: Vec<String> = messages.iter()
             .map(|msg| msg.to_string())
             .collect();
         collection.join("\n")
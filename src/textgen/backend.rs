@@ -0,0 +1,128 @@
+use std::{sync::Mutex, time::{Duration, Instant}};
+use serde::{Serialize, Deserialize};
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Endpoint {
+    pub textgen_url: String,
+    pub model_url: String,
+    #[serde(default)]
+    pub priority: i32,
+    #[serde(default)]
+    pub api: EndpointApi,
+    /// Model asked for from OpenAI-compatible endpoints
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub model: String,
+}
+
+/// Payloads an endpoint understands
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum EndpointApi {
+    /// oobabooga's gradio API, `textgen_url` being `/run/textgen` and `model_url` `/api/v1/model`
+    #[default]
+    Textgen,
+    /// OpenAI-compatible chat completions, `textgen_url` being `/v1/chat/completions` and `model_url` `/v1/models`
+    #[serde(rename = "openai_chat")]
+    OpenAiChat,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    pub timeout_secs: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct CircuitBreakerPolicy {
+    pub failure_threshold: u32,
+    pub cooldown_secs: u64,
+    pub health_check_interval_secs: u64,
+}
+
+/// A backend endpoint together with its circuit breaker state
+pub struct EndpointState {
+    pub endpoint: Endpoint,
+    breaker: Mutex<Breaker>,
+}
+
+struct Breaker {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff_ms: 500,
+            max_backoff_ms: 8000,
+            timeout_secs: 20,
+        }
+    }
+}
+
+impl Default for CircuitBreakerPolicy {
+    fn default() -> Self {
+        CircuitBreakerPolicy {
+            failure_threshold: 3,
+            cooldown_secs: 60,
+            health_check_interval_secs: 30,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before the given retry (1 = first retry), doubling every time up to the configured maximum
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = 2u64.saturating_pow(retry.saturating_sub(1));
+        let millis = self.initial_backoff_ms.saturating_mul(factor).min(self.max_backoff_ms);
+        Duration::from_millis(millis)
+    }
+}
+
+impl EndpointState {
+    pub fn new(endpoint: Endpoint) -> EndpointState {
+        EndpointState {
+            endpoint,
+            breaker: Mutex::new(Breaker { consecutive_failures: 0, open_until: None }),
+        }
+    }
+
+    /// Closed circuits are always available, open ones become available again (half-open) once the cooldown is over
+    pub fn is_available(&self) -> bool {
+        let breaker = self.breaker.lock().unwrap();
+        match breaker.open_until {
+            Some(until) => Instant::now() >= until,
+            None => true,
+        }
+    }
+
+    pub fn record_success(&self) {
+        let mut breaker = self.breaker.lock().unwrap();
+        if breaker.open_until.is_some() {
+            println!("Backend {} is reachable again, closing circuit", self.endpoint.textgen_url);
+        }
+        breaker.consecutive_failures = 0;
+        breaker.open_until = None;
+    }
+
+    pub fn record_failure(&self, policy: &CircuitBreakerPolicy) {
+        let mut breaker = self.breaker.lock().unwrap();
+        breaker.consecutive_failures += 1;
+        if breaker.consecutive_failures >= policy.failure_threshold {
+            println!("Backend {} failed {} times in a row, opening circuit for {}s",
+                self.endpoint.textgen_url, breaker.consecutive_failures, policy.cooldown_secs);
+            breaker.open_until = Some(Instant::now() + Duration::from_secs(policy.cooldown_secs));
+        }
+    }
+}
+
+/// Endpoints sorted by priority (lowest value first), keeping config order for equal priorities
+pub fn sort_endpoints(mut endpoints: Vec<Endpoint>) -> Vec<EndpointState> {
+    endpoints.sort_by_key(|endpoint| endpoint.priority);
+    endpoints.into_iter().map(EndpointState::new).collect()
+}
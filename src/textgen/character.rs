@@ -1,15 +1,90 @@
-use std::{fs, error::Error, collections::HashMap, path::PathBuf};
+use std::{fs, error::Error, collections::HashMap, path::{Path, PathBuf}};
+use rand::seq::SliceRandom;
 use serde::{Serialize, Deserialize};
 
 use super::api::Message;
 
-#[derive(Serialize, Deserialize)]
+/// Names filled in greetings and in the fields put in the prompt, both the prompt template's syntax and the usual macros
+const NAME_PLACEHOLDERS: [&str; 4] = ["{{char}}", "[[NAME]]", "{{user}}", "[[USER]]"];
+
+/// Extensions of the image types avatars can be uploaded as
+pub const AVATAR_EXTENSIONS: [&str; 4] = ["png", "jpg", "gif", "webp"];
+
+/// Who can see and invite a character
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    /// Visible in every server
+    #[default]
+    Global,
+    /// Only visible in the server it was created in, characters created outside of a server are private instead
+    Guild,
+    /// Only visible to its owner
+    Private,
+}
+
+impl Visibility {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Visibility::Global => "global",
+            Visibility::Guild => "guild",
+            Visibility::Private => "private"
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Visibility> {
+        match value {
+            "global" => Some(Visibility::Global),
+            "guild" => Some(Visibility::Guild),
+            "private" => Some(Visibility::Private),
+            _ => None
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
 pub struct Character {
     pub char_name: String,
     pub char_description: String,
     pub char_persona: String,
     pub example_dialogue: Vec<Message>,
     pub avatar_url: String,
+    /// Discord user that created the character from a command, if any. Host-provided characters have no owner and are read-only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner_id: Option<u64>,
+    /// Guild the character was created in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub guild_id: Option<u64>,
+    #[serde(default)]
+    pub visibility: Visibility,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub author: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub version: String,
+    /// Notes for people using the character, never sent to the LLM
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub creator_notes: String,
+    /// Circumstances of the conversation, available to the prompt as `[[SCENARIO]]`
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub scenario: String,
+    /// First message the character sends when invited
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub greeting: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alternate_greetings: Vec<String>,
+    /// Prepended to every image prompt, usually what the character looks like
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub image_prompt: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub image_negative_prompt: String,
+    /// Text-to-speech voice the character speaks with in voice channels
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub voice: String,
+    /// Reminder put in the history, `history.instructions_depth` messages from its end, so the character stays in persona
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub post_history_instructions: String,
 }
 
 impl Character{
@@ -41,15 +116,133 @@ impl Character{
             };
 
             println!("Loading character {} with ID {}", loaded_character.char_name, id);
+            for problem in loaded_character.validate() {
+                println!("Character {} has a problem, run `uc207 validate` for details: {}", id, problem);
+            }
             char_dict.insert(id, loaded_character);
         }
 
         fn load_character(char_path: &PathBuf) -> Result<Character, Box<dyn Error>> {
             let json = fs::read_to_string(char_path)?;
             let character: Character = serde_json::from_str(&json)?;
-            return Ok(character);
+            Ok(character)
         }
 
-        return Ok(char_dict);
+        Ok(char_dict)
+    }
+
+    pub fn is_visible_to(&self, user_id: u64, guild_id: Option<u64>) -> bool {
+        match self.visibility {
+            Visibility::Global => true,
+            Visibility::Guild => self.guild_id.is_some() && self.guild_id == guild_id,
+            Visibility::Private => self.owner_id == Some(user_id)
+        }
+    }
+
+    /// Case-insensitive search over the name, description and tags
+    pub fn matches(&self, query: &str) -> bool {
+        let query = query.trim().to_lowercase();
+        query.is_empty()
+            || self.char_name.to_lowercase().contains(&query)
+            || self.char_description.to_lowercase().contains(&query)
+            || self.author.to_lowercase().contains(&query)
+            || self.tags.iter().any(|tag| tag.to_lowercase().contains(&query))
+    }
+
+    /// The main greeting followed by the alternate ones, skipping empty entries
+    pub fn greetings(&self) -> Vec<&String> {
+        std::iter::once(&self.greeting)
+            .chain(self.alternate_greetings.iter())
+            .filter(|greeting| !greeting.trim().is_empty())
+            .collect()
+    }
+
+    /// Greeting number `index` (starting at 1) or a random one, with the names filled in. `None` if there's no such greeting.
+    pub fn pick_greeting(&self, index: Option<i64>, user_name: &str) -> Option<String> {
+        let greetings = self.greetings();
+        let greeting = match index {
+            Some(index) => usize::try_from(index - 1).ok().and_then(|index| greetings.get(index)).copied(),
+            None => greetings.choose(&mut rand::thread_rng()).copied()
+        };
+        greeting.map(|greeting| self.fill_names(greeting, user_name))
+    }
+
+    /// Fills in the character's name (`{{char}}` or `[[NAME]]`) and the user's (`{{user}}` or `[[USER]]`)
+    pub fn fill_names(&self, text: &str, user_name: &str) -> String {
+        aho_corasick::AhoCorasick::new(NAME_PLACEHOLDERS)
+            .replace_all(text, &[&self.char_name, &self.char_name, user_name, user_name])
+    }
+
+    pub fn is_valid_id(id: &str) -> bool {
+        !id.is_empty() && id.len() <= 32 && id.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-')
+    }
+
+    /// Returns a human readable description of every problem with this character, empty if it's valid
+    pub fn validate(&self) -> Vec<String> {
+        self.field_problems().into_iter().map(|(_, problem)| problem).collect()
+    }
+
+    /// Same as `validate`, along with the JSON field each problem is about
+    pub fn field_problems(&self) -> Vec<(&'static str, String)> {
+        let mut problems = Vec::new();
+        let name = self.char_name.trim();
+        if name.is_empty() || name.chars().count() > 80 {
+            problems.push(("char_name", String::from("Name must be between 1 and 80 characters")));
+        }
+        let lowercase_name = name.to_lowercase();
+        if lowercase_name.contains("discord") || lowercase_name.contains("clyde") {
+            problems.push(("char_name", String::from("Name can't contain \"discord\" or \"clyde\"")));
+        }
+        if self.char_description.chars().count() > 1000 {
+            problems.push(("char_description", String::from("Description must be at most 1000 characters")));
+        }
+        if self.char_persona.trim().is_empty() {
+            problems.push(("char_persona", String::from("Persona can't be empty")));
+        }
+        let avatar_is_url = self.avatar_url.starts_with("https://") || self.avatar_url.starts_with("http://");
+        if !self.avatar_url.is_empty() && !avatar_is_url {
+            problems.push(("avatar_url", String::from("Avatar must be an http(s) URL")));
+        }
+        for message in &self.example_dialogue {
+            if message.speaker.trim().is_empty() {
+                problems.push(("example_dialogue", format!("Example line \"{}\" has no speaker", message.content)));
+            }
+        }
+        problems
+    }
+
+    pub fn save(&self, characters_dir: &str, id: &str) -> Result<(), Box<dyn Error>> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(Self::path(characters_dir, id), json)?;
+        Ok(())
+    }
+
+    pub fn delete(characters_dir: &str, id: &str) -> Result<(), Box<dyn Error>> {
+        fs::remove_file(Self::path(characters_dir, id))?;
+        Ok(())
+    }
+
+    /// Saves an uploaded avatar as `<id>.<extension>` in `avatars_dir`, replacing any previous one, and returns its file name
+    pub fn save_avatar(avatars_dir: &str, id: &str, extension: &str, image: &[u8]) -> Result<String, Box<dyn Error>> {
+        fs::create_dir_all(avatars_dir)?;
+        Self::delete_avatar(avatars_dir, id)?;
+        let file_name = [id, ".", extension].join("");
+        fs::write(Path::new(avatars_dir).join(&file_name), image)?;
+        Ok(file_name)
+    }
+
+    /// Removes the character's uploaded avatar, if it has one
+    pub fn delete_avatar(avatars_dir: &str, id: &str) -> Result<(), Box<dyn Error>> {
+        for extension in AVATAR_EXTENSIONS {
+            let path = Path::new(avatars_dir).join([id, ".", extension].join(""));
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn path(characters_dir: &str, id: &str) -> PathBuf {
+        Path::new(characters_dir).join([id, ".json"].join(""))
     }
 }
\ No newline at end of file
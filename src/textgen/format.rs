@@ -0,0 +1,137 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{FormatConfig, PromptFormat};
+use super::api::Message;
+
+/// What's sent to a backend: text for it to continue, or a conversation for chat-completion endpoints
+#[derive(Clone, Debug, PartialEq)]
+pub enum Prompt {
+    Text(String),
+    Chat(Vec<ChatMessage>),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ChatMessage {
+    pub role: Role,
+    pub content: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+}
+
+impl From<String> for Prompt {
+    fn from(text: String) -> Self {
+        Prompt::Text(text)
+    }
+}
+
+impl Prompt {
+    /// The conversation a chat-completion endpoint gets, text prompts being a single user message
+    pub fn messages(&self) -> Vec<ChatMessage> {
+        match self {
+            Prompt::Text(text) => vec![ChatMessage { role: Role::User, content: text.to_owned() }],
+            Prompt::Chat(messages) => messages.clone()
+        }
+    }
+}
+
+/// The prompt as text, for backends that only take text and for showing it. Chat messages are laid out as ChatML.
+impl fmt::Display for Prompt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Prompt::Text(text) => write!(f, "{}", text),
+            Prompt::Chat(messages) => {
+                for message in messages {
+                    write!(f, "<|im_start|>{}\n{}<|im_end|>\n", message.role.name(), message.content)?;
+                }
+                writeln!(f, "<|im_start|>assistant")
+            }
+        }
+    }
+}
+
+impl Role {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Role::System => "system",
+            Role::User => "user",
+            Role::Assistant => "assistant"
+        }
+    }
+}
+
+/// What comes before and after each turn of an instruct format
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TurnMarkers<'a> {
+    pub user_prefix: &'a str,
+    pub user_suffix: &'a str,
+    pub assistant_prefix: &'a str,
+    pub assistant_suffix: &'a str,
+    /// Around notes put in the history, the user markers for formats without a system turn
+    pub system_prefix: &'a str,
+    pub system_suffix: &'a str,
+}
+
+/// Turn markers of the instruct format, `None` for formats without turns
+pub fn markers(config: &FormatConfig) -> Option<TurnMarkers<'_>> {
+    let (user_prefix, user_suffix, assistant_prefix, assistant_suffix) = match config.mode {
+        PromptFormat::Transcript | PromptFormat::Chat => return None,
+        PromptFormat::ChatMl => ("<|im_start|>user\n", "<|im_end|>\n", "<|im_start|>assistant\n", "<|im_end|>\n"),
+        PromptFormat::Llama2 => ("[INST] ", " [/INST] ", "", " </s><s>"),
+        PromptFormat::Vicuna => ("USER: ", "\n", "ASSISTANT: ", "</s>\n"),
+        PromptFormat::Custom => (
+            config.user_prefix.as_str(),
+            config.user_suffix.as_str(),
+            config.assistant_prefix.as_str(),
+            config.assistant_suffix.as_str()
+        )
+    };
+    let (system_prefix, system_suffix) = match config.mode {
+        PromptFormat::ChatMl => ("<|im_start|>system\n", "<|im_end|>\n"),
+        _ => (user_prefix, user_suffix)
+    };
+    Some(TurnMarkers { user_prefix, user_suffix, assistant_prefix, assistant_suffix, system_prefix, system_suffix })
+}
+
+/// Groups the history into user and assistant turns, merging consecutive messages of the same side.
+/// With `include_names`, each message keeps its `Speaker: ` prefix. Messages without a speaker are system turns of their own.
+pub fn turns(history: &[Message], include_names: bool, is_assistant: impl Fn(&Message) -> bool) -> Vec<ChatMessage> {
+    let mut turns: Vec<ChatMessage> = Vec::new();
+    for message in history {
+        let role = match message.speaker.is_empty() {
+            true => Role::System,
+            false if is_assistant(message) => Role::Assistant,
+            false => Role::User
+        };
+        let content = if include_names {message.to_string()} else {message.content.to_owned()};
+        match turns.last_mut() {
+            Some(turn) if turn.role == role && role != Role::System => {
+                turn.content.push('\n');
+                turn.content.push_str(&content);
+            },
+            _ => turns.push(ChatMessage { role, content })
+        }
+    }
+    turns
+}
+
+/// Turns wrapped in their markers, ending with the opened turn of the reply, which starts with `reply_start`
+pub fn instruct_context(turns: &[ChatMessage], markers: &TurnMarkers, reply_start: &str) -> String {
+    let mut context: String = turns.iter()
+        .map(|turn| match turn.role {
+            Role::Assistant => [markers.assistant_prefix, &turn.content, markers.assistant_suffix].join(""),
+            Role::System => [markers.system_prefix, &turn.content, markers.system_suffix].join(""),
+            Role::User => [markers.user_prefix, &turn.content, markers.user_suffix].join("")
+        })
+        .collect();
+    context.push_str(markers.assistant_prefix);
+    context.push_str(reply_start);
+    context
+}
@@ -0,0 +1,86 @@
+use std::error::Error;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use regex::Regex;
+use reqwest::Client;
+use serde_json::{json, Value};
+
+use crate::config::ImageGenerationConfig;
+use super::character::Character;
+
+/// Client for an AUTOMATIC1111-compatible txt2img API
+pub struct ImageGenerator {
+    client: Client,
+    config: ImageGenerationConfig,
+}
+
+impl ImageGenerator {
+    /// `None` when no image generation URL is configured
+    pub fn new(config: &ImageGenerationConfig) -> Option<ImageGenerator> {
+        config.url.as_ref()?;
+        Some(ImageGenerator { client: Client::new(), config: config.clone() })
+    }
+
+    pub fn max_images_per_reply(&self) -> usize {
+        self.config.max_images_per_reply
+    }
+
+    /// Prompt and negative prompt for a picture of the character
+    pub fn prompts(&self, character: &Character, prompt: &str) -> (String, String) {
+        (join_prompts(&[&character.image_prompt, prompt]), join_prompts(&[&self.config.negative_prompt, &character.image_negative_prompt]))
+    }
+
+    /// Generates an image, returning the PNG file
+    pub async fn generate(&self, prompt: &str, negative_prompt: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+        let url = [self.config.url.as_deref().unwrap_or_default().trim_end_matches('/'), "/sdapi/v1/txt2img"].join("");
+        let response: Value = self.client.post(url)
+            .timeout(Duration::from_secs(self.config.timeout_secs))
+            .json(&txt2img_body(&self.config, prompt, negative_prompt))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let image = response["images"][0].as_str().ok_or("txt2img returned no image")?;
+        // Some servers prefix the image with its data URL header
+        let image = image.split_once("base64,").map_or(image, |(_, image)| image);
+        Ok(STANDARD.decode(image)?)
+    }
+}
+
+pub fn txt2img_body(config: &ImageGenerationConfig, prompt: &str, negative_prompt: &str) -> Value {
+    json!({
+        "prompt": prompt,
+        "negative_prompt": negative_prompt,
+        "steps": config.steps,
+        "width": config.width,
+        "height": config.height,
+        "cfg_scale": config.cfg_scale,
+        "sampler_name": config.sampler_name,
+        "batch_size": 1,
+        "n_iter": 1
+    })
+}
+
+/// Removes `[picture: prompt]` tags from a reply, returning the text left and the prompts in order.
+/// Not `[image: ...]`, the captions of images in the history, or the model would ask for pictures by copying them.
+pub fn extract_image_tags(text: &str) -> (String, Vec<String>) {
+    static TAG: OnceLock<Regex> = OnceLock::new();
+    let tag = TAG.get_or_init(|| Regex::new(r"(?i)\[picture:\s*([^\]]*)\]").unwrap());
+    let prompts = tag.captures_iter(text)
+        .map(|captures| captures[1].trim().to_owned())
+        .filter(|prompt| !prompt.is_empty())
+        .collect();
+    (tag.replace_all(text, "").trim_end().to_owned(), prompts)
+}
+
+fn join_prompts(parts: &[&str]) -> String {
+    parts.iter()
+        .map(|part| part.trim())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<&str>>()
+        .join(", ")
+}
@@ -1,2 +1,6 @@
 pub mod api;
-pub mod character;
\ No newline at end of file
+pub mod backend;
+pub mod character;
+pub mod format;
+pub mod imagegen;
+pub mod vision;
@@ -0,0 +1,97 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde_json::{json, Value};
+
+use crate::config::{LimitsConfig, MultimodalFormat, SamplingConfig};
+
+/// An image from the chat, downloaded so it can be sent to the model
+#[derive(Clone, Debug)]
+pub struct Image {
+    pub url: String,
+    pub data: Vec<u8>,
+}
+
+impl Image {
+    /// Guessed from the file signature, since platforms don't all tell
+    pub fn mime_type(&self) -> &'static str {
+        match self.data.as_slice() {
+            [0x89, b'P', b'N', b'G', ..] => "image/png",
+            [0xFF, 0xD8, 0xFF, ..] => "image/jpeg",
+            [b'G', b'I', b'F', b'8', ..] => "image/gif",
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => "image/webp",
+            _ => "application/octet-stream"
+        }
+    }
+
+    pub fn base64(&self) -> String {
+        STANDARD.encode(&self.data)
+    }
+
+    pub fn data_url(&self) -> String {
+        format!("data:{};base64,{}", self.mime_type(), self.base64())
+    }
+}
+
+/// Text standing for the `index`th image (from 1) in the history. llama.cpp swaps `[img-N]` for the image with ID N.
+pub fn marker(format: MultimodalFormat, index: usize) -> String {
+    match format {
+        MultimodalFormat::LlamaCpp => format!("[img-{}]", index),
+        MultimodalFormat::OpenAi => format!("[image {}]", index)
+    }
+}
+
+/// Generation request for a multimodal endpoint, images in the order of their markers
+pub fn request_body(format: MultimodalFormat, model: &str, prompt: &str, images: &[Image], sampling: &SamplingConfig, limits: &LimitsConfig) -> Value {
+    match format {
+        MultimodalFormat::LlamaCpp => json!({
+            "prompt": prompt,
+            "image_data": images.iter().enumerate()
+                .map(|(index, image)| json!({ "data": image.base64(), "id": index + 1 }))
+                .collect::<Vec<Value>>(),
+            "n_predict": limits.max_new_tokens,
+            "temperature": sampling.temperature,
+            "top_p": sampling.top_p,
+            "typical_p": sampling.typical_p,
+            "top_k": sampling.top_k,
+            "repeat_penalty": sampling.repetition_penalty,
+            "stream": false
+        }),
+        MultimodalFormat::OpenAi => {
+            let mut content = vec![json!({ "type": "text", "text": prompt })];
+            content.extend(images.iter().map(|image| json!({ "type": "image_url", "image_url": { "url": image.data_url() } })));
+            json!({
+                "model": model,
+                "messages": [{ "role": "user", "content": content }],
+                "max_tokens": limits.max_new_tokens,
+                "temperature": sampling.temperature,
+                "top_p": sampling.top_p
+            })
+        }
+    }
+}
+
+/// Generated text out of a multimodal endpoint's response
+pub fn parse_reply(format: MultimodalFormat, response: &Value) -> Option<String> {
+    let text = match format {
+        MultimodalFormat::LlamaCpp => &response["content"],
+        MultimodalFormat::OpenAi => &response["choices"][0]["message"]["content"]
+    };
+    text.as_str().map(String::from)
+}
+
+/// Request sent to the captioning endpoint
+pub fn caption_body(image: &Image) -> Value {
+    json!({ "image": image.base64(), "mime_type": image.mime_type() })
+}
+
+/// Caption out of the captioning endpoint's response: `{"caption": ...}`, `[{"generated_text": ...}]` or plain text
+pub fn parse_caption(response: &str) -> Option<String> {
+    let caption = match serde_json::from_str::<Value>(response) {
+        Ok(json) => json["caption"].as_str()
+            .or_else(|| json[0]["generated_text"].as_str())
+            .or_else(|| json.as_str())
+            .map(String::from),
+        Err(_) => Some(String::from(response))
+    };
+    caption.map(|caption| caption.trim().to_owned()).filter(|caption| !caption.is_empty())
+}
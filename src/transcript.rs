@@ -0,0 +1,178 @@
+use std::error::Error;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::textgen::api::Message;
+
+/// Most messages an export goes back
+pub const MAX_EXPORT_MESSAGES: u64 = 1000;
+
+/// File formats chats are exported to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// `Speaker: content` lines, like the prompt's history
+    Text,
+    /// One `Message` per line
+    Jsonl,
+    /// SillyTavern's chat files, with a metadata line first
+    SillyTavern,
+    Html,
+}
+
+impl ExportFormat {
+    pub const ALL: [ExportFormat; 4] = [ExportFormat::Text, ExportFormat::Jsonl, ExportFormat::SillyTavern, ExportFormat::Html];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExportFormat::Text => "text",
+            ExportFormat::Jsonl => "jsonl",
+            ExportFormat::SillyTavern => "sillytavern",
+            ExportFormat::Html => "html"
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<ExportFormat> {
+        ExportFormat::ALL.into_iter().find(|format| format.name().eq_ignore_ascii_case(name.trim()))
+    }
+
+    /// Format for a file name given by `file_name`, plain text for unknown extensions
+    pub fn of_file(path: &str) -> ExportFormat {
+        let path = path.to_lowercase();
+        ExportFormat::ALL.into_iter()
+            .rev()
+            .find(|format| path.ends_with(&format.file_name("")))
+            .unwrap_or(ExportFormat::Text)
+    }
+
+    /// Name of the exported file, without its directory
+    pub fn file_name(&self, stem: &str) -> String {
+        match self {
+            ExportFormat::Text => format!("{}.txt", stem),
+            ExportFormat::Jsonl => format!("{}.jsonl", stem),
+            ExportFormat::SillyTavern => format!("{}-sillytavern.jsonl", stem),
+            ExportFormat::Html => format!("{}.html", stem)
+        }
+    }
+}
+
+/// Messages imported into a channel, seen by the characters before the channel's own history until the next fence
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ImportedHistory {
+    /// Latest fence in the channel when the messages were imported
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fence: Option<String>,
+    pub messages: Vec<Message>,
+}
+
+/// Writes a chat, oldest message first. Messages from `character_name` are the character's, the others the users'.
+/// `date` is when the chat is exported, SillyTavern files need one.
+pub fn export(format: ExportFormat, messages: &[Message], character_name: &str, date: &str) -> String {
+    match format {
+        ExportFormat::Text => Message::format_conversation(messages),
+        ExportFormat::Jsonl => messages.iter()
+            .map(|message| json!(message).to_string())
+            .collect::<Vec<String>>()
+            .join("\n"),
+        ExportFormat::SillyTavern => {
+            let user_name = messages.iter()
+                .find(|message| message.speaker != character_name)
+                .map_or("User", |message| message.speaker.as_str());
+            let metadata = json!({
+                "user_name": user_name,
+                "character_name": character_name,
+                "create_date": date,
+                "chat_metadata": {}
+            });
+            let lines = messages.iter().map(|message| json!({
+                "name": message.speaker,
+                "is_user": message.speaker != character_name,
+                "is_system": false,
+                "send_date": date,
+                "mes": message.content,
+                "extra": {}
+            }));
+            std::iter::once(metadata).chain(lines)
+                .map(|line| line.to_string())
+                .collect::<Vec<String>>()
+                .join("\n")
+        },
+        ExportFormat::Html => {
+            let title = match character_name.is_empty() {
+                true => String::from("Chat"),
+                false => format!("Chat with {}", escape_html(character_name))
+            };
+            let body: String = messages.iter()
+                .map(|message| format!(
+                    "<div class=\"message{}\"><b>{}</b><p>{}</p></div>\n",
+                    if message.speaker == character_name { " character" } else { "" },
+                    escape_html(&message.speaker),
+                    escape_html(&message.content).replace('\n', "<br>")
+                ))
+                .collect();
+            format!(
+                "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{0}</title>\n<style>\n\
+                body {{ font-family: sans-serif; max-width: 50em; margin: auto; }}\n\
+                .message {{ margin: 1em 0; }}\n.message p {{ margin: 0.2em 0; }}\n.character b {{ color: #5865f2; }}\n\
+                </style>\n</head>\n<body>\n<h1>{0}</h1>\n<p>Exported {1}</p>\n{2}</body>\n</html>\n",
+                title, escape_html(date), body
+            )
+        }
+    }
+}
+
+/// Reads a chat exported as text, JSONL or SillyTavern JSONL, guessing the format from its content
+pub fn import(contents: &str) -> Result<Vec<Message>, Box<dyn Error>> {
+    let lines: Vec<&str> = contents.lines().filter(|line| !line.trim().is_empty()).collect();
+    let first = lines.first().ok_or("The transcript is empty")?;
+    if serde_json::from_str::<Value>(first).is_ok_and(|json| json.is_object()) {
+        return import_jsonl(&lines);
+    }
+
+    // Lines without a speaker continue the previous message
+    let mut messages: Vec<Message> = Vec::new();
+    for line in contents.lines() {
+        let speaker = line.split_once(':')
+            .map(|(speaker, content)| (speaker.trim(), content.trim()))
+            .filter(|(speaker, _)| !speaker.is_empty());
+        match (speaker, messages.last_mut()) {
+            (Some((speaker, content)), _) => messages.push(Message { speaker: speaker.to_owned(), content: content.to_owned() }),
+            (None, Some(last)) => {
+                last.content.push('\n');
+                last.content.push_str(line.trim_end());
+            },
+            (None, None) if line.trim().is_empty() => {},
+            (None, None) => return Err(string_error::into_err(format!("Line \"{}\" should look like \"Speaker: message\"", line)))
+        }
+    }
+    for message in &mut messages {
+        message.content = message.content.trim_end().to_owned();
+    }
+    Ok(messages)
+}
+
+fn import_jsonl(lines: &[&str]) -> Result<Vec<Message>, Box<dyn Error>> {
+    let mut messages = Vec::new();
+    for (index, line) in lines.iter().enumerate() {
+        let json: Value = serde_json::from_str(line)
+            .map_err(|why| string_error::into_err(format!("Line {}: {}", index + 1, why)))?;
+        let message = match (&json["speaker"], &json["content"], &json["name"], &json["mes"]) {
+            (Value::String(speaker), Value::String(content), _, _) | (_, _, Value::String(speaker), Value::String(content)) =>
+                Message { speaker: speaker.to_owned(), content: content.to_owned() },
+            // SillyTavern's metadata line
+            _ if json.get("chat_metadata").is_some() || json.get("user_name").is_some() => continue,
+            _ => return Err(string_error::into_err(format!("Line {} isn't a chat message", index + 1)))
+        };
+        if json["is_system"].as_bool() != Some(true) {
+            messages.push(message);
+        }
+    }
+    Ok(messages)
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+use std::error::Error;
+
+use serde::{Deserialize, Serialize};
+
+use crate::store::Store;
+
+/// A user's persona: what they asked the characters to call them, and what they told about themselves.
+/// An empty name keeps their platform name.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct UserProfile {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub description: String,
+}
+
+/// Profiles of one user: the one used everywhere, and the ones used in one server only
+#[derive(Serialize, Deserialize, Default)]
+struct Profiles {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    global: Option<UserProfile>,
+    /// By server ID
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    servers: HashMap<String, UserProfile>,
+}
+
+impl Profiles {
+    fn is_empty(&self) -> bool {
+        self.global.is_none() && self.servers.is_empty()
+    }
+}
+
+/// Profiles of every user by user ID, saved to a file on every change
+#[derive(Default)]
+pub struct UserProfiles {
+    users: Store<Profiles>,
+}
+
+impl UserProfiles {
+    /// Loads the profiles saved at `path`, or starts without any if the file doesn't exist yet
+    pub fn load(path: &str) -> Result<UserProfiles, Box<dyn Error>> {
+        Ok(UserProfiles { users: Store::load(path)? })
+    }
+
+    /// Profile a user goes by in a server (or outside of any with `None`): the server's own, else their global one
+    pub fn get(&self, user_id: &str, server: Option<&str>) -> Option<&UserProfile> {
+        server.and_then(|server| self.get_exact(user_id, Some(server)))
+            .or_else(|| self.get_exact(user_id, None))
+    }
+
+    /// The user's profile for that server only, or their global one for `None`
+    pub fn get_exact(&self, user_id: &str, server: Option<&str>) -> Option<&UserProfile> {
+        let profiles = self.users.get(user_id)?;
+        match server {
+            Some(server) => profiles.servers.get(server),
+            None => profiles.global.as_ref()
+        }
+    }
+
+    /// Sets the user's profile for one server, or their global one for `None`
+    pub fn set(&mut self, user_id: &str, server: Option<&str>, profile: UserProfile) -> Result<(), Box<dyn Error>> {
+        self.users.update(user_id, |profiles| {
+            let profiles = profiles.get_or_insert_with(Profiles::default);
+            match server {
+                Some(server) => {
+                    profiles.servers.insert(String::from(server), profile);
+                },
+                None => profiles.global = Some(profile)
+            }
+        })
+    }
+
+    /// Renames the user's global profile and keeps its description unless a new one is given.
+    /// An empty name goes back to their platform name, the profile is only dropped once it has no description either.
+    pub fn set_name(&mut self, user_id: &str, name: &str, description: Option<String>) -> Result<(), Box<dyn Error>> {
+        self.users.update(user_id, |profiles| {
+            let entry = profiles.get_or_insert_with(Profiles::default);
+            let mut profile = entry.global.take().unwrap_or_default();
+            profile.name = String::from(name);
+            if let Some(description) = description {
+                profile.description = description;
+            }
+            if !profile.name.is_empty() || !profile.description.is_empty() {
+                entry.global = Some(profile);
+            }
+            if entry.is_empty() {
+                *profiles = None;
+            }
+        })
+    }
+
+    pub fn remove(&mut self, user_id: &str, server: Option<&str>) -> Result<Option<UserProfile>, Box<dyn Error>> {
+        if self.get_exact(user_id, server).is_none() {
+            return Ok(None);
+        }
+        self.users.update(user_id, |profiles| {
+            let entry = profiles.as_mut()?;
+            let removed = match server {
+                Some(server) => entry.servers.remove(server),
+                None => entry.global.take()
+            };
+            if entry.is_empty() {
+                *profiles = None;
+            }
+            removed
+        })
+    }
+}
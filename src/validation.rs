@@ -0,0 +1,189 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use regex::Regex;
+use serde_json::Value;
+
+use crate::config::{self, Config, ConfigFormat};
+use crate::textgen::api::{TextgenApi, PLACEHOLDERS, REQUIRED_PLACEHOLDERS};
+use crate::textgen::character::Character;
+
+/// JSON Schema of the config, also published in `schemas/`
+pub const CONFIG_SCHEMA: &str = include_str!("../schemas/config.schema.json");
+
+/// JSON Schema of character files, also published in `schemas/`
+pub const CHARACTER_SCHEMA: &str = include_str!("../schemas/character.schema.json");
+
+/// Something wrong in a file, with the line it's on when it can be found
+#[derive(Debug, PartialEq)]
+pub struct Problem {
+    pub file: String,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl Problem {
+    fn new(file: &str, line: Option<usize>, message: impl Into<String>) -> Problem {
+        Problem { file: String::from(file), line, message: message.into() }
+    }
+
+    fn from_json_error(file: &str, err: &serde_json::Error) -> Problem {
+        // serde_json appends the position to the message, it's already in the line column
+        let message = err.to_string();
+        let position = format!(" at line {} column {}", err.line(), err.column());
+        Problem::new(file, Some(err.line()), message.trim_end_matches(&position))
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.file, line, self.message),
+            None => write!(f, "{}: {}", self.file, self.message)
+        }
+    }
+}
+
+/// Checks the config file and the prompt template it points to. Environment overrides aren't applied, so problems are in the file.
+pub fn check_config(path: &str) -> Vec<Problem> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(why) => return vec![Problem::new(path, None, why.to_string())]
+    };
+    let mut value = match config::parse_value(&text, ConfigFormat::of(path)) {
+        Ok(value) => value,
+        Err(err) => return vec![Problem::new(path, err.line, err.message)]
+    };
+    config::migrate_legacy(&mut value);
+
+    let schema: Value = serde_json::from_str(CONFIG_SCHEMA).expect("Invalid config schema");
+    let mut problems = unknown_fields(path, &text, &value, &schema, "");
+    if !problems.is_empty() {
+        // Deserializing would only stop at the first of them
+        return problems;
+    }
+    let mut config = match Config::from_value(value) {
+        Ok(config) => config,
+        Err(err) => {
+            let setting = err.path().to_string();
+            let key = setting.rsplit('.').next().unwrap_or_default().to_owned();
+            return vec![Problem::new(path, line_of(&text, &key), err.to_string())];
+        }
+    };
+    if let Some(config_dir) = Path::new(path).parent() {
+        config.resolve_paths(config_dir);
+    }
+
+    if let Err(why) = TextgenApi::new(&config) {
+        problems.push(Problem::new(path, line_of(&text, "backend"), why.to_string()));
+    }
+    problems.extend(check_template(&config.backend.prompt_template));
+    problems
+}
+
+/// Checks that the template only uses known placeholders, and all the required ones
+pub fn check_template(path: &str) -> Vec<Problem> {
+    let template = match fs::read_to_string(path) {
+        Ok(template) => template,
+        Err(why) => return vec![Problem::new(path, None, format!("can't read prompt template: {}", why))]
+    };
+
+    let placeholder = Regex::new(r"\[\[[^\[\]]*\]\]").unwrap();
+    let mut problems: Vec<Problem> = template.lines().enumerate()
+        .flat_map(|(index, line)| placeholder.find_iter(line).map(move |found| (index + 1, found.as_str())))
+        .filter(|(_, found)| !PLACEHOLDERS.contains(found))
+        .map(|(line, found)| Problem::new(path, Some(line), format!("unknown placeholder {}", found)))
+        .collect();
+    for required in REQUIRED_PLACEHOLDERS {
+        if !template.contains(required) {
+            problems.push(Problem::new(path, None, format!("missing required placeholder {}", required)));
+        }
+    }
+    problems
+}
+
+/// Checks a character file, returning the character if it could be loaded at all
+pub fn check_character(path: &Path) -> (Option<Character>, Vec<Problem>) {
+    let file = path.to_string_lossy();
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(why) => return (None, vec![Problem::new(&file, None, why.to_string())])
+    };
+    let json: Value = match serde_json::from_str(&text) {
+        Ok(json) => json,
+        Err(why) => return (None, vec![Problem::from_json_error(&file, &why)])
+    };
+
+    let schema: Value = serde_json::from_str(CHARACTER_SCHEMA).expect("Invalid character schema");
+    let mut problems = unknown_fields(&file, &text, &json, &schema, "");
+    let id = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    if !Character::is_valid_id(&id) {
+        problems.push(Problem::new(&file, None, "file name must be at most 32 lowercase letters, digits, - or _ followed by .json"));
+    }
+
+    let character: Character = match serde_json::from_str(&text) {
+        Ok(character) => character,
+        Err(why) => {
+            problems.push(Problem::from_json_error(&file, &why));
+            return (None, problems);
+        }
+    };
+    for (field, problem) in character.field_problems() {
+        problems.push(Problem::new(&file, line_of(&text, field), problem));
+    }
+    (Some(character), problems)
+}
+
+/// Checks every file in the characters directory
+pub fn check_characters(dir: &str) -> (usize, Vec<Problem>) {
+    let mut paths: Vec<_> = match fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).collect(),
+        Err(why) => return (0, vec![Problem::new(dir, None, why.to_string())])
+    };
+    paths.sort();
+
+    let mut problems = Vec::new();
+    for path in &paths {
+        problems.extend(check_character(path).1);
+    }
+    (paths.len(), problems)
+}
+
+/// Reports keys the schema doesn't know about, since serde silently ignores them and a typo'd optional field just does nothing
+fn unknown_fields(file: &str, text: &str, value: &Value, schema: &Value, prefix: &str) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    match value {
+        Value::Object(object) => {
+            let properties = match schema["properties"].as_object() {
+                Some(properties) => properties,
+                None => return problems
+            };
+            for (key, child) in object {
+                let name = [prefix, key].join("");
+                match properties.get(key) {
+                    Some(child_schema) => problems.extend(unknown_fields(file, text, child, child_schema, &[&name, "."].join(""))),
+                    None if schema["additionalProperties"] == false => {
+                        problems.push(Problem::new(file, line_of(text, key), format!("unknown field {}", name)));
+                    },
+                    None => {}
+                }
+            }
+        },
+        Value::Array(items) => {
+            for item in items {
+                problems.extend(unknown_fields(file, text, item, &schema["items"], prefix));
+            }
+        },
+        _ => {}
+    }
+    problems
+}
+
+/// Line of the first `key` set in the file, whether it's JSON (`"key":`), YAML (`key:`) or TOML (`key =` or `[key]`)
+fn line_of(text: &str, key: &str) -> Option<usize> {
+    let key = regex::escape(key);
+    let pattern = Regex::new(&format!(r#"(?m)"{key}"\s*:|^\s*{key}\s*[:=]|^\s*\[\[?([\w.]+\.)?{key}\]"#)).unwrap();
+    let found = pattern.find(text)?;
+    Some(text[..found.start()].matches('\n').count() + 1)
+}
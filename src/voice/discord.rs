@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+use serenity::async_trait;
+use serenity::model::prelude::{ChannelId, GuildId, UserId};
+use serenity::prelude::Context;
+use songbird::input::{Input, Reader};
+use songbird::{CoreEvent, Event, EventContext, EventHandler};
+
+use crate::botmanager::BotManagerData;
+use crate::conversation::Conversation;
+use crate::platform::discord::{self, DiscordPlatform};
+use crate::platform::{Persona, PlatformResult};
+use crate::textgen::api::TextgenApi;
+use super::utterance::Utterances;
+use super::wav::{self, DISCORD_SAMPLE_RATE, SPEECH_SAMPLE_RATE};
+
+/// How often finished utterances are picked up
+const UTTERANCE_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+
+/// songbird's config: received audio has to be decoded to be transcribed
+pub fn songbird_config() -> songbird::Config {
+    songbird::Config::default().decode_mode(songbird::driver::DecodeMode::Decode)
+}
+
+/// Joins a voice channel, leaving the one the bot was in in that server. Returns the channel left, if any.
+/// With a listener, what people say in the voice channel is transcribed and replied to.
+pub async fn join(ctx: &Context, guild: GuildId, channel: ChannelId, listener: Option<Listener>) -> PlatformResult<Option<ChannelId>> {
+    let manager = songbird::get(ctx).await.ok_or("Songbird isn't registered")?;
+    let previous = match manager.get(guild) {
+        Some(call) => call.lock().await.current_channel().map(|channel| ChannelId(channel.0)),
+        None => None
+    };
+    let (call, result) = manager.join(guild, channel).await;
+    result?;
+
+    let mut call = call.lock().await;
+    call.remove_all_global_events();
+    if let Some(listener) = listener {
+        let listener = Arc::new(listener);
+        for event in [CoreEvent::SpeakingStateUpdate, CoreEvent::VoicePacket, CoreEvent::ClientDisconnect] {
+            call.add_global_event(event.into(), ListenerHandle(listener.clone()));
+        }
+        tokio::spawn(Listener::check_utterances(Arc::downgrade(&listener)));
+    }
+    Ok(previous.filter(|previous| *previous != channel))
+}
+
+/// Leaves the voice channel the bot is in in that server, returning it
+pub async fn leave(ctx: &Context, guild: GuildId) -> PlatformResult<Option<ChannelId>> {
+    let manager = songbird::get(ctx).await.ok_or("Songbird isn't registered")?;
+    let call = match manager.get(guild) {
+        Some(call) => call,
+        None => return Ok(None)
+    };
+    let channel = call.lock().await.current_channel().map(|channel| ChannelId(channel.0));
+    manager.remove(guild).await?;
+    Ok(channel)
+}
+
+/// Queues a WAV file in the server's voice call, after whatever is already playing
+pub async fn play(ctx: &Context, guild: GuildId, audio: &[u8]) -> PlatformResult<()> {
+    let pcm = wav::decode(audio).map_err(|err| err.to_string())?;
+    let samples = wav::to_le_bytes(&pcm.to_discord_stereo());
+
+    let manager = songbird::get(ctx).await.ok_or("Songbird isn't registered")?;
+    let call = manager.get(guild).ok_or("Not in a voice channel in this server")?;
+    call.lock().await.enqueue_source(Input::float_pcm(true, Reader::from_memory(samples)));
+    Ok(())
+}
+
+/// Transcribes what people say in a voice call and replies to it in a text channel
+pub struct Listener {
+    context: Context,
+    api: Arc<TextgenApi>,
+    data: Arc<Mutex<BotManagerData>>,
+    text_channel: ChannelId,
+    /// Who's behind each audio stream
+    users: Mutex<HashMap<u32, UserId>>,
+    utterances: Mutex<Utterances>,
+}
+
+impl Listener {
+    /// `None` when speech-to-text isn't configured
+    pub fn new(context: &Context, api: Arc<TextgenApi>, data: Arc<Mutex<BotManagerData>>, text_channel: ChannelId) -> Option<Listener> {
+        let transcriber = api.transcriber()?;
+        let utterances = Utterances::new(DISCORD_SAMPLE_RATE, 2, transcriber.silence(), transcriber.min_utterance());
+        Some(Listener {
+            context: context.clone(),
+            api,
+            data,
+            text_channel,
+            users: Mutex::new(HashMap::new()),
+            utterances: Mutex::new(utterances),
+        })
+    }
+
+    /// Picks up finished utterances until the call drops the listener
+    async fn check_utterances(listener: Weak<Listener>) {
+        let mut interval = tokio::time::interval(UTTERANCE_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let listener = match listener.upgrade() {
+                Some(listener) => listener,
+                None => return
+            };
+            let finished = listener.utterances.lock().unwrap().take_finished(Instant::now());
+            for (user, samples) in finished {
+                tokio::spawn(Listener::hear(
+                    listener.context.clone(),
+                    listener.api.clone(),
+                    listener.data.clone(),
+                    listener.text_channel,
+                    UserId(user),
+                    samples
+                ));
+            }
+        }
+    }
+
+    /// Transcribes an utterance and hands it to the conversation as a message from its speaker
+    async fn hear(context: Context, api: Arc<TextgenApi>, data: Arc<Mutex<BotManagerData>>, channel: ChannelId, user: UserId, samples: Vec<i16>) {
+        let user = match user.to_user(&context).await {
+            Ok(user) if !user.bot => user,
+            Ok(_) => return,
+            Err(why) => {
+                println!("Failed getting speaker: {}", why);
+                return;
+            }
+        };
+        let transcriber = match api.transcriber() {
+            Some(transcriber) => transcriber,
+            None => return
+        };
+        let audio = wav::encode(SPEECH_SAMPLE_RATE, 1, &wav::to_speech_mono(&samples));
+        let text = match transcriber.transcribe(audio).await.map_err(|err| err.to_string()) {
+            Ok(text) => text,
+            Err(why) => {
+                println!("Failed transcribing speech: {}", why);
+                return;
+            }
+        };
+
+        // Posted by a webhook, so the transcript needs the speaker's persona name already
+        let server = discord::guild_of(&context, channel).map(|guild| guild.to_string());
+        let name = data.lock().unwrap().user_profiles.get(&user.id.to_string(), server.as_deref())
+            .filter(|profile| !profile.name.is_empty())
+            .map_or_else(|| user.name.to_owned(), |profile| profile.name.to_owned());
+        let speaker = Persona { name, avatar_url: user.face() };
+        let platform = DiscordPlatform::new(&context);
+        Conversation::new(&api, &data).on_voice_message(&platform, &channel.to_string(), &user.id.to_string(), &speaker, &text).await;
+    }
+}
+
+struct ListenerHandle(Arc<Listener>);
+
+#[async_trait]
+impl EventHandler for ListenerHandle {
+    async fn act(&self, event: &EventContext<'_>) -> Option<Event> {
+        let listener = &self.0;
+        match event {
+            EventContext::SpeakingStateUpdate(speaking) => {
+                if let Some(user) = speaking.user_id {
+                    listener.users.lock().unwrap().insert(speaking.ssrc, UserId(user.0));
+                }
+            },
+            EventContext::VoicePacket(packet) => {
+                let user = listener.users.lock().unwrap().get(&packet.packet.ssrc).copied();
+                if let (Some(user), Some(audio)) = (user, packet.audio) {
+                    listener.utterances.lock().unwrap().push(user.0, audio, Instant::now());
+                }
+            },
+            EventContext::ClientDisconnect(disconnect) => {
+                listener.utterances.lock().unwrap().discard(disconnect.user_id.0);
+                listener.users.lock().unwrap().retain(|_, user| user.0 != disconnect.user_id.0);
+            },
+            _ => {}
+        }
+        None
+    }
+}
@@ -0,0 +1,6 @@
+#[cfg(feature = "voice")]
+pub mod discord;
+pub mod stt;
+pub mod tts;
+pub mod utterance;
+pub mod wav;
@@ -0,0 +1,61 @@
+use std::error::Error;
+use std::time::Duration;
+
+use reqwest::Client;
+use reqwest::multipart::{Form, Part};
+use serde_json::Value;
+
+use crate::config::VoiceConfig;
+
+/// Client for a Whisper-compatible speech-to-text server
+pub struct Transcriber {
+    client: Client,
+    config: VoiceConfig,
+}
+
+impl Transcriber {
+    /// `None` when no speech-to-text URL is configured
+    pub fn new(config: &VoiceConfig) -> Option<Transcriber> {
+        config.stt_url.as_ref()?;
+        Some(Transcriber { client: Client::new(), config: config.clone() })
+    }
+
+    /// How long a pause ends an utterance
+    pub fn silence(&self) -> Duration {
+        Duration::from_millis(self.config.silence_ms)
+    }
+
+    /// Shortest utterance worth transcribing
+    pub fn min_utterance(&self) -> Duration {
+        Duration::from_millis(self.config.min_utterance_ms)
+    }
+
+    /// Transcribes a WAV file, the way OpenAI's `/v1/audio/transcriptions` and whisper.cpp's `/inference` take it
+    pub async fn transcribe(&self, wav: Vec<u8>) -> Result<String, Box<dyn Error>> {
+        let mut form = Form::new()
+            .part("file", Part::bytes(wav).file_name("speech.wav").mime_str("audio/wav")?)
+            .text("model", self.config.stt_model.to_owned())
+            .text("response_format", "json");
+        if !self.config.stt_language.is_empty() {
+            form = form.text("language", self.config.stt_language.to_owned());
+        }
+        let response = self.client.post(self.config.stt_url.as_deref().unwrap_or_default())
+            .timeout(Duration::from_secs(self.config.timeout_secs))
+            .multipart(form)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        Ok(parse_transcription(&response))
+    }
+}
+
+/// Text out of the server's response: `{"text": ...}` or plain text
+pub fn parse_transcription(response: &str) -> String {
+    let text = match serde_json::from_str::<Value>(response) {
+        Ok(json) => json["text"].as_str().map(String::from).unwrap_or_default(),
+        Err(_) => String::from(response)
+    };
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
@@ -0,0 +1,52 @@
+use std::error::Error;
+use std::time::Duration;
+
+use reqwest::Client;
+
+use crate::config::VoiceConfig;
+use crate::textgen::character::Character;
+
+/// Client for a local text-to-speech server
+pub struct SpeechSynthesizer {
+    client: Client,
+    config: VoiceConfig,
+}
+
+impl SpeechSynthesizer {
+    /// `None` when no text-to-speech URL is configured
+    pub fn new(config: &VoiceConfig) -> Option<SpeechSynthesizer> {
+        config.tts_url.as_ref()?;
+        Some(SpeechSynthesizer { client: Client::new(), config: config.clone() })
+    }
+
+    /// The character's own voice, or the configured default
+    pub fn voice_for<'a>(&'a self, character: &'a Character) -> &'a str {
+        match character.voice.is_empty() {
+            true => &self.config.default_voice,
+            false => &character.voice
+        }
+    }
+
+    /// Speaks the text, returning a WAV file
+    pub async fn synthesize(&self, text: &str, voice: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut query = vec![("text", text)];
+        if !voice.is_empty() {
+            query.push((&self.config.tts_voice_param, voice));
+        }
+        let response = self.client.get(self.config.tts_url.as_deref().unwrap_or_default())
+            .timeout(Duration::from_secs(self.config.timeout_secs))
+            .query(&query)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(response.bytes().await?.to_vec())
+    }
+}
+
+/// What's read out of a reply: markdown emphasis would be spelled out by some voices
+pub fn spoken_text(reply: &str) -> String {
+    reply.replace(['*', '~', '`'], "")
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Frames quieter than this are silence. Discord sends a few silent frames when someone stops talking.
+const SILENCE_LEVEL: i16 = 64;
+
+/// Utterances are cut at this length even without a pause, Whisper models hear 30 seconds at a time
+const MAX_UTTERANCE: Duration = Duration::from_secs(30);
+
+struct Speech {
+    samples: Vec<i16>,
+    started: Instant,
+    last_heard: Instant,
+}
+
+/// Collects what each user says in a voice channel until they pause
+pub struct Utterances {
+    sample_rate: u32,
+    channels: u16,
+    silence: Duration,
+    min_length: Duration,
+    speakers: HashMap<u64, Speech>,
+}
+
+impl Utterances {
+    /// An utterance ends after `silence` without hearing its speaker. Utterances shorter than `min_length` are dropped,
+    /// they're usually coughs and clicks.
+    pub fn new(sample_rate: u32, channels: u16, silence: Duration, min_length: Duration) -> Utterances {
+        Utterances { sample_rate, channels, silence, min_length, speakers: HashMap::new() }
+    }
+
+    /// Adds a frame of audio from `user`, received at `now`
+    pub fn push(&mut self, user: u64, frame: &[i16], now: Instant) {
+        if frame.iter().all(|sample| sample.unsigned_abs() < SILENCE_LEVEL as u16) {
+            return;
+        }
+        let speech = self.speakers.entry(user).or_insert_with(|| Speech { samples: Vec::new(), started: now, last_heard: now });
+        speech.samples.extend_from_slice(frame);
+        speech.last_heard = now;
+    }
+
+    /// Forgets what a user said so far, when they leave
+    pub fn discard(&mut self, user: u64) {
+        self.speakers.remove(&user);
+    }
+
+    /// Takes the utterances whose speaker paused (or talked for too long) by `now`, as user and samples
+    pub fn take_finished(&mut self, now: Instant) -> Vec<(u64, Vec<i16>)> {
+        let finished: Vec<u64> = self.speakers.iter()
+            .filter(|(_, speech)| now.duration_since(speech.last_heard) >= self.silence || now.duration_since(speech.started) >= MAX_UTTERANCE)
+            .map(|(user, _)| *user)
+            .collect();
+        let min_samples = (self.min_length.as_secs_f64() * self.sample_rate as f64) as usize * self.channels as usize;
+        finished.into_iter()
+            .filter_map(|user| self.speakers.remove(&user).map(|speech| (user, speech.samples)))
+            .filter(|(_, samples)| samples.len() >= min_samples)
+            .collect()
+    }
+}
@@ -0,0 +1,121 @@
+use std::error::Error;
+
+/// Sample rate Discord voice runs at
+pub const DISCORD_SAMPLE_RATE: u32 = 48000;
+
+/// Sample rate speech recognition models work at
+pub const SPEECH_SAMPLE_RATE: u32 = 16000;
+
+/// Decoded audio, samples interleaved by channel
+#[derive(Debug, PartialEq)]
+pub struct Pcm {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub samples: Vec<f32>,
+}
+
+/// Decodes a WAV file with 16 bit integer or 32 bit float samples, what TTS servers answer with
+pub fn decode(wav: &[u8]) -> Result<Pcm, Box<dyn Error>> {
+    if wav.len() < 12 || &wav[0..4] != b"RIFF" || &wav[8..12] != b"WAVE" {
+        return Err(string_error::new_err("Not a WAV file"));
+    }
+
+    let mut format = None;
+    let mut position = 12;
+    while position + 8 <= wav.len() {
+        let id = &wav[position..position + 4];
+        let size = u32::from_le_bytes(wav[position + 4..position + 8].try_into()?) as usize;
+        let body = &wav[position + 8..(position + 8 + size).min(wav.len())];
+        match id {
+            b"fmt " if body.len() >= 16 => {
+                let tag = u16::from_le_bytes([body[0], body[1]]);
+                let channels = u16::from_le_bytes([body[2], body[3]]);
+                let sample_rate = u32::from_le_bytes(body[4..8].try_into()?);
+                let bits = u16::from_le_bytes([body[14], body[15]]);
+                format = Some((tag, channels, sample_rate, bits));
+            },
+            b"data" => {
+                let (tag, channels, sample_rate, bits) = format.ok_or("WAV data before its format")?;
+                let samples = match (tag, bits) {
+                    // WAVE_FORMAT_EXTENSIBLE is used as is for the common cases
+                    (1, 16) | (0xFFFE, 16) => body.chunks_exact(2)
+                        .map(|sample| i16::from_le_bytes([sample[0], sample[1]]) as f32 / 32768.0)
+                        .collect(),
+                    (3, 32) | (0xFFFE, 32) => body.chunks_exact(4)
+                        .map(|sample| f32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]]))
+                        .collect(),
+                    _ => return Err(string_error::into_err(format!("Unsupported WAV format {} with {} bit samples", tag, bits)))
+                };
+                if channels == 0 || sample_rate == 0 {
+                    return Err(string_error::new_err("Invalid WAV format"));
+                }
+                return Ok(Pcm { sample_rate, channels, samples });
+            },
+            _ => {}
+        }
+        // Chunks are padded to an even size
+        position += 8 + size + size % 2;
+    }
+    Err(string_error::new_err("WAV file has no data"))
+}
+
+impl Pcm {
+    /// Converts to interleaved stereo at `DISCORD_SAMPLE_RATE`, with linear interpolation
+    pub fn to_discord_stereo(&self) -> Vec<f32> {
+        let channels = self.channels as usize;
+        let frames = self.samples.len() / channels;
+        let frame = |index: usize| {
+            let start = index.min(frames.saturating_sub(1)) * channels;
+            let left = self.samples.get(start).copied().unwrap_or(0.0);
+            let right = if channels > 1 { self.samples[start + 1] } else { left };
+            (left, right)
+        };
+
+        let output_frames = (frames as u64 * DISCORD_SAMPLE_RATE as u64 / self.sample_rate as u64) as usize;
+        let mut output = Vec::with_capacity(output_frames * 2);
+        for index in 0..output_frames {
+            let position = index as f64 * self.sample_rate as f64 / DISCORD_SAMPLE_RATE as f64;
+            let before = position.floor() as usize;
+            let weight = (position - before as f64) as f32;
+            let (left_before, right_before) = frame(before);
+            let (left_after, right_after) = frame(before + 1);
+            output.push(left_before + (left_after - left_before) * weight);
+            output.push(right_before + (right_after - right_before) * weight);
+        }
+        output
+    }
+}
+
+/// Raw little endian `f32` samples, what songbird plays
+pub fn to_le_bytes(samples: &[f32]) -> Vec<u8> {
+    samples.iter().flat_map(|sample| sample.to_le_bytes()).collect()
+}
+
+/// Turns Discord's 48 kHz stereo into 16 kHz mono for speech recognition, averaging every 3 frames
+pub fn to_speech_mono(stereo: &[i16]) -> Vec<i16> {
+    stereo.chunks_exact(6)
+        .map(|frames| (frames.iter().map(|&sample| sample as i32).sum::<i32>() / 6) as i16)
+        .collect()
+}
+
+/// Encodes 16 bit samples as a WAV file
+pub fn encode(sample_rate: u32, channels: u16, samples: &[i16]) -> Vec<u8> {
+    let data_size = (samples.len() * 2) as u32;
+    let mut wav = Vec::with_capacity(44 + data_size as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_size).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&channels.to_le_bytes());
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    wav.extend_from_slice(&(sample_rate * channels as u32 * 2).to_le_bytes());
+    wav.extend_from_slice(&(channels * 2).to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_size.to_le_bytes());
+    for sample in samples {
+        wav.extend_from_slice(&sample.to_le_bytes());
+    }
+    wav
+}
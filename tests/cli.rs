@@ -0,0 +1,82 @@
+use std::fs;
+
+use clap::Parser;
+use uc207::cli::{self, Cli, CliCommand};
+
+fn temp_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("uc207-cli-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("characters")).unwrap();
+    dir
+}
+
+#[test]
+fn config_defaults_to_data_dir() {
+    let cli = Cli::parse_from(["uc207", "--data-dir", "/srv/bot", "validate"]);
+
+    assert!(matches!(cli.command, Some(CliCommand::Validate)));
+    assert_eq!(cli.config_path(), "/srv/bot/config.json");
+}
+
+#[test]
+fn config_file_is_found_in_any_format() {
+    let dir = temp_dir("formats");
+    fs::write(dir.join("config.yaml"), "sampling:\n  temperature: 0.5\n").unwrap();
+    let cli = Cli::parse_from(["uc207", "--data-dir", dir.to_str().unwrap()]);
+
+    assert_eq!(cli.config_path(), dir.join("config.yaml").to_str().unwrap());
+    assert_eq!(cli.load_config().unwrap().sampling.temperature, 0.5);
+}
+
+#[test]
+fn flags_override_config() {
+    let dir = temp_dir("flags");
+    fs::write(dir.join("config.toml"), "[storage]\ncharacters_dir = \"bots\"\n[limits]\nhistory_messages = 4\n").unwrap();
+    let data_dir = dir.to_str().unwrap();
+
+    let cli = Cli::parse_from(["uc207", "chat", "alice", "--data-dir", data_dir]);
+    assert!(matches!(cli.command, Some(CliCommand::Chat { ref character_id }) if character_id == "alice"));
+    assert_eq!(cli.load_config().unwrap().storage.characters_dir, dir.join("bots").to_str().unwrap());
+
+    let cli = Cli::parse_from(["uc207", "--data-dir", data_dir, "--characters-dir", "/srv/characters", "--set", "limits.history_messages=20"]);
+    let config = cli.load_config().unwrap();
+    assert_eq!(config.storage.characters_dir, "/srv/characters");
+    assert_eq!(config.limits.history_messages, 20);
+}
+
+#[test]
+fn token_file_flag_wins_over_config_token() {
+    let dir = temp_dir("token");
+    fs::write(dir.join("config.toml"), "[discord]\ntoken = \"from-config\"\n").unwrap();
+    fs::write(dir.join("token.txt"), "from-flag\n").unwrap();
+    let data_dir = dir.to_str().unwrap();
+
+    let cli = Cli::parse_from(["uc207", "--data-dir", data_dir, "--token-file", dir.join("token.txt").to_str().unwrap()]);
+
+    assert_eq!(cli.load_config().unwrap().discord_token().unwrap().as_deref(), Some("from-flag"));
+}
+
+#[test]
+fn import_copies_valid_character() {
+    let dir = temp_dir("import");
+    let file = dir.join("Alice.json");
+    fs::write(&file, r#"{"char_name": "Alice", "char_description": "", "char_persona": "A robot.", "example_dialogue": [], "avatar_url": ""}"#).unwrap();
+    let characters_dir = dir.join("characters");
+    let characters_dir = characters_dir.to_str().unwrap();
+
+    assert_eq!(cli::import(characters_dir, &file, None, false).unwrap(), "alice");
+    assert!(dir.join("characters/alice.json").exists());
+    // Existing characters are only replaced with --force
+    assert!(cli::import(characters_dir, &file, None, false).is_err());
+    assert!(cli::import(characters_dir, &file, None, true).is_ok());
+}
+
+#[test]
+fn import_rejects_invalid_character() {
+    let dir = temp_dir("import-invalid");
+    let file = dir.join("bad.json");
+    fs::write(&file, r#"{"char_name": "", "char_description": "", "char_persona": "A robot.", "example_dialogue": [], "avatar_url": ""}"#).unwrap();
+
+    assert!(cli::import(dir.join("characters").to_str().unwrap(), &file, None, false).is_err());
+    assert!(!dir.join("characters/bad.json").exists());
+}
@@ -0,0 +1,81 @@
+use serde_json::{json, Value};
+use serenity::model::prelude::command::Command;
+use uc207::commands::{self, character, definitions, list};
+use uc207::textgen::character::Character;
+
+/// What Discord would answer for the definitions, with the fields it adds
+fn registered() -> Vec<Command> {
+    definitions().iter()
+        .enumerate()
+        .map(|(index, definition)| {
+            let mut command: Value = Value::Object(definition.0.iter().map(|(key, value)| (key.to_string(), value.clone())).collect());
+            command["id"] = json!((index + 1).to_string());
+            command["application_id"] = json!("42");
+            command["type"] = json!(1);
+            command["version"] = json!("1");
+            command["default_member_permissions"] = Value::Null;
+            command["dm_permission"] = json!(true);
+            serde_json::from_value(command).unwrap()
+        })
+        .collect()
+}
+
+#[test]
+fn registered_definitions_are_up_to_date() {
+    let mut existing = registered();
+    existing.reverse();
+
+    assert!(commands::is_up_to_date(&definitions(), &existing));
+}
+
+#[test]
+fn changed_definitions_need_sync() {
+    let mut existing = registered();
+    existing[0].description = String::from("Old description");
+
+    assert!(!commands::is_up_to_date(&definitions(), &existing));
+}
+
+#[test]
+fn missing_or_extra_commands_need_sync() {
+    let mut existing = registered();
+    existing.pop();
+
+    assert!(!commands::is_up_to_date(&definitions(), &existing));
+    assert!(!commands::is_up_to_date(&[], &registered()));
+    assert!(commands::is_up_to_date(&[], &[]));
+}
+
+#[test]
+fn full_list_page_fits_in_an_embed() {
+    let character = Character { char_name: "N".repeat(200), char_description: "D".repeat(1000), ..Default::default() };
+    let (name, value) = list::entry(&"i".repeat(32), &character);
+
+    assert!(value.chars().count() <= commands::MAX_FIELD_LENGTH);
+    // Title, description and footer take up less than 200 characters
+    assert!(list::PAGE_SIZE * (name.chars().count() + value.chars().count()) + 200 <= 6000);
+}
+
+#[test]
+fn list_buttons_fit_discord_custom_ids() {
+    assert_eq!(list::button_id(42, 2, "robot"), "list:42:2:robot");
+    let long = list::button_id(u64::MAX, 1000, &"é".repeat(200));
+    assert!(long.starts_with("list:18446744073709551615:1000:é"));
+    assert_eq!(long.chars().count(), 100);
+}
+
+#[test]
+fn uploaded_avatars_replace_the_previous_one() {
+    let dir = std::env::temp_dir().join(format!("uc207-avatars-{}", std::process::id()));
+    let dir = dir.to_string_lossy();
+    assert_eq!(character::avatar_extension("image/jpeg"), Some("jpg"));
+    assert_eq!(character::avatar_extension("text/plain"), None);
+
+    assert_eq!(Character::save_avatar(&dir, "alice", "png", b"first").unwrap(), "alice.png");
+    assert_eq!(Character::save_avatar(&dir, "alice", "jpg", b"second").unwrap(), "alice.jpg");
+
+    assert!(!std::path::Path::new(&*dir).join("alice.png").exists());
+    assert_eq!(std::fs::read(std::path::Path::new(&*dir).join("alice.jpg")).unwrap(), b"second");
+    Character::delete_avatar(&dir, "alice").unwrap();
+    assert!(!std::path::Path::new(&*dir).join("alice.jpg").exists());
+}
@@ -0,0 +1,285 @@
+//! In-process stand-in for oobabooga's textgen server, speaking the same payloads as
+//! `TextgenApi::request` (gradio's `/run/textgen`) and `TextgenApi::check_model` (`/api/v1/model`).
+//! It also answers like llama.cpp's `/completion`, OpenAI's `/v1/chat/completions`, a `/caption` endpoint,
+//! AUTOMATIC1111's `/sdapi/v1/txt2img`, Coqui's `/api/tts` and OpenAI's `/v1/audio/transcriptions`.
+
+// Each test binary only uses part of the helpers
+#![allow(dead_code)]
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use uc207::config::{Config, ConfigFormat};
+
+/// What the mock answers to a single generation request
+#[derive(Clone)]
+pub enum MockResponse {
+    /// Echoes the prompt followed by this text, like the real server does
+    Reply(String),
+    /// Answers with the given HTTP status and an empty body
+    Status(u16),
+    /// Answers 200 with this raw body
+    Raw(String),
+}
+
+#[derive(Default)]
+struct MockState {
+    script: VecDeque<MockResponse>,
+    default_reply: String,
+    model: String,
+    latency: Duration,
+    failures_left: u32,
+    requests: Vec<Value>,
+    model_requests: u32,
+    vision_requests: Vec<(String, Value)>,
+    caption: String,
+    speech_requests: Vec<Vec<(String, String)>>,
+    transcription: String,
+    transcription_requests: Vec<String>,
+}
+
+#[derive(Clone)]
+pub struct MockTextgen {
+    pub port: u16,
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockTextgen {
+    pub async fn start() -> MockTextgen {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("Failed binding mock server");
+        let port = listener.local_addr().unwrap().port();
+        let state = Arc::new(Mutex::new(MockState {
+            default_reply: String::from(" Hello there!"),
+            model: String::from("mock-model-7b"),
+            caption: String::from("a cat on a keyboard"),
+            transcription: String::from(" What's the weather like?"),
+            ..Default::default()
+        }));
+
+        let server_state = state.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = match listener.accept().await {
+                    Ok(connection) => connection,
+                    Err(_) => return
+                };
+                tokio::spawn(handle(stream, server_state.clone()));
+            }
+        });
+
+        MockTextgen { port, state }
+    }
+
+    pub fn textgen_url(&self) -> String {
+        format!("http://127.0.0.1:{}/run/textgen", self.port)
+    }
+
+    pub fn model_url(&self) -> String {
+        format!("http://127.0.0.1:{}/api/v1/model", self.port)
+    }
+
+    /// Queues responses for the next requests, after which the default reply is used again
+    pub fn script(&self, responses: Vec<MockResponse>) {
+        self.state.lock().unwrap().script.extend(responses);
+    }
+
+    pub fn set_default_reply(&self, reply: &str) {
+        self.state.lock().unwrap().default_reply = String::from(reply);
+    }
+
+    pub fn set_latency(&self, latency: Duration) {
+        self.state.lock().unwrap().latency = latency;
+    }
+
+    /// Makes the next `count` requests of any kind fail with a 500
+    pub fn fail_next(&self, count: u32) {
+        self.state.lock().unwrap().failures_left = count;
+    }
+
+    /// Decoded generation requests received so far, as `[prompt, parameters]`
+    pub fn requests(&self) -> Vec<Value> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// Multimodal, captioning and txt2img requests received so far, as path and body
+    pub fn vision_requests(&self) -> Vec<(String, Value)> {
+        self.state.lock().unwrap().vision_requests.clone()
+    }
+
+    /// Text-to-speech requests received so far, as their query parameters
+    pub fn speech_requests(&self) -> Vec<Vec<(String, String)>> {
+        self.state.lock().unwrap().speech_requests.clone()
+    }
+
+    /// Transcription requests received so far, as their multipart bodies
+    pub fn transcription_requests(&self) -> Vec<String> {
+        self.state.lock().unwrap().transcription_requests.clone()
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://127.0.0.1:{}{}", self.port, path)
+    }
+
+    pub fn model_requests(&self) -> u32 {
+        self.state.lock().unwrap().model_requests
+    }
+}
+
+async fn handle(mut stream: TcpStream, state: Arc<Mutex<MockState>>) {
+    let (path, body) = match read_request(&mut stream).await {
+        Some(request) => request,
+        None => return
+    };
+
+    let latency = state.lock().unwrap().latency;
+    tokio::time::sleep(latency).await;
+
+    let (status, content_type, response) = match path.starts_with("/api/tts") {
+        true => (200, "audio/wav", speak(&path, &state)),
+        false => {
+            let (status, response) = respond(&path, &body, &state);
+            (status, "application/json", response.into_bytes())
+        }
+    };
+    let mut reply = format!(
+        "HTTP/1.1 {} MOCK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status, content_type, response.len()
+    ).into_bytes();
+    reply.extend(response);
+    let _ = stream.write_all(&reply).await;
+    let _ = stream.shutdown().await;
+}
+
+fn respond(path: &str, body: &str, state: &Mutex<MockState>) -> (u16, String) {
+    let mut state = state.lock().unwrap();
+    if path.ends_with("/model") {
+        state.model_requests += 1;
+    }
+    if state.failures_left > 0 {
+        state.failures_left -= 1;
+        return (500, String::new());
+    }
+
+    if path.ends_with("/model") {
+        return (200, json!({ "result": state.model }).to_string());
+    }
+
+    if path == "/v1/audio/transcriptions" {
+        state.transcription_requests.push(body.to_owned());
+        return (200, json!({ "text": state.transcription }).to_string());
+    }
+
+    if ["/completion", "/v1/chat/completions", "/caption", "/sdapi/v1/txt2img"].contains(&path) {
+        state.vision_requests.push((path.to_owned(), serde_json::from_str(body).unwrap_or(Value::Null)));
+        if path == "/caption" {
+            return (200, json!({ "caption": state.caption }).to_string());
+        }
+        if path == "/sdapi/v1/txt2img" {
+            // A PNG signature is enough of an image for the tests
+            return (200, json!({ "images": ["iVBORw0KGgo="] }).to_string());
+        }
+        let reply = state.script.pop_front().unwrap_or_else(|| MockResponse::Reply(state.default_reply.clone()));
+        let reply = match reply {
+            MockResponse::Reply(reply) => reply,
+            MockResponse::Status(status) => return (status, String::new()),
+            MockResponse::Raw(raw) => return (200, raw),
+        };
+        return match path {
+            "/completion" => (200, json!({ "content": reply }).to_string()),
+            _ => (200, json!({ "choices": [{ "message": { "role": "assistant", "content": reply } }] }).to_string())
+        };
+    }
+
+    // The prompt and parameters are a JSON string nested inside gradio's `data` array
+    let outer: Value = serde_json::from_str(body).unwrap_or(Value::Null);
+    let inner: Value = outer["data"][0].as_str()
+        .and_then(|inner| serde_json::from_str(inner).ok())
+        .unwrap_or(Value::Null);
+    let prompt = inner[0].as_str().unwrap_or("").to_owned();
+    state.requests.push(inner);
+
+    let next = state.script.pop_front().unwrap_or_else(|| MockResponse::Reply(state.default_reply.clone()));
+    match next {
+        MockResponse::Reply(reply) => {
+            let generated = [prompt, reply].join("");
+            (200, json!({ "data": [generated] }).to_string())
+        },
+        MockResponse::Status(status) => (status, String::new()),
+        MockResponse::Raw(raw) => (200, raw),
+    }
+}
+
+/// A tenth of a second of silence for any text, as a 22.05 kHz mono WAV file like Coqui's default models make
+fn speak(path: &str, state: &Mutex<MockState>) -> Vec<u8> {
+    let url = reqwest::Url::parse(&format!("http://mock{}", path)).unwrap();
+    state.lock().unwrap().speech_requests.push(url.query_pairs().into_owned().collect());
+    uc207::voice::wav::encode(22050, 1, &[0; 2205])
+}
+
+async fn read_request(stream: &mut TcpStream) -> Option<(String, String)> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let header_end = loop {
+        let read = stream.read(&mut chunk).await.ok()?;
+        if read == 0 {
+            return None;
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+        if let Some(position) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+            break position + 4;
+        }
+    };
+
+    let headers = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+    let path = headers.split_whitespace().nth(1).unwrap_or("/").to_owned();
+    let content_length = headers.lines()
+        .find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            if name.eq_ignore_ascii_case("content-length") { value.trim().parse::<usize>().ok() } else { None }
+        })
+        .unwrap_or(0);
+
+    while buffer.len() < header_end + content_length {
+        let read = stream.read(&mut chunk).await.ok()?;
+        if read == 0 {
+            break;
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+
+    let body = String::from_utf8_lossy(&buffer[header_end..]).to_string();
+    Some((path, body))
+}
+
+/// A config pointing at the given endpoints with fast retries, so failure tests don't take forever
+pub fn config(endpoints: Value, prompt_template: &str) -> Config {
+    let json = json!({
+        "backend": {
+            "endpoints": endpoints,
+            "retry": { "max_attempts": 2, "initial_backoff_ms": 10, "max_backoff_ms": 20, "timeout_secs": 1 },
+            "circuit_breaker": { "failure_threshold": 2, "cooldown_secs": 60, "health_check_interval_secs": 30 },
+            "prompt_template": prompt_template
+        }
+    });
+    Config::parse(&json.to_string(), ConfigFormat::Json).expect("Invalid test config")
+}
+
+pub fn endpoint(mock: &MockTextgen, priority: i32) -> Value {
+    json!({ "textgen_url": mock.textgen_url(), "model_url": mock.model_url(), "priority": priority })
+}
+
+/// An endpoint nothing listens on
+pub fn dead_endpoint(priority: i32) -> Value {
+    json!({ "textgen_url": "http://127.0.0.1:9/run/textgen", "model_url": "http://127.0.0.1:9/api/v1/model", "priority": priority })
+}
+
+/// Writes a prompt template to a unique file in the temp dir and returns its path
+pub fn write_template(name: &str, template: &str) -> String {
+    let path = std::env::temp_dir().join(format!("uc207-{}-{}.txt", name, std::process::id()));
+    std::fs::write(&path, template).expect("Failed writing template");
+    path.to_string_lossy().to_string()
+}
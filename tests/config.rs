@@ -0,0 +1,118 @@
+use std::fs;
+
+use serde_json::json;
+use uc207::config::{self, Config, ConfigFormat};
+
+const TOML: &str = r#"
+[backend]
+endpoints = [{ textgen_url = "http://a/run/textgen", model_url = "http://a/api/v1/model" }]
+
+[sampling]
+temperature = 0.5
+"#;
+
+const YAML: &str = "
+backend:
+  endpoints:
+    - textgen_url: http://a/run/textgen
+      model_url: http://a/api/v1/model
+sampling:
+  temperature: 0.5
+";
+
+const JSON: &str = r#"{
+    "backend": {"endpoints": [{"textgen_url": "http://a/run/textgen", "model_url": "http://a/api/v1/model"}]},
+    "sampling": {"temperature": 0.5}
+}"#;
+
+#[test]
+fn formats_are_equivalent() {
+    for (text, format) in [(TOML, ConfigFormat::Toml), (YAML, ConfigFormat::Yaml), (JSON, ConfigFormat::Json)] {
+        let config = Config::parse(text, format).unwrap();
+        assert_eq!(config.backend.endpoints[0].textgen_url, "http://a/run/textgen");
+        assert_eq!(config.sampling.temperature, 0.5);
+        // Everything else keeps its default
+        assert_eq!(config.sampling.top_p, 0.73);
+        assert_eq!(config.limits.history_messages, 10);
+        assert!(config.matrix.is_none());
+    }
+}
+
+#[test]
+fn format_follows_extension() {
+    assert_eq!(ConfigFormat::of("bot/config.toml"), ConfigFormat::Toml);
+    assert_eq!(ConfigFormat::of("config.yml"), ConfigFormat::Yaml);
+    assert_eq!(ConfigFormat::of("config.json"), ConfigFormat::Json);
+}
+
+#[test]
+fn legacy_flat_json_is_migrated() {
+    let legacy = json!({
+        "textgen_url": "http://a/run/textgen",
+        "model_url": "http://a/api/v1/model",
+        "temperature": 0.3,
+        "num_beams": 2
+    });
+
+    let config = Config::parse(&legacy.to_string(), ConfigFormat::Json).unwrap();
+
+    assert_eq!(config.backend.textgen_url.as_deref(), Some("http://a/run/textgen"));
+    assert_eq!(config.sampling.temperature, 0.3);
+    assert_eq!(config.sampling.num_beams, 2);
+}
+
+#[test]
+fn unknown_settings_are_errors() {
+    let err = Config::parse("[sampling]\ntemprature = 0.5", ConfigFormat::Toml).err().unwrap();
+
+    assert!(err.to_string().contains("temprature"));
+}
+
+#[test]
+fn env_overrides_file() {
+    let mut value = config::parse_value(TOML, ConfigFormat::Toml).ok().unwrap();
+    let vars = [
+        ("UC207_SAMPLING_TEMPERATURE", "0.9"),
+        ("UC207_BACKEND_RETRY_MAX_ATTEMPTS", "7"),
+        ("UC207_BACKEND_PROMPT_TEMPLATE", "other.txt"),
+        ("UC207_MATRIX_HOMESERVER_URL", "http://localhost:8008"),
+        ("UC207_MATRIX_ACCESS_TOKEN", "secret"),
+        ("UC207_MATRIX_USER_ID", "@bot:localhost"),
+        ("UC207_DATA_DIR", "/not/a/setting"),
+        ("PATH", "/usr/bin"),
+    ].map(|(name, value)| (String::from(name), String::from(value)));
+
+    config::apply_env(&mut value, vars).unwrap();
+    let config = Config::from_value(value).unwrap();
+
+    assert_eq!(config.sampling.temperature, 0.9);
+    assert_eq!(config.backend.retry.max_attempts, 7);
+    assert_eq!(config.backend.prompt_template, "other.txt");
+    assert_eq!(config.matrix.unwrap().command_prefix, "!");
+}
+
+#[test]
+fn overrides_apply_last() {
+    let mut value = config::parse_value(TOML, ConfigFormat::Toml).ok().unwrap();
+    config::apply_env(&mut value, [(String::from("UC207_SAMPLING_TEMPERATURE"), String::from("0.9"))]).unwrap();
+    config::apply_overrides(&mut value, &[String::from("sampling.temperature=0.1"), String::from("logging.log_requests=false")]).unwrap();
+    let config = Config::from_value(value.clone()).unwrap();
+
+    assert_eq!(config.sampling.temperature, 0.1);
+    assert!(!config.logging.log_requests);
+    assert!(config::apply_overrides(&mut value, &[String::from("sampling.temprature=0.1")]).is_err());
+    assert!(config::apply_overrides(&mut value, &[String::from("sampling.temperature=hot")]).is_err());
+}
+
+#[test]
+fn relative_paths_follow_config_file() {
+    let dir = std::env::temp_dir().join(format!("uc207-config-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("config.toml");
+    fs::write(&path, "[storage]\ncharacters_dir = \"bots\"\n[backend]\nprompt_template = \"/etc/uc207/template.txt\"\n").unwrap();
+
+    let config = Config::load(path.to_str().unwrap(), &[]).unwrap();
+
+    assert_eq!(config.storage.characters_dir, dir.join("bots").to_str().unwrap());
+    assert_eq!(config.backend.prompt_template, "/etc/uc207/template.txt");
+}
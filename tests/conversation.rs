@@ -0,0 +1,849 @@
+mod common;
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde_json::json;
+use uc207::botmanager::BotManagerData;
+use uc207::config::{MultimodalFormat, SpeakerNames};
+use uc207::conversation::{build_history, speaker_name, Conversation, BACKEND_UNAVAILABLE_REACTION};
+use uc207::fences::{Fence, FENCE_MESSAGE};
+use uc207::notes::AuthorsNote;
+use uc207::platform::memory::MemoryPlatform;
+use uc207::platform::{ChatPlatform, Persona, PlatformMessage, SwipeControls};
+use uc207::swipes::SwipeAction;
+use uc207::textgen::api::{Message, TextgenApi};
+use uc207::store::Store;
+use uc207::textgen::character::Character;
+use uc207::users::{UserProfile, UserProfiles};
+
+use common::{MockResponse, MockTextgen};
+
+const CHANNEL: &str = "1234";
+
+fn data(invited: bool) -> Mutex<BotManagerData> {
+    let mut characters = HashMap::new();
+    characters.insert(String::from("alice"), Character {
+        char_name: String::from("Alice"),
+        char_persona: String::from("Alice is a cheerful robot."),
+        avatar_url: String::from("https://example.com/alice.png"),
+        ..Default::default()
+    });
+    let mut data = BotManagerData::new(characters);
+    if invited {
+        data.invited_characters.insert(String::from(CHANNEL), String::from("alice"));
+    }
+    Mutex::new(data)
+}
+
+fn api(mock: &MockTextgen, name: &str) -> TextgenApi {
+    let template = common::write_template(name, "[[CONTEXT]]\n[[NAME]]:");
+    TextgenApi::new(&common::config(json!([common::endpoint(mock, 0)]), &template)).unwrap()
+}
+
+fn vision_api(mock: &MockTextgen, name: &str, vision: &str) -> TextgenApi {
+    let template = common::write_template(name, "[[CONTEXT]]\n[[NAME]]:");
+    let mut config = common::config(json!([common::endpoint(mock, 0)]), &template);
+    match vision {
+        "caption" => config.vision.caption_url = Some(mock.url("/caption")),
+        "openai" => {
+            config.vision.multimodal_url = Some(mock.url("/v1/chat/completions"));
+            config.vision.multimodal_format = MultimodalFormat::OpenAi;
+        },
+        _ => config.vision.multimodal_url = Some(mock.url("/completion"))
+    }
+    TextgenApi::new(&config).unwrap()
+}
+
+/// Starts of a PNG file, enough for the MIME type to be recognized
+const PNG: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+fn message(author: &str, content: &str, is_bot: bool, is_own: bool) -> PlatformMessage {
+    PlatformMessage {
+        id: String::new(),
+        author_id: String::from(author),
+        author_name: String::from(author),
+        display_name: None,
+        nickname: None,
+        content: String::from(content),
+        is_bot,
+        is_own,
+        images: Vec::new(),
+        reply_to: None
+    }
+}
+
+#[test]
+fn history_is_oldest_first_and_skips_empty_messages() {
+    let messages = vec![
+        message("Bob", "second", false, false),
+        message("Bob", "", false, false),
+        message("Carol", "first", false, false),
+    ];
+
+    let history = build_history(&messages);
+
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[0].speaker, "Carol");
+    assert_eq!(history[1].content, "second");
+}
+
+#[tokio::test]
+async fn history_stops_at_latest_fence() {
+    let mock = MockTextgen::start().await;
+    let api = api(&mock, "conversation-fence");
+    let data = data(true);
+    let platform = MemoryPlatform::new();
+
+    platform.post_user(CHANNEL, "Bob", "before");
+    let fence = platform.post_own(CHANNEL, FENCE_MESSAGE);
+    data.lock().unwrap().fences.add(CHANNEL, Fence { message_id: fence.id, character_id: None }).unwrap();
+    platform.post_user(CHANNEL, "Bob", "after");
+    let (prompt, _) = Conversation::new(&api, &data).prompt_for(&platform, CHANNEL).await.unwrap();
+
+    assert_eq!(prompt, "Bob: after\nAlice:");
+}
+
+#[tokio::test]
+async fn history_stops_at_deleted_fence() {
+    let mock = MockTextgen::start().await;
+    let api = api(&mock, "conversation-deleted-fence");
+    let data = data(true);
+    let platform = MemoryPlatform::new();
+
+    platform.post_user(CHANNEL, "Bob", "before");
+    let fence = platform.post_own(CHANNEL, FENCE_MESSAGE);
+    data.lock().unwrap().fences.add(CHANNEL, Fence { message_id: fence.id.to_owned(), character_id: None }).unwrap();
+    platform.post_user(CHANNEL, "Bob", "after");
+    platform.delete_message(CHANNEL, &fence.id).await.unwrap();
+    let (prompt, _) = Conversation::new(&api, &data).prompt_for(&platform, CHANNEL).await.unwrap();
+
+    assert_eq!(prompt, "Bob: after
+Alice:");
+}
+
+#[tokio::test]
+async fn fences_for_other_characters_are_ignored() {
+    let mock = MockTextgen::start().await;
+    let api = api(&mock, "conversation-other-fence");
+    let data = data(true);
+    let platform = MemoryPlatform::new();
+
+    platform.post_user(CHANNEL, "Bob", "before");
+    let fence = platform.post_own(CHANNEL, FENCE_MESSAGE);
+    data.lock().unwrap().fences.add(CHANNEL, Fence { message_id: fence.id, character_id: Some(String::from("bob")) }).unwrap();
+    platform.post_user(CHANNEL, "Bob", "after");
+    let (prompt, _) = Conversation::new(&api, &data).prompt_for(&platform, CHANNEL).await.unwrap();
+
+    assert!(prompt.starts_with("Bob: before\n"));
+    assert!(prompt.ends_with("Bob: after\nAlice:"));
+}
+
+#[tokio::test]
+async fn fence_text_alone_is_not_a_fence() {
+    let mock = MockTextgen::start().await;
+    let api = api(&mock, "conversation-fence-text");
+    let data = data(true);
+    let platform = MemoryPlatform::new();
+
+    platform.post_user(CHANNEL, "Bob", "before");
+    platform.post_own(CHANNEL, FENCE_MESSAGE);
+    let (prompt, _) = Conversation::new(&api, &data).prompt_for(&platform, CHANNEL).await.unwrap();
+
+    assert!(prompt.starts_with("Bob: before\n"));
+}
+
+#[tokio::test]
+async fn reply_is_sent_as_invited_character() {
+    let mock = MockTextgen::start().await;
+    mock.set_default_reply(" Beep boop!");
+    let api = api(&mock, "conversation-reply");
+    let data = data(true);
+    let platform = MemoryPlatform::new();
+
+    platform.post_user(CHANNEL, "Bob", "Hi Alice");
+    let trigger = platform.post_user(CHANNEL, "Bob", "How are you?");
+    Conversation::new(&api, &data).on_message(&platform, CHANNEL, &trigger).await;
+
+    let messages = platform.messages(CHANNEL);
+    let reply = messages.last().unwrap();
+    assert_eq!(reply.message.content, " Beep boop!");
+    assert_eq!(reply.persona.as_ref().unwrap().name, "Alice");
+    assert_eq!(reply.persona.as_ref().unwrap().avatar_url, "https://example.com/alice.png");
+    assert_eq!(reply.controls, Some(SwipeControls { current: 0, total: 1 }));
+    assert_eq!(mock.requests()[0][0], "Bob: Hi Alice\nBob: How are you?\nAlice:");
+    assert_eq!(platform.typing_count(), 1);
+    assert!(!platform.is_typing(CHANNEL));
+}
+
+#[tokio::test]
+async fn no_reply_without_invited_character() {
+    let mock = MockTextgen::start().await;
+    let api = api(&mock, "conversation-uninvited");
+    let data = data(false);
+    let platform = MemoryPlatform::new();
+
+    let trigger = platform.post_user(CHANNEL, "Bob", "Anyone there?");
+    Conversation::new(&api, &data).on_message(&platform, CHANNEL, &trigger).await;
+
+    assert_eq!(platform.messages(CHANNEL).len(), 1);
+    assert!(mock.requests().is_empty());
+}
+
+#[tokio::test]
+async fn bot_messages_are_not_answered() {
+    let mock = MockTextgen::start().await;
+    let api = api(&mock, "conversation-bots");
+    let data = data(true);
+    let platform = MemoryPlatform::new();
+
+    let trigger = platform.post_bot(CHANNEL, "OtherBot", "Hello Alice");
+    Conversation::new(&api, &data).on_message(&platform, CHANNEL, &trigger).await;
+
+    assert!(mock.requests().is_empty());
+}
+
+#[tokio::test]
+async fn unavailable_backend_adds_reaction() {
+    let mock = MockTextgen::start().await;
+    mock.script(vec![MockResponse::Status(500), MockResponse::Status(500)]);
+    let api = api(&mock, "conversation-down");
+    let data = data(true);
+    let platform = MemoryPlatform::new();
+
+    let trigger = platform.post_user(CHANNEL, "Bob", "Hello?");
+    Conversation::new(&api, &data).on_message(&platform, CHANNEL, &trigger).await;
+
+    assert_eq!(platform.messages(CHANNEL).len(), 1);
+    assert_eq!(platform.reactions(), vec![(trigger.id, BACKEND_UNAVAILABLE_REACTION)]);
+    assert!(!platform.is_typing(CHANNEL));
+}
+
+#[tokio::test]
+async fn swipes_regenerate_and_cycle_candidates() {
+    let mock = MockTextgen::start().await;
+    mock.script(vec![MockResponse::Reply(String::from(" First")), MockResponse::Reply(String::from(" Second"))]);
+    let api = api(&mock, "conversation-swipes");
+    let data = data(true);
+    let platform = MemoryPlatform::new();
+    let conversation = Conversation::new(&api, &data);
+
+    let trigger = platform.post_user(CHANNEL, "Bob", "Tell me something");
+    conversation.on_message(&platform, CHANNEL, &trigger).await;
+    let reply_id = platform.messages(CHANNEL).last().unwrap().message.id.clone();
+
+    conversation.swipe(&platform, CHANNEL, &reply_id, SwipeAction::Regenerate).await.unwrap();
+    let reply = platform.messages(CHANNEL).last().unwrap().clone();
+    assert_eq!(reply.message.content, " Second");
+    assert_eq!(reply.controls, Some(SwipeControls { current: 1, total: 2 }));
+    // The candidate was generated from the same prompt
+    assert_eq!(mock.requests()[0][0], mock.requests()[1][0]);
+
+    conversation.swipe(&platform, CHANNEL, &reply_id, SwipeAction::Previous).await.unwrap();
+    assert_eq!(platform.messages(CHANNEL).last().unwrap().message.content, " First");
+}
+
+#[tokio::test]
+async fn only_latest_reply_keeps_swipe_controls() {
+    let mock = MockTextgen::start().await;
+    let api = api(&mock, "conversation-latest");
+    let data = data(true);
+    let platform = MemoryPlatform::new();
+    let conversation = Conversation::new(&api, &data);
+
+    let first = platform.post_user(CHANNEL, "Bob", "One");
+    conversation.on_message(&platform, CHANNEL, &first).await;
+    let second = platform.post_user(CHANNEL, "Bob", "Two");
+    conversation.on_message(&platform, CHANNEL, &second).await;
+
+    let messages = platform.messages(CHANNEL);
+    assert_eq!(messages[1].controls, None);
+    assert!(messages[3].controls.is_some());
+
+    // Swiping an older reply does nothing
+    conversation.swipe(&platform, CHANNEL, &messages[1].message.id, SwipeAction::Regenerate).await.unwrap();
+    assert_eq!(mock.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn new_reply_during_regenerate_keeps_the_old_reply() {
+    let mock = MockTextgen::start().await;
+    let api = api(&mock, "conversation-swipe-race");
+    let data = data(true);
+    let platform = MemoryPlatform::new();
+    let conversation = Conversation::new(&api, &data);
+
+    mock.script(vec![MockResponse::Reply(String::from(" First"))]);
+    let first = platform.post_user(CHANNEL, "Bob", "One");
+    conversation.on_message(&platform, CHANNEL, &first).await;
+    let old_reply = platform.messages(CHANNEL).last().unwrap().message.id.clone();
+
+    // The regenerate is slow, a reply to a new message lands before it's done
+    mock.set_latency(Duration::from_millis(300));
+    mock.script(vec![MockResponse::Reply(String::from(" New"))]);
+    let regenerate = conversation.swipe(&platform, CHANNEL, &old_reply, SwipeAction::Regenerate);
+    let new_reply = async {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        mock.set_latency(Duration::ZERO);
+        let second = platform.post_user(CHANNEL, "Bob", "Two");
+        conversation.on_message(&platform, CHANNEL, &second).await;
+    };
+    let (result, _) = tokio::join!(regenerate, new_reply);
+    result.unwrap();
+
+    let messages = platform.messages(CHANNEL);
+    assert_eq!(messages[1].message.content, " First");
+    assert_eq!(messages[1].controls, None);
+    assert_eq!(messages[3].message.content, " New");
+    assert_eq!(messages[3].controls, Some(SwipeControls { current: 0, total: 1 }));
+}
+
+#[tokio::test]
+async fn prompt_matches_what_is_sent() {
+    let mock = MockTextgen::start().await;
+    let api = api(&mock, "conversation-prompt");
+    let data = data(true);
+    let platform = MemoryPlatform::new();
+    let conversation = Conversation::new(&api, &data);
+
+    let trigger = platform.post_user(CHANNEL, "Bob", "Hi Alice");
+    let (prompt, persona) = conversation.prompt_for(&platform, CHANNEL).await.unwrap();
+    conversation.on_message(&platform, CHANNEL, &trigger).await;
+
+    assert_eq!(persona.name, "Alice");
+    assert_eq!(mock.requests()[0][0], prompt);
+}
+
+#[tokio::test]
+async fn images_are_sent_to_llama_cpp_with_markers() {
+    let mock = MockTextgen::start().await;
+    mock.set_default_reply(" What a cute cat!");
+    let api = vision_api(&mock, "conversation-llama-cpp", "llama_cpp");
+    let data = data(true);
+    let platform = MemoryPlatform::new();
+
+    let trigger = platform.post_user_with_images(CHANNEL, "Bob", "Look", vec![("https://example.com/cat.png", PNG.to_vec())]);
+    Conversation::new(&api, &data).on_message(&platform, CHANNEL, &trigger).await;
+
+    let requests = mock.vision_requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].0, "/completion");
+    assert_eq!(requests[0].1["prompt"], "Bob: Look [img-1]\nAlice:");
+    assert_eq!(requests[0].1["image_data"][0]["id"], 1);
+    assert_eq!(requests[0].1["image_data"][0]["data"], "iVBORw0KGgo=");
+    assert!(mock.requests().is_empty());
+    assert_eq!(platform.messages(CHANNEL).last().unwrap().message.content, " What a cute cat!");
+}
+
+#[tokio::test]
+async fn images_are_sent_as_openai_content_parts() {
+    let mock = MockTextgen::start().await;
+    let api = vision_api(&mock, "conversation-openai", "openai");
+    let data = data(true);
+    let platform = MemoryPlatform::new();
+
+    let trigger = platform.post_user_with_images(CHANNEL, "Bob", "", vec![("https://example.com/cat.png", PNG.to_vec())]);
+    Conversation::new(&api, &data).on_message(&platform, CHANNEL, &trigger).await;
+
+    let request = &mock.vision_requests()[0].1;
+    let content = &request["messages"][0]["content"];
+    assert_eq!(content[0]["text"], "Bob: [image 1]\nAlice:");
+    assert_eq!(content[1]["image_url"]["url"], "data:image/png;base64,iVBORw0KGgo=");
+    assert_eq!(platform.messages(CHANNEL).last().unwrap().message.content, " Hello there!");
+}
+
+#[tokio::test]
+async fn failing_multimodal_endpoint_falls_back_to_textgen() {
+    let mock = MockTextgen::start().await;
+    let api = vision_api(&mock, "conversation-multimodal-failover", "llama_cpp");
+    let data = data(true);
+    let platform = MemoryPlatform::new();
+    let conversation = Conversation::new(&api, &data);
+
+    platform.post_user_with_images(CHANNEL, "Bob", "Look", vec![("https://example.com/cat.png", PNG.to_vec())]);
+    for _ in 0..2 {
+        mock.script(vec![MockResponse::Status(503), MockResponse::Status(503)]);
+        let trigger = platform.post_user(CHANNEL, "Bob", "Well?");
+        conversation.on_message(&platform, CHANNEL, &trigger).await;
+    }
+    assert_eq!(mock.vision_requests().len(), 4);
+    assert_eq!(mock.requests().len(), 2);
+
+    // The circuit is open, the multimodal endpoint isn't even tried
+    let trigger = platform.post_user(CHANNEL, "Bob", "Hello?");
+    conversation.on_message(&platform, CHANNEL, &trigger).await;
+    assert_eq!(mock.vision_requests().len(), 4);
+    assert_eq!(mock.requests().len(), 3);
+    assert_eq!(platform.messages(CHANNEL).last().unwrap().message.content, " Hello there!");
+}
+
+#[tokio::test]
+async fn images_are_captioned_without_multimodal_backend() {
+    let mock = MockTextgen::start().await;
+    let api = vision_api(&mock, "conversation-caption", "caption");
+    let data = data(true);
+    let platform = MemoryPlatform::new();
+
+    platform.post_user_with_images(CHANNEL, "Bob", "", vec![("https://example.com/cat.png?ex=1", PNG.to_vec())]);
+    platform.post_user_with_images(CHANNEL, "Bob", "Again", vec![("https://example.com/cat.png?ex=2", PNG.to_vec())]);
+    let (prompt, _) = Conversation::new(&api, &data).prompt_for(&platform, CHANNEL).await.unwrap();
+
+    assert_eq!(prompt, "Bob: [image: a cat on a keyboard]\nBob: Again [image: a cat on a keyboard]\nAlice:");
+    // Same image with a different signature, captioned only once
+    assert_eq!(mock.vision_requests().len(), 1);
+    assert_eq!(mock.vision_requests()[0].1["mime_type"], "image/png");
+}
+
+#[tokio::test]
+async fn images_are_ignored_without_vision_settings() {
+    let mock = MockTextgen::start().await;
+    let api = api(&mock, "conversation-no-vision");
+    let data = data(true);
+    let platform = MemoryPlatform::new();
+
+    platform.post_user_with_images(CHANNEL, "Bob", "", vec![("https://example.com/cat.png", PNG.to_vec())]);
+    platform.post_user(CHANNEL, "Bob", "Hi");
+    let (prompt, _) = Conversation::new(&api, &data).prompt_for(&platform, CHANNEL).await.unwrap();
+
+    assert_eq!(prompt, "Bob: Hi\nAlice:");
+}
+
+fn imagegen_api(mock: &MockTextgen, name: &str) -> TextgenApi {
+    let template = common::write_template(name, "[[CONTEXT]]\n[[NAME]]:");
+    let mut config = common::config(json!([common::endpoint(mock, 0)]), &template);
+    config.image_generation.url = Some(mock.url("/"));
+    config.image_generation.negative_prompt = String::from("lowres");
+    TextgenApi::new(&config).unwrap()
+}
+
+#[tokio::test]
+async fn image_tags_in_replies_become_pictures() {
+    let mock = MockTextgen::start().await;
+    mock.set_default_reply(" Here I am! [picture: waving at the beach]");
+    let api = imagegen_api(&mock, "conversation-imagegen");
+    let data = data(true);
+    if let Some(alice) = data.lock().unwrap().characters.get_mut("alice") {
+        alice.image_prompt = String::from("silver robot");
+        alice.image_negative_prompt = String::from("humans");
+    }
+    let platform = MemoryPlatform::new();
+
+    let trigger = platform.post_user(CHANNEL, "Bob", "Send a picture!");
+    Conversation::new(&api, &data).on_message(&platform, CHANNEL, &trigger).await;
+
+    let messages = platform.messages(CHANNEL);
+    assert_eq!(messages[1].message.content, " Here I am!");
+    assert_eq!(messages[2].image.as_deref(), Some(PNG.as_slice()));
+    assert_eq!(messages[2].persona.as_ref().unwrap().name, "Alice");
+    let (path, request) = &mock.vision_requests()[0];
+    assert_eq!(path, "/sdapi/v1/txt2img");
+    assert_eq!(request["prompt"], "silver robot, waving at the beach");
+    assert_eq!(request["negative_prompt"], "lowres, humans");
+}
+
+#[tokio::test]
+async fn image_tags_stay_without_image_generation() {
+    let mock = MockTextgen::start().await;
+    mock.set_default_reply(" Here I am! [picture: waving]");
+    let api = api(&mock, "conversation-no-imagegen");
+    let data = data(true);
+    let platform = MemoryPlatform::new();
+
+    let trigger = platform.post_user(CHANNEL, "Bob", "Send a picture!");
+    Conversation::new(&api, &data).on_message(&platform, CHANNEL, &trigger).await;
+
+    assert_eq!(platform.messages(CHANNEL).len(), 2);
+    assert_eq!(platform.messages(CHANNEL)[1].message.content, " Here I am! [picture: waving]");
+}
+
+#[tokio::test]
+async fn imagine_needs_an_invited_character() {
+    let mock = MockTextgen::start().await;
+    let api = imagegen_api(&mock, "conversation-imagine-uninvited");
+    let data = data(false);
+    let platform = MemoryPlatform::new();
+    let persona = uc207::platform::Persona { name: String::from("Alice"), avatar_url: String::new() };
+
+    assert!(Conversation::new(&api, &data).imagine(&platform, CHANNEL, &persona, "selfie").await.is_err());
+    assert!(mock.vision_requests().is_empty());
+}
+
+fn voice_api(mock: &MockTextgen, name: &str) -> TextgenApi {
+    let template = common::write_template(name, "[[CONTEXT]]\n[[NAME]]:");
+    let mut config = common::config(json!([common::endpoint(mock, 0)]), &template);
+    config.voice.tts_url = Some(mock.url("/api/tts"));
+    config.voice.default_voice = String::from("p225");
+    TextgenApi::new(&config).unwrap()
+}
+
+#[tokio::test]
+async fn replies_are_spoken_in_linked_voice_channels() {
+    let mock = MockTextgen::start().await;
+    mock.set_default_reply(" *waves* Hello   there!");
+    let api = voice_api(&mock, "conversation-voice");
+    let data = data(true);
+    data.lock().unwrap().voice_channels.insert(String::from(CHANNEL), String::from("5678"));
+    if let Some(alice) = data.lock().unwrap().characters.get_mut("alice") {
+        alice.voice = String::from("p243");
+    }
+    let platform = MemoryPlatform::new();
+
+    let trigger = platform.post_user(CHANNEL, "Bob", "Hi");
+    Conversation::new(&api, &data).on_message(&platform, CHANNEL, &trigger).await;
+
+    let requests = mock.speech_requests();
+    assert_eq!(requests.len(), 1);
+    assert!(requests[0].contains(&(String::from("text"), String::from("waves Hello there!"))));
+    assert!(requests[0].contains(&(String::from("speaker_id"), String::from("p243"))));
+    let played = platform.played_audio();
+    assert_eq!(played.len(), 1);
+    assert_eq!(played[0].0, CHANNEL);
+    assert_eq!(&played[0].1[0..4], b"RIFF");
+}
+
+#[tokio::test]
+async fn characters_without_a_voice_use_the_default_one() {
+    let mock = MockTextgen::start().await;
+    let api = voice_api(&mock, "conversation-default-voice");
+    let data = data(true);
+    data.lock().unwrap().voice_channels.insert(String::from(CHANNEL), String::from("5678"));
+    let platform = MemoryPlatform::new();
+
+    let trigger = platform.post_user(CHANNEL, "Bob", "Hi");
+    Conversation::new(&api, &data).on_message(&platform, CHANNEL, &trigger).await;
+
+    assert!(mock.speech_requests()[0].contains(&(String::from("speaker_id"), String::from("p225"))));
+}
+
+#[tokio::test]
+async fn replies_are_not_spoken_without_a_voice_channel() {
+    let mock = MockTextgen::start().await;
+    let api = voice_api(&mock, "conversation-no-voice");
+    let data = data(true);
+    let platform = MemoryPlatform::new();
+
+    let trigger = platform.post_user(CHANNEL, "Bob", "Hi");
+    Conversation::new(&api, &data).on_message(&platform, CHANNEL, &trigger).await;
+
+    assert!(mock.speech_requests().is_empty());
+    assert!(platform.played_audio().is_empty());
+}
+
+#[tokio::test]
+async fn regenerated_replies_are_spoken() {
+    let mock = MockTextgen::start().await;
+    mock.script(vec![MockResponse::Reply(String::from(" First")), MockResponse::Reply(String::from(" Second"))]);
+    let api = voice_api(&mock, "conversation-voice-swipe");
+    let data = data(true);
+    data.lock().unwrap().voice_channels.insert(String::from(CHANNEL), String::from("5678"));
+    let platform = MemoryPlatform::new();
+    let conversation = Conversation::new(&api, &data);
+
+    let trigger = platform.post_user(CHANNEL, "Bob", "Hi");
+    conversation.on_message(&platform, CHANNEL, &trigger).await;
+    let reply = platform.messages(CHANNEL)[1].message.id.to_owned();
+    conversation.swipe(&platform, CHANNEL, &reply, SwipeAction::Regenerate).await.unwrap();
+    conversation.swipe(&platform, CHANNEL, &reply, SwipeAction::Previous).await.unwrap();
+
+    let texts: Vec<String> = mock.speech_requests().iter()
+        .filter_map(|query| query.iter().find(|(name, _)| name == "text").map(|(_, text)| text.to_owned()))
+        .collect();
+    assert_eq!(texts, vec!["First", "Second"]);
+}
+
+#[tokio::test]
+async fn voice_messages_are_posted_and_replied_to() {
+    let mock = MockTextgen::start().await;
+    let api = api(&mock, "conversation-voice-message");
+    let data = data(true);
+    let platform = MemoryPlatform::new();
+    platform.post_user(CHANNEL, "Bob", "Hi");
+    let speaker = Persona { name: String::from("Carol"), avatar_url: String::from("https://example.com/carol.png") };
+
+    Conversation::new(&api, &data).on_voice_message(&platform, CHANNEL, "carol", &speaker, "Can you hear me?").await;
+
+    let messages = platform.messages(CHANNEL);
+    assert_eq!(messages[1].message.content, "Can you hear me?");
+    assert_eq!(messages[1].persona.as_ref(), Some(&speaker));
+    assert_eq!(messages[2].message.author_name, "Alice");
+    let prompt = mock.requests()[0][0].as_str().unwrap().to_owned();
+    assert!(prompt.ends_with("Bob: Hi\nCarol: Can you hear me?\nAlice:"));
+}
+
+#[tokio::test]
+async fn voice_speakers_are_users_the_character_replies_to() {
+    let mock = MockTextgen::start().await;
+    let template = common::write_template("conversation-voice-user", "[[USER]]|[[USER_PERSONA]]|[[USERS]]|[[CONTEXT]]");
+    let api = TextgenApi::new(&common::config(json!([common::endpoint(&mock, 0)]), &template)).unwrap();
+    let data = data(true);
+    let profile = UserProfile { name: String::from("Caz"), description: String::from("Likes radios") };
+    data.lock().unwrap().user_profiles.set("carol", None, profile).unwrap();
+    let platform = MemoryPlatform::new();
+    platform.post_user(CHANNEL, "Bob", "Hi");
+    let speaker = Persona { name: String::from("Caz"), avatar_url: String::new() };
+
+    Conversation::new(&api, &data).on_voice_message(&platform, CHANNEL, "carol", &speaker, "Can you hear me?").await;
+
+    assert_eq!(mock.requests()[0][0], "Caz|Likes radios|Caz: Likes radios|Bob: Hi\nCaz: Can you hear me?");
+}
+
+#[tokio::test]
+async fn voice_messages_are_ignored_without_a_character() {
+    let mock = MockTextgen::start().await;
+    let api = api(&mock, "conversation-voice-no-character");
+    let data = data(false);
+    let platform = MemoryPlatform::new();
+    let speaker = Persona { name: String::from("Carol"), avatar_url: String::new() };
+
+    Conversation::new(&api, &data).on_voice_message(&platform, CHANNEL, "carol", &speaker, "Hello?").await;
+
+    assert!(platform.messages(CHANNEL).is_empty());
+    assert!(mock.requests().is_empty());
+}
+
+#[tokio::test]
+async fn transcripts_go_back_to_the_fence_or_a_range() {
+    let mock = MockTextgen::start().await;
+    let api = api(&mock, "conversation-transcript");
+    let data = data(true);
+    let platform = MemoryPlatform::new();
+    let first = platform.post_user(CHANNEL, "Bob", "Before the fence");
+    let fence = platform.post_own(CHANNEL, FENCE_MESSAGE);
+    data.lock().unwrap().fences.add(CHANNEL, Fence { message_id: fence.id, character_id: None }).unwrap();
+    platform.post_user(CHANNEL, "Bob", "Hi");
+    let reply = platform.post_bot(CHANNEL, "Alice", "Hello!");
+    platform.post_user(CHANNEL, "Bob", "Bye");
+    let conversation = Conversation::new(&api, &data);
+
+    let contents = |messages: Vec<Message>| messages.into_iter().map(|message| message.content).collect::<Vec<String>>();
+    assert_eq!(contents(conversation.transcript(&platform, CHANNEL, None, None).await.unwrap()), vec!["Hi", "Hello!", "Bye"]);
+    assert_eq!(
+        contents(conversation.transcript(&platform, CHANNEL, Some(&first.id), Some(&reply.id)).await.unwrap()),
+        vec!["Before the fence", FENCE_MESSAGE, "Hi", "Hello!"]
+    );
+    assert!(conversation.transcript(&platform, CHANNEL, Some("404"), None).await.is_err());
+}
+
+#[tokio::test]
+async fn imported_messages_come_before_the_history_until_the_next_fence() {
+    let mock = MockTextgen::start().await;
+    let api = api(&mock, "conversation-import");
+    let data = data(true);
+    let platform = MemoryPlatform::new();
+    let conversation = Conversation::new(&api, &data);
+    conversation.import(CHANNEL, vec![
+        Message { speaker: String::from("Bob"), content: String::from("Remember the cake?") },
+        Message { speaker: String::from("Alice"), content: String::from("It was a lie.") }
+    ]).unwrap();
+    platform.post_user(CHANNEL, "Bob", "Hi again");
+
+    let (prompt, _) = conversation.prompt_for(&platform, CHANNEL).await.unwrap();
+    assert_eq!(prompt, "Bob: Remember the cake?\nAlice: It was a lie.\nBob: Hi again\nAlice:");
+    assert_eq!(conversation.transcript(&platform, CHANNEL, None, None).await.unwrap().len(), 3);
+
+    let fence = platform.post_own(CHANNEL, FENCE_MESSAGE);
+    data.lock().unwrap().fences.add(CHANNEL, Fence { message_id: fence.id, character_id: None }).unwrap();
+    platform.post_user(CHANNEL, "Bob", "Fresh start");
+    let (prompt, _) = conversation.prompt_for(&platform, CHANNEL).await.unwrap();
+    assert_eq!(prompt, "Bob: Fresh start\nAlice:");
+}
+
+#[tokio::test]
+async fn imported_messages_survive_a_restart() {
+    let mock = MockTextgen::start().await;
+    let api = api(&mock, "conversation-import-saved");
+    let path = std::env::temp_dir().join(format!("uc207-imports-{}.json", std::process::id())).to_string_lossy().into_owned();
+    let _ = std::fs::remove_file(&path);
+    let data = data(true);
+    data.lock().unwrap().imported_history = Store::load(&path).unwrap();
+    Conversation::new(&api, &data).import(CHANNEL, vec![
+        Message { speaker: String::from("Bob"), content: String::from("Remember the cake?") }
+    ]).unwrap();
+
+    let restarted = self::data(true);
+    restarted.lock().unwrap().imported_history = Store::load(&path).unwrap();
+    let platform = MemoryPlatform::new();
+    platform.post_user(CHANNEL, "Bob", "Hi again");
+    let (prompt, _) = Conversation::new(&api, &restarted).prompt_for(&platform, CHANNEL).await.unwrap();
+
+    assert_eq!(prompt, "Bob: Remember the cake?\nBob: Hi again\nAlice:");
+}
+
+#[tokio::test]
+async fn imported_messages_only_fill_the_history_limit() {
+    let mock = MockTextgen::start().await;
+    let template = common::write_template("conversation-import-limit", "[[CONTEXT]]\n[[NAME]]:");
+    let mut config = common::config(json!([common::endpoint(&mock, 0)]), &template);
+    config.limits.history_messages = 3;
+    let api = TextgenApi::new(&config).unwrap();
+    let data = data(true);
+    let platform = MemoryPlatform::new();
+    let conversation = Conversation::new(&api, &data);
+    conversation.import(CHANNEL, (1..=5).map(|index| Message { speaker: String::from("Bob"), content: index.to_string() }).collect()).unwrap();
+    platform.post_user(CHANNEL, "Bob", "Now");
+
+    let (prompt, _) = conversation.prompt_for(&platform, CHANNEL).await.unwrap();
+
+    assert_eq!(prompt, "Bob: 4\nBob: 5\nBob: Now\nAlice:");
+}
+
+fn named(author: &str, display_name: Option<&str>, nickname: Option<&str>, content: &str) -> PlatformMessage {
+    let mut message = message(author, content, false, false);
+    message.display_name = display_name.map(String::from);
+    message.nickname = nickname.map(String::from);
+    message
+}
+
+#[test]
+fn speakers_are_named_as_configured() {
+    let mut profiles = UserProfiles::default();
+    profiles.set("carol", None, UserProfile { name: String::from("Cee"), description: String::new() }).unwrap();
+    profiles.set("dan", None, UserProfile { name: String::new(), description: String::from("Likes robots") }).unwrap();
+    let bob = named("bob", Some("Bob"), Some("Bobby"), "");
+    let dan = named("dan", Some("Dan"), None, "");
+
+    assert_eq!(speaker_name(&bob, SpeakerNames::Nickname, &profiles, None), "Bobby");
+    assert_eq!(speaker_name(&dan, SpeakerNames::Nickname, &profiles, None), "Dan");
+    assert_eq!(speaker_name(&bob, SpeakerNames::DisplayName, &profiles, None), "Bob");
+    assert_eq!(speaker_name(&bob, SpeakerNames::Username, &profiles, None), "bob");
+    assert_eq!(speaker_name(&named("carol", None, Some("Carol"), ""), SpeakerNames::Nickname, &profiles, None), "Cee");
+    assert_eq!(speaker_name(&message("carol", "", true, false), SpeakerNames::Nickname, &profiles, None), "carol");
+}
+
+#[tokio::test]
+async fn history_uses_nicknames_and_callme_names() {
+    let mock = MockTextgen::start().await;
+    let api = api(&mock, "conversation-names");
+    let data = data(true);
+    data.lock().unwrap().user_profiles.set("carol", None, UserProfile { name: String::from("Cee"), description: String::new() }).unwrap();
+    let platform = MemoryPlatform::new();
+
+    platform.post_message(CHANNEL, named("bob", Some("Bob"), Some("Bobby"), "Hi"));
+    platform.post_user(CHANNEL, "carol", "Hello");
+    let (prompt, _) = Conversation::new(&api, &data).prompt_for(&platform, CHANNEL).await.unwrap();
+
+    assert_eq!(prompt, "Bobby: Hi\nCee: Hello\nAlice:");
+}
+
+#[tokio::test]
+async fn replies_quote_the_message_they_reply_to() {
+    let mock = MockTextgen::start().await;
+    let template = common::write_template("conversation-replies", "[[CONTEXT]]\n[[NAME]]:");
+    let mut config = common::config(json!([common::endpoint(&mock, 0)]), &template);
+    config.history.quote_length = 10;
+    let api = TextgenApi::new(&config).unwrap();
+    let data = data(true);
+    let platform = MemoryPlatform::new();
+
+    let question = platform.post_message(CHANNEL, named("bob", None, Some("Bobby"), "What's the   weather like?"));
+    let mut reply = message("carol", "Sunny", false, false);
+    reply.reply_to = Some(Box::new(question));
+    platform.post_message(CHANNEL, reply);
+    let (prompt, _) = Conversation::new(&api, &data).prompt_for(&platform, CHANNEL).await.unwrap();
+
+    assert_eq!(prompt, "Bobby: What's the   weather like?\ncarol: (replying to Bobby: \"What's the…\") Sunny\nAlice:");
+}
+
+#[tokio::test]
+async fn reply_context_can_be_turned_off() {
+    let mock = MockTextgen::start().await;
+    let template = common::write_template("conversation-no-replies", "[[CONTEXT]]\n[[NAME]]:");
+    let mut config = common::config(json!([common::endpoint(&mock, 0)]), &template);
+    config.history.reply_context = false;
+    let api = TextgenApi::new(&config).unwrap();
+    let data = data(true);
+    let platform = MemoryPlatform::new();
+
+    let question = platform.post_user(CHANNEL, "bob", "Hi");
+    let mut reply = message("carol", "Hello", false, false);
+    reply.reply_to = Some(Box::new(question));
+    platform.post_message(CHANNEL, reply);
+    let (prompt, _) = Conversation::new(&api, &data).prompt_for(&platform, CHANNEL).await.unwrap();
+
+    assert_eq!(prompt, "bob: Hi\ncarol: Hello\nAlice:");
+}
+
+#[tokio::test]
+async fn users_placeholder_lists_descriptions_of_users_in_the_history() {
+    let mock = MockTextgen::start().await;
+    let template = common::write_template("conversation-users", "[[USERS]]\n[[CONTEXT]]\n[[NAME]]:");
+    let api = TextgenApi::new(&common::config(json!([common::endpoint(&mock, 0)]), &template)).unwrap();
+    let data = data(true);
+    {
+        let profiles = &mut data.lock().unwrap().user_profiles;
+        profiles.set("bob", None, UserProfile { name: String::from("Bobby"), description: String::from("A robot fan") }).unwrap();
+        profiles.set("carol", None, UserProfile { name: String::from("Cee"), description: String::new() }).unwrap();
+        profiles.set("dan", None, UserProfile { name: String::from("Dan"), description: String::from("Not here") }).unwrap();
+    }
+    let platform = MemoryPlatform::new();
+
+    platform.post_user(CHANNEL, "bob", "Hi");
+    platform.post_user(CHANNEL, "carol", "Hello");
+    platform.post_user(CHANNEL, "bob", "Again");
+    let (prompt, _) = Conversation::new(&api, &data).prompt_for(&platform, CHANNEL).await.unwrap();
+
+    assert_eq!(prompt, "Bobby: A robot fan\nBobby: Hi\nCee: Hello\nBobby: Again\nAlice:");
+}
+
+#[tokio::test]
+async fn server_personas_win_in_their_server() {
+    let mock = MockTextgen::start().await;
+    let api = api(&mock, "conversation-server-persona");
+    let data = data(true);
+    {
+        let profiles = &mut data.lock().unwrap().user_profiles;
+        profiles.set("bob", None, UserProfile { name: String::from("Bobby"), description: String::new() }).unwrap();
+        profiles.set("bob", Some("42"), UserProfile { name: String::from("Sir Robert"), description: String::new() }).unwrap();
+    }
+    let platform = MemoryPlatform::new();
+    platform.post_user(CHANNEL, "bob", "Hi");
+    let conversation = Conversation::new(&api, &data);
+
+    let (prompt, _) = conversation.prompt_for(&platform, CHANNEL).await.unwrap();
+    assert_eq!(prompt, "Bobby: Hi\nAlice:");
+
+    platform.set_server(CHANNEL, "42");
+    let (prompt, _) = conversation.prompt_for(&platform, CHANNEL).await.unwrap();
+    assert_eq!(prompt, "Sir Robert: Hi\nAlice:");
+}
+
+#[tokio::test]
+async fn user_persona_placeholders_describe_who_is_replied_to() {
+    let mock = MockTextgen::start().await;
+    let template = common::write_template("conversation-user-persona", "[[USER]] is [[USER_PERSONA]]\n[[CONTEXT]]\n[[NAME]]:");
+    let api = TextgenApi::new(&common::config(json!([common::endpoint(&mock, 0)]), &template)).unwrap();
+    let data = data(true);
+    {
+        let profiles = &mut data.lock().unwrap().user_profiles;
+        profiles.set("bob", None, UserProfile { name: String::from("Bobby"), description: String::from("a robot fan") }).unwrap();
+        profiles.set("carol", None, UserProfile { name: String::from("Cee"), description: String::from("a painter") }).unwrap();
+    }
+    let platform = MemoryPlatform::new();
+
+    platform.post_user(CHANNEL, "bob", "Hi");
+    platform.post_user(CHANNEL, "carol", "Hello");
+    platform.post_bot(CHANNEL, "Other bot", "Beep");
+    let (prompt, _) = Conversation::new(&api, &data).prompt_for(&platform, CHANNEL).await.unwrap();
+
+    assert_eq!(prompt, "Cee is a painter\nBobby: Hi\nCee: Hello\nOther bot: Beep\nAlice:");
+}
+
+#[tokio::test]
+async fn authors_note_of_the_channel_goes_into_the_history() {
+    let mock = MockTextgen::start().await;
+    let api = api(&mock, "conversation-authors-note");
+    let data = data(true);
+    let note = AuthorsNote { text: String::from("[Style: terse]"), depth: Some(1) };
+    data.lock().unwrap().authors_notes.set(CHANNEL, note).unwrap();
+    let platform = MemoryPlatform::new();
+
+    platform.post_user(CHANNEL, "bob", "Hi");
+    platform.post_user(CHANNEL, "carol", "Hello");
+    let (prompt, _) = Conversation::new(&api, &data).prompt_for(&platform, CHANNEL).await.unwrap();
+    assert_eq!(prompt, "bob: Hi\n[Style: terse]\ncarol: Hello\nAlice:");
+
+    data.lock().unwrap().authors_notes.remove(CHANNEL).unwrap();
+    let (prompt, _) = Conversation::new(&api, &data).prompt_for(&platform, CHANNEL).await.unwrap();
+    assert_eq!(prompt, "bob: Hi\ncarol: Hello\nAlice:");
+}
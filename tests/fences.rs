@@ -0,0 +1,50 @@
+use uc207::fences::{Fence, Fences};
+
+fn fence(message_id: &str, character_id: Option<&str>) -> Fence {
+    Fence { message_id: String::from(message_id), character_id: character_id.map(String::from) }
+}
+
+fn fences_path(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("uc207-fences-{}-{}.json", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path.to_string_lossy().into_owned()
+}
+
+#[test]
+fn fences_are_saved_and_loaded() {
+    let path = fences_path("saved");
+    let mut fences = Fences::load(&path).unwrap();
+    fences.add("1", fence("10", None)).unwrap();
+    fences.add("1", fence("20", Some("alice"))).unwrap();
+
+    let loaded = Fences::load(&path).unwrap();
+
+    assert_eq!(loaded.list("1"), &[fence("10", None), fence("20", Some("alice"))]);
+    assert!(loaded.list("2").is_empty());
+}
+
+#[test]
+fn latest_fence_respects_character() {
+    let mut fences = Fences::default();
+    fences.add("1", fence("10", None)).unwrap();
+    fences.add("1", fence("20", Some("alice"))).unwrap();
+
+    assert_eq!(fences.latest("1", "alice"), Some(&fence("20", Some("alice"))));
+    assert_eq!(fences.latest("1", "bob"), Some(&fence("10", None)));
+    assert_eq!(fences.latest("2", "alice"), None);
+}
+
+#[test]
+fn remove_defaults_to_latest_fence() {
+    let path = fences_path("remove");
+    let mut fences = Fences::load(&path).unwrap();
+    fences.add("1", fence("10", None)).unwrap();
+    fences.add("1", fence("20", None)).unwrap();
+    fences.add("1", fence("30", None)).unwrap();
+
+    assert_eq!(fences.remove("1", Some("20")).unwrap(), Some(fence("20", None)));
+    assert_eq!(fences.remove("1", None).unwrap(), Some(fence("30", None)));
+    assert_eq!(fences.remove("1", Some("99")).unwrap(), None);
+    assert_eq!(fences.remove("2", None).unwrap(), None);
+    assert_eq!(Fences::load(&path).unwrap().list("1"), &[fence("10", None)]);
+}
@@ -0,0 +1,216 @@
+mod common;
+
+use serde_json::json;
+use uc207::config::{AssistantSpeakers, Config, PromptFormat};
+use uc207::notes::AuthorsNote;
+use uc207::textgen::api::{Message, PromptExtras, TextgenApi};
+use uc207::textgen::character::Character;
+use uc207::textgen::format::{self, ChatMessage, Prompt, Role};
+
+use common::MockTextgen;
+
+fn character() -> Character {
+    Character { char_name: String::from("Alice"), char_persona: String::from("Alice is a cheerful robot."), ..Default::default() }
+}
+
+fn message(speaker: &str, content: &str) -> Message {
+    Message { speaker: String::from(speaker), content: String::from(content) }
+}
+
+fn history() -> Vec<Message> {
+    vec![
+        message("Bob", "Hi!"),
+        message("Carol", "Hello"),
+        message("Alice", "Beep boop."),
+        message("Dave", "Beep?"),
+        message("Bob", "How are you?"),
+    ]
+}
+
+fn config(name: &str, template: &str, mode: PromptFormat) -> Config {
+    let template = common::write_template(name, template);
+    let mut config = common::config(json!([common::dead_endpoint(0)]), &template);
+    config.format.mode = mode;
+    config
+}
+
+fn chat(role: Role, content: &str) -> ChatMessage {
+    ChatMessage { role, content: String::from(content) }
+}
+
+#[test]
+fn consecutive_messages_of_a_side_share_a_turn() {
+    let turns = format::turns(&history(), true, |message| message.speaker == "Alice");
+
+    assert_eq!(turns, vec![
+        chat(Role::User, "Bob: Hi!\nCarol: Hello"),
+        chat(Role::Assistant, "Alice: Beep boop."),
+        chat(Role::User, "Dave: Beep?\nBob: How are you?"),
+    ]);
+}
+
+#[test]
+fn transcript_format_is_the_default() {
+    let api = TextgenApi::new(&config("format-transcript", "[[CONTEXT]]\n[[NAME]]:", PromptFormat::Transcript)).unwrap();
+
+    let prompt = api.make_prompt(&character(), &history()[..2], &PromptExtras::default()).unwrap();
+
+    assert_eq!(prompt, Prompt::Text(String::from("Bob: Hi!\nCarol: Hello\nAlice:")));
+}
+
+#[test]
+fn chatml_wraps_turns_and_opens_the_reply() {
+    let api = TextgenApi::new(&config("format-chatml", "<|im_start|>system\n[[PERSONA]]<|im_end|>\n[[CONTEXT]]", PromptFormat::ChatMl)).unwrap();
+
+    let prompt = api.make_prompt(&character(), &history()[..3], &PromptExtras::default()).unwrap();
+
+    assert_eq!(prompt.to_string(), "<|im_start|>system\nAlice is a cheerful robot.<|im_end|>\n\
+        <|im_start|>user\nBob: Hi!\nCarol: Hello<|im_end|>\n\
+        <|im_start|>assistant\nAlice: Beep boop.<|im_end|>\n\
+        <|im_start|>assistant\nAlice:");
+}
+
+#[test]
+fn llama2_turns_can_leave_names_out() {
+    let mut config = config("format-llama2", "[[CONTEXT]]", PromptFormat::Llama2);
+    config.format.include_names = false;
+    let api = TextgenApi::new(&config).unwrap();
+
+    let prompt = api.make_prompt(&character(), &history()[..3], &PromptExtras::default()).unwrap();
+
+    assert_eq!(prompt.to_string(), "[INST] Hi!\nHello [/INST] Beep boop. </s><s>");
+}
+
+#[test]
+fn custom_format_uses_configured_markers() {
+    let mut config = config("format-custom", "[[CONTEXT]]", PromptFormat::Custom);
+    config.format.user_prefix = String::from("<u>");
+    config.format.user_suffix = String::from("</u>");
+    config.format.assistant_prefix = String::from("<a>");
+    config.format.assistant_suffix = String::from("</a>");
+    let api = TextgenApi::new(&config).unwrap();
+
+    let prompt = api.make_prompt(&character(), &history()[2..4], &PromptExtras::default()).unwrap();
+
+    assert_eq!(prompt.to_string(), "<a>Alice: Beep boop.</a><u>Dave: Beep?</u><a>Alice:");
+}
+
+#[test]
+fn chat_format_sends_the_template_as_system_message() {
+    let api = TextgenApi::new(&config("format-chat", "[[PERSONA]]\n[[CONTEXT]]", PromptFormat::Chat)).unwrap();
+
+    let prompt = api.make_prompt(&character(), &history()[..3], &PromptExtras::default()).unwrap();
+
+    assert_eq!(prompt, Prompt::Chat(vec![
+        chat(Role::System, "Alice is a cheerful robot."),
+        chat(Role::User, "Bob: Hi!\nCarol: Hello"),
+        chat(Role::Assistant, "Alice: Beep boop."),
+    ]));
+}
+
+#[test]
+fn other_bots_can_count_as_the_assistant() {
+    let mut config = config("format-bots", "[[CONTEXT]]", PromptFormat::Chat);
+    config.format.assistant = AssistantSpeakers::Bots;
+    let api = TextgenApi::new(&config).unwrap();
+    let extras = PromptExtras { bots: vec![String::from("Dave")], ..Default::default() };
+
+    let prompt = api.make_prompt(&character(), &history()[2..], &extras).unwrap();
+
+    assert_eq!(prompt, Prompt::Chat(vec![
+        chat(Role::Assistant, "Alice: Beep boop.\nDave: Beep?"),
+        chat(Role::User, "Bob: How are you?"),
+    ]));
+}
+
+#[test]
+fn authors_note_and_instructions_are_put_at_their_depth() {
+    let mut config = config("format-notes", "[[CONTEXT]]\n[[NAME]]:", PromptFormat::Transcript);
+    config.history.authors_note_depth = 2;
+    let api = TextgenApi::new(&config).unwrap();
+    let character = Character { post_history_instructions: String::from("[Stay in character.]"), ..character() };
+    let note = AuthorsNote { text: String::from("[Style: terse]"), depth: None };
+    let extras = PromptExtras { authors_note: Some(note), ..Default::default() };
+
+    let prompt = api.make_prompt(&character, &history()[..4], &extras).unwrap();
+
+    assert_eq!(prompt.to_string(), "Bob: Hi!\nCarol: Hello\n[Style: terse]\nAlice: Beep boop.\nDave: Beep?\n[Stay in character.]\nAlice:");
+}
+
+#[test]
+fn notes_at_the_same_depth_keep_the_authors_note_first() {
+    let api = TextgenApi::new(&config("format-notes-chat", "[[PERSONA]]\n[[CONTEXT]]", PromptFormat::Chat)).unwrap();
+    let character = Character { post_history_instructions: String::from("Stay in character."), ..character() };
+    let note = AuthorsNote { text: String::from("Be terse."), depth: Some(0) };
+    let extras = PromptExtras { authors_note: Some(note), ..Default::default() };
+
+    let prompt = api.make_prompt(&character, &history()[..2], &extras).unwrap();
+
+    assert_eq!(prompt, Prompt::Chat(vec![
+        chat(Role::System, "Alice is a cheerful robot."),
+        chat(Role::User, "Bob: Hi!\nCarol: Hello"),
+        chat(Role::System, "Be terse."),
+        chat(Role::System, "Stay in character."),
+    ]));
+}
+
+#[test]
+fn chatml_notes_are_system_turns() {
+    let api = TextgenApi::new(&config("format-notes-chatml", "[[CONTEXT]]", PromptFormat::ChatMl)).unwrap();
+    let note = AuthorsNote { text: String::from("Be terse."), depth: Some(1) };
+    let extras = PromptExtras { authors_note: Some(note), ..Default::default() };
+
+    let prompt = api.make_prompt(&character(), &history()[2..4], &extras).unwrap();
+
+    assert_eq!(prompt.to_string(), "<|im_start|>assistant\nAlice: Beep boop.<|im_end|>\n\
+        <|im_start|>system\nBe terse.<|im_end|>\n\
+        <|im_start|>user\nDave: Beep?<|im_end|>\n\
+        <|im_start|>assistant\nAlice:");
+}
+
+#[tokio::test]
+async fn openai_chat_endpoints_get_role_messages() {
+    let mock = MockTextgen::start().await;
+    mock.set_default_reply("I'm great!");
+    let template = common::write_template("format-openai", "[[PERSONA]]");
+    let endpoint = json!({
+        "textgen_url": mock.url("/v1/chat/completions"), "model_url": mock.model_url(), "api": "openai_chat", "model": "gpt-test"
+    });
+    let mut config = common::config(json!([endpoint]), &template);
+    config.format.mode = PromptFormat::Chat;
+    let api = TextgenApi::new(&config).unwrap();
+
+    let prompt = api.make_prompt(&character(), &history()[4..], &PromptExtras::default()).unwrap();
+    let reply = api.request(prompt).await.unwrap();
+
+    assert_eq!(reply, "I'm great!");
+    let (path, body) = mock.vision_requests().pop().unwrap();
+    assert_eq!(path, "/v1/chat/completions");
+    assert_eq!(body["model"], "gpt-test");
+    assert_eq!(body["messages"], json!([
+        { "role": "system", "content": "Alice is a cheerful robot." },
+        { "role": "user", "content": "Bob: How are you?" }
+    ]));
+}
+
+#[tokio::test]
+async fn openai_chat_endpoints_get_their_sampling_settings() {
+    let mock = MockTextgen::start().await;
+    let template = common::write_template("format-openai-sampling", "[[PERSONA]]");
+    let endpoint = json!({
+        "textgen_url": mock.url("/v1/chat/completions"), "model_url": mock.model_url(), "api": "openai_chat", "model": "gpt-test"
+    });
+    let mut config = common::config(json!([endpoint]), &template);
+    config.sampling.presence_penalty = 0.5;
+    config.sampling.frequency_penalty = 0.25;
+    config.sampling.stop = vec![String::from("\nBob:")];
+    let api = TextgenApi::new(&config).unwrap();
+
+    api.request(String::from("Alice:")).await.unwrap();
+
+    let (_, body) = mock.vision_requests().pop().unwrap();
+    assert_eq!(body["presence_penalty"], 0.5);
+    assert_eq!(body["frequency_penalty"], 0.25);
+    assert_eq!(body["stop"], json!(["\nBob:"]));
+    assert!(body.get("repetition_penalty").is_none());
+}
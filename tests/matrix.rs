@@ -0,0 +1,124 @@
+use serde_json::json;
+use uc207::matrixbot::{is_allowed_room, parse_command, MatrixCommand};
+use uc207::platform::Persona;
+use uc207::platform::matrix::{localpart, parse_history, persona_content};
+
+const BOT: &str = "@uc207:localhost";
+
+fn persona() -> Persona {
+    Persona { name: String::from("Alice"), avatar_url: String::from("https://example.com/alice.png") }
+}
+
+#[test]
+fn persona_messages_use_per_message_profile_with_fallback() {
+    let content = persona_content(&persona(), Some("mxc://localhost/alice"), "Hello!");
+
+    assert_eq!(content["body"], "Alice: Hello!");
+    assert_eq!(content["com.beeper.per_message_profile"]["displayname"], "Alice");
+    assert_eq!(content["com.beeper.per_message_profile"]["avatar_url"], "mxc://localhost/alice");
+}
+
+#[test]
+fn history_resolves_personas_and_senders() {
+    let mut persona_event = json!({ "type": "m.room.message", "event_id": "$2", "sender": BOT });
+    persona_event["content"] = persona_content(&persona(), None, "Hi Bob!");
+    let events = vec![
+        persona_event,
+        json!({ "type": "m.room.message", "event_id": "$1", "sender": "@bob:localhost", "content": { "msgtype": "m.text", "body": "Hi Alice" } }),
+    ];
+
+    let messages = parse_history(&events, BOT);
+
+    assert_eq!(messages[0].author_name, "Alice");
+    assert_eq!(messages[0].content, "Hi Bob!");
+    assert!(messages[0].is_bot);
+    assert!(!messages[0].is_own);
+    assert_eq!(messages[1].author_name, "bob");
+    assert!(!messages[1].is_bot);
+}
+
+#[test]
+fn history_applies_latest_edit() {
+    let mut original = json!({ "type": "m.room.message", "event_id": "$1", "sender": BOT });
+    original["content"] = persona_content(&persona(), None, "First swipe");
+    let mut new_content = persona_content(&persona(), None, "Second swipe");
+    let mut edit = |id: &str, body: &str| {
+        new_content["body"] = json!(body);
+        json!({
+            "type": "m.room.message", "event_id": id, "sender": BOT,
+            "content": { "body": "* edit", "m.new_content": new_content, "m.relates_to": { "rel_type": "m.replace", "event_id": "$1" } }
+        })
+    };
+    let third = edit("$3", "Alice: Third swipe");
+    let second = edit("$2", "Alice: Second swipe");
+    let events = vec![third, second, original];
+
+    let messages = parse_history(&events, BOT);
+
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].id, "$1");
+    assert_eq!(messages[0].content, "Third swipe");
+}
+
+#[test]
+fn image_messages_carry_their_mxc_uri() {
+    let events = vec![
+        json!({ "type": "m.room.message", "event_id": "$1", "sender": "@bob:localhost", "content": { "msgtype": "m.image", "body": "cat.png", "url": "mxc://localhost/cat" } }),
+    ];
+
+    let messages = parse_history(&events, BOT);
+
+    assert_eq!(messages[0].images, vec![String::from("mxc://localhost/cat")]);
+    assert_eq!(messages[0].content, "");
+}
+
+#[test]
+fn replies_link_to_their_message_without_fallback() {
+    let events = vec![
+        json!({
+            "type": "m.room.message", "event_id": "$2", "sender": "@carol:localhost",
+            "content": { "msgtype": "m.text", "body": "> <@bob:localhost> Hi Alice\n\nHi Bob", "m.relates_to": { "m.in_reply_to": { "event_id": "$1" } } }
+        }),
+        json!({ "type": "m.room.message", "event_id": "$1", "sender": "@bob:localhost", "content": { "msgtype": "m.text", "body": "Hi Alice" } }),
+    ];
+
+    let messages = parse_history(&events, BOT);
+
+    assert_eq!(messages[0].content, "Hi Bob");
+    assert_eq!(messages[0].author_id, "@carol:localhost");
+    assert_eq!(messages[0].reply_to.as_ref().map(|reply_to| reply_to.content.as_str()), Some("Hi Alice"));
+    assert!(messages[1].reply_to.is_none());
+}
+
+#[test]
+fn commands_are_parsed_with_prefix() {
+    assert_eq!(parse_command("!invite alice", "!"), Some(MatrixCommand::Invite(String::from("alice"))));
+    assert_eq!(parse_command("!invite", "!"), Some(MatrixCommand::Help));
+    assert_eq!(parse_command("!list robot", "!"), Some(MatrixCommand::List(String::from("robot"))));
+    assert_eq!(parse_command("!uninvite", "!"), Some(MatrixCommand::Uninvite));
+    assert_eq!(parse_command("!fence", "!"), Some(MatrixCommand::Fence(None)));
+    assert_eq!(parse_command("!fence alice", "!"), Some(MatrixCommand::Fence(Some(String::from("alice")))));
+    assert_eq!(parse_command("!fence remove", "!"), Some(MatrixCommand::RemoveFence(String::new())));
+    assert_eq!(parse_command("!fence remove $2", "!"), Some(MatrixCommand::RemoveFence(String::from("$2"))));
+    assert_eq!(parse_command("!fence list", "!"), Some(MatrixCommand::ListFences));
+    assert_eq!(parse_command("!imagine", "!"), Some(MatrixCommand::Imagine(String::new())));
+    assert_eq!(parse_command("!imagine a cat", "!"), Some(MatrixCommand::Imagine(String::from("a cat"))));
+    assert_eq!(parse_command("!unknown", "!"), None);
+    assert_eq!(parse_command("hello !fence", "!"), None);
+}
+
+#[test]
+fn localpart_strips_server() {
+    assert_eq!(localpart("@bob:example.org"), "bob");
+}
+
+#[test]
+fn invites_are_limited_to_allowed_rooms() {
+    let allowed = vec![String::from("!lounge:example.org"), String::from("localhost")];
+
+    assert!(is_allowed_room(&[], "!any:example.org", None));
+    assert!(is_allowed_room(&allowed, "!lounge:example.org", Some("@eve:example.org")));
+    assert!(is_allowed_room(&allowed, "!other:example.org", Some("@bob:localhost")));
+    assert!(!is_allowed_room(&allowed, "!other:example.org", Some("@eve:example.org")));
+    assert!(!is_allowed_room(&allowed, "!other:localhost", None));
+}
@@ -0,0 +1,36 @@
+use uc207::notes::{AuthorsNote, AuthorsNotes};
+
+fn note(text: &str, depth: Option<usize>) -> AuthorsNote {
+    AuthorsNote { text: String::from(text), depth }
+}
+
+fn notes_path(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("uc207-notes-{}-{}.json", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path.to_string_lossy().into_owned()
+}
+
+#[test]
+fn notes_are_saved_and_loaded() {
+    let path = notes_path("saved");
+    let mut notes = AuthorsNotes::load(&path).unwrap();
+    notes.set("1", note("[Style: terse]", None)).unwrap();
+    notes.set("2", note("[Mood: gloomy]", Some(0))).unwrap();
+
+    let loaded = AuthorsNotes::load(&path).unwrap();
+
+    assert_eq!(loaded.get("1"), Some(&note("[Style: terse]", None)));
+    assert_eq!(loaded.get("2"), Some(&note("[Mood: gloomy]", Some(0))));
+    assert_eq!(loaded.get("3"), None);
+}
+
+#[test]
+fn removed_notes_stay_removed() {
+    let path = notes_path("remove");
+    let mut notes = AuthorsNotes::load(&path).unwrap();
+    notes.set("1", note("[Style: terse]", None)).unwrap();
+
+    assert_eq!(notes.remove("1").unwrap(), Some(note("[Style: terse]", None)));
+    assert_eq!(notes.remove("1").unwrap(), None);
+    assert_eq!(AuthorsNotes::load(&path).unwrap().get("1"), None);
+}
@@ -0,0 +1,255 @@
+mod common;
+
+use std::time::Duration;
+
+use serde_json::json;
+use uc207::textgen::api::{Message, PromptExtras, TextgenApi, BACKEND_UNAVAILABLE};
+use uc207::textgen::character::{Character, Visibility};
+use uc207::textgen::format::Prompt;
+use uc207::textgen::imagegen::extract_image_tags;
+
+use common::{MockResponse, MockTextgen};
+
+const TEMPLATE: &str = "[[PERSONA]]\n[[EXAMPLE]]\n### Input:\n[[CONTEXT]]\n### Response:\n[[NAME]]:";
+
+fn character() -> Character {
+    Character {
+        char_name: String::from("Alice"),
+        char_persona: String::from("Alice is a cheerful robot."),
+        example_dialogue: vec![
+            Message { speaker: String::from("Bob"), content: String::from("Hi!") },
+            Message { speaker: String::from("Alice"), content: String::from("Beep boop, hello!") }
+        ],
+        scenario: String::from("A lab"),
+        tags: vec![String::from("robot"), String::from("sfw")],
+        ..Default::default()
+    }
+}
+
+fn history() -> Vec<Message> {
+    vec![Message { speaker: String::from("Carol"), content: String::from("How are you?") }]
+}
+
+async fn api_for(mock: &MockTextgen, name: &str) -> TextgenApi {
+    let template = common::write_template(name, TEMPLATE);
+    TextgenApi::new(&common::config(json!([common::endpoint(mock, 0)]), &template)).unwrap()
+}
+
+#[tokio::test]
+async fn make_prompt_fills_placeholders() {
+    let template = common::write_template("placeholders", "[[NAME]]|[[PERSONA]]|[[EXAMPLE]]|[[CONTEXT]]|[[SCENARIO]]|[[TAGS]]");
+    let api = TextgenApi::new(&common::config(json!([common::dead_endpoint(0)]), &template)).unwrap();
+
+    let prompt = api.make_prompt(&character(), &history(), &PromptExtras::default()).unwrap();
+
+    assert_eq!(prompt, Prompt::Text(String::from("Alice|Alice is a cheerful robot.|Bob: Hi!\nAlice: Beep boop, hello!|Carol: How are you?|A lab|robot, sfw")));
+}
+
+#[tokio::test]
+async fn both_name_syntaxes_work_in_prompts_and_greetings() {
+    let template = common::write_template("name-macros", "{{char}} talks to {{user}}. [[PERSONA]] [[SCENARIO]]");
+    let api = TextgenApi::new(&common::config(json!([common::dead_endpoint(0)]), &template)).unwrap();
+    let character = Character {
+        char_persona: String::from("{{char}} likes {{user}}."),
+        scenario: String::from("[[NAME]] meets [[USER]]."),
+        greeting: String::from("{{char}} waves at [[USER]], [[NAME]] greets {{user}}."),
+        ..character()
+    };
+    let extras = PromptExtras { user: String::from("Carol"), ..Default::default() };
+
+    let prompt = api.make_prompt(&character, &history(), &extras).unwrap();
+
+    assert_eq!(prompt, Prompt::Text(String::from("Alice talks to Carol. Alice likes Carol. Alice meets Carol.")));
+    assert_eq!(character.pick_greeting(Some(1), "Carol").unwrap(), "Alice waves at Carol, Alice greets Carol.");
+}
+
+#[test]
+fn missing_greetings_are_not_replaced_by_another() {
+    let character = Character {
+        greeting: String::from("Hi!"),
+        alternate_greetings: vec![String::from("Hello!")],
+        ..character()
+    };
+
+    assert_eq!(character.pick_greeting(Some(2), "Carol").as_deref(), Some("Hello!"));
+    assert_eq!(character.pick_greeting(Some(3), "Carol"), None);
+    assert_eq!(character.pick_greeting(Some(0), "Carol"), None);
+}
+
+#[tokio::test]
+async fn make_prompt_fails_without_template() {
+    let api = TextgenApi::new(&common::config(json!([common::dead_endpoint(0)]), "/nonexistent/template.txt")).unwrap();
+
+    assert!(api.make_prompt(&character(), &history(), &PromptExtras::default()).is_err());
+}
+
+#[tokio::test]
+async fn init_requires_an_endpoint() {
+    assert!(TextgenApi::new(&common::config(json!([]), "template.txt")).is_err());
+}
+
+#[tokio::test]
+async fn request_strips_prompt_from_response() {
+    let mock = MockTextgen::start().await;
+    mock.set_default_reply(" Beep! I'm great.");
+    let api = api_for(&mock, "strip").await;
+
+    let prompt = api.make_prompt(&character(), &history(), &PromptExtras::default()).unwrap();
+    let response = api.request(prompt).await.unwrap();
+
+    assert_eq!(response, " Beep! I'm great.");
+}
+
+#[tokio::test]
+async fn request_sends_prompt_and_sampling_parameters() {
+    let mock = MockTextgen::start().await;
+    let api = api_for(&mock, "payload").await;
+
+    api.request(String::from("the prompt")).await.unwrap();
+
+    let requests = mock.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0][0], "the prompt");
+    assert_eq!(requests[0][1]["max_new_tokens"], 200);
+    assert!((requests[0][1]["temperature"].as_f64().unwrap() - 0.72).abs() < 1e-6);
+}
+
+#[tokio::test]
+async fn request_retries_after_server_error() {
+    let mock = MockTextgen::start().await;
+    mock.script(vec![MockResponse::Status(500), MockResponse::Reply(String::from(" Second try"))]);
+    let api = api_for(&mock, "retry").await;
+
+    assert_eq!(api.request(String::from("prompt")).await.unwrap(), " Second try");
+    assert_eq!(mock.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn request_rejects_malformed_responses() {
+    let mock = MockTextgen::start().await;
+    mock.script(vec![MockResponse::Raw(String::from("not json")), MockResponse::Raw(String::from("{\"data\": []}"))]);
+    let api = api_for(&mock, "malformed").await;
+
+    let error = api.request(String::from("prompt")).await.unwrap_err();
+
+    assert!(error.to_string().contains(BACKEND_UNAVAILABLE));
+}
+
+#[tokio::test]
+async fn request_times_out_on_slow_backend() {
+    let mock = MockTextgen::start().await;
+    mock.set_latency(Duration::from_millis(1500));
+    let api = api_for(&mock, "timeout").await;
+
+    assert!(api.request(String::from("prompt")).await.is_err());
+}
+
+#[tokio::test]
+async fn request_fails_over_to_next_endpoint() {
+    let mock = MockTextgen::start().await;
+    let template = common::write_template("failover", TEMPLATE);
+    let api = TextgenApi::new(&common::config(json!([common::endpoint(&mock, 1), common::dead_endpoint(0)]), &template)).unwrap();
+
+    assert_eq!(api.request(String::from("prompt")).await.unwrap(), " Hello there!");
+}
+
+#[tokio::test]
+async fn request_prefers_lower_priority_values() {
+    let primary = MockTextgen::start().await;
+    let secondary = MockTextgen::start().await;
+    let template = common::write_template("priority", TEMPLATE);
+    let api = TextgenApi::new(&common::config(json!([common::endpoint(&secondary, 5), common::endpoint(&primary, 1)]), &template)).unwrap();
+
+    api.request(String::from("prompt")).await.unwrap();
+
+    assert_eq!(primary.requests().len(), 1);
+    assert_eq!(secondary.requests().len(), 0);
+}
+
+#[tokio::test]
+async fn open_circuit_skips_endpoint() {
+    let mock = MockTextgen::start().await;
+    mock.script(vec![MockResponse::Status(503); 4]);
+    let api = api_for(&mock, "circuit").await;
+
+    assert!(api.request(String::from("prompt")).await.is_err());
+    assert!(api.request(String::from("prompt")).await.is_err());
+    // The breaker opened after two failed requests, so the endpoint isn't even tried anymore
+    assert!(api.request(String::from("prompt")).await.is_err());
+    assert_eq!(mock.requests().len(), 4);
+}
+
+#[tokio::test]
+async fn retries_count_as_one_failure() {
+    let mock = MockTextgen::start().await;
+    mock.script(vec![MockResponse::Status(503), MockResponse::Status(503)]);
+    let api = api_for(&mock, "one-failure").await;
+
+    assert!(api.request(String::from("prompt")).await.is_err());
+    assert_eq!(api.request(String::from("prompt")).await.unwrap(), " Hello there!");
+}
+
+#[tokio::test]
+async fn health_check_closes_circuit_once_backend_recovers() {
+    let mock = MockTextgen::start().await;
+    mock.script(vec![MockResponse::Status(503); 4]);
+    let api = api_for(&mock, "recovery").await;
+    assert!(api.request(String::from("prompt")).await.is_err());
+    assert!(api.request(String::from("prompt")).await.is_err());
+
+    assert_eq!(api.health_check().await, Some(String::from("mock-model-7b")));
+    assert_eq!(api.request(String::from("prompt")).await.unwrap(), " Hello there!");
+}
+
+#[tokio::test]
+async fn check_model_returns_loaded_model() {
+    let mock = MockTextgen::start().await;
+    let api = api_for(&mock, "model").await;
+
+    assert_eq!(api.check_model().await, Some(String::from("mock-model-7b")));
+    assert_eq!(mock.model_requests(), 1);
+}
+
+#[tokio::test]
+async fn check_model_survives_unreachable_backend() {
+    let mock = MockTextgen::start().await;
+    mock.fail_next(1);
+    let api = api_for(&mock, "model-down").await;
+
+    assert_eq!(api.check_model().await, None);
+    assert_eq!(api.check_model().await, Some(String::from("mock-model-7b")));
+}
+
+#[test]
+fn parse_conversation_round_trips() {
+    let messages = Message::parse_conversation("Bob: Hi!\n\nAlice: Hello: how are you?").unwrap();
+
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[1].speaker, "Alice");
+    assert_eq!(messages[1].content, "Hello: how are you?");
+    assert_eq!(Message::format_conversation(&messages), "Bob: Hi!\nAlice: Hello: how are you?");
+}
+
+#[test]
+fn parse_conversation_rejects_lines_without_speaker() {
+    assert!(Message::parse_conversation("just some text").is_err());
+}
+
+#[test]
+fn guild_characters_are_only_visible_in_their_guild() {
+    let scoped = Character { owner_id: Some(1), guild_id: Some(10), visibility: Visibility::Guild, ..character() };
+    let unscoped = Character { guild_id: None, ..scoped.clone() };
+
+    assert!(scoped.is_visible_to(2, Some(10)));
+    assert!(!scoped.is_visible_to(2, Some(20)));
+    assert!(!scoped.is_visible_to(1, None));
+    assert!(!unscoped.is_visible_to(2, Some(20)));
+}
+
+#[test]
+fn image_tags_are_extracted_from_replies() {
+    let (text, prompts) = extract_image_tags("Look! [picture: a red fox] And this [PICTURE:  snow ] [picture: ] [image: a caption]");
+
+    assert_eq!(text, "Look!  And this   [image: a caption]");
+    assert_eq!(prompts, vec![String::from("a red fox"), String::from("snow")]);
+}
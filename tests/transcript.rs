@@ -0,0 +1,84 @@
+use serde_json::Value;
+use uc207::textgen::api::Message;
+use uc207::transcript::{self, ExportFormat};
+
+const DATE: &str = "2023-05-14T17:30:20Z";
+
+fn chat() -> Vec<Message> {
+    vec![
+        Message { speaker: String::from("Bob"), content: String::from("Hi <Alice> & co") },
+        Message { speaker: String::from("Alice"), content: String::from("Hello!\nHow are you?") },
+    ]
+}
+
+#[test]
+fn text_exports_are_the_prompt_history() {
+    assert_eq!(transcript::export(ExportFormat::Text, &chat(), "Alice", DATE), "Bob: Hi <Alice> & co\nAlice: Hello!\nHow are you?");
+}
+
+#[test]
+fn jsonl_exports_have_a_message_per_line() {
+    let exported = transcript::export(ExportFormat::Jsonl, &chat(), "Alice", DATE);
+    let lines: Vec<Message> = exported.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+
+    assert_eq!(lines, chat());
+}
+
+#[test]
+fn sillytavern_exports_start_with_metadata() {
+    let exported = transcript::export(ExportFormat::SillyTavern, &chat(), "Alice", DATE);
+    let lines: Vec<Value> = exported.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0]["user_name"], "Bob");
+    assert_eq!(lines[0]["character_name"], "Alice");
+    assert_eq!(lines[0]["create_date"], DATE);
+    assert_eq!(lines[1]["name"], "Bob");
+    assert_eq!(lines[1]["is_user"], true);
+    assert_eq!(lines[2]["mes"], "Hello!\nHow are you?");
+    assert_eq!(lines[2]["is_user"], false);
+}
+
+#[test]
+fn html_exports_are_escaped() {
+    let exported = transcript::export(ExportFormat::Html, &chat(), "Alice", DATE);
+
+    assert!(exported.starts_with("<!DOCTYPE html>"));
+    assert!(exported.contains("<title>Chat with Alice</title>"));
+    assert!(exported.contains("<b>Bob</b><p>Hi &lt;Alice&gt; &amp; co</p>"));
+    assert!(exported.contains("<div class=\"message character\"><b>Alice</b><p>Hello!<br>How are you?</p></div>"));
+}
+
+#[test]
+fn every_export_but_html_imports_back() {
+    for format in [ExportFormat::Text, ExportFormat::Jsonl, ExportFormat::SillyTavern] {
+        let exported = transcript::export(format, &chat(), "Alice", DATE);
+        assert_eq!(transcript::import(&exported).unwrap(), chat(), "{:?}", format);
+    }
+}
+
+#[test]
+fn sillytavern_system_messages_are_skipped() {
+    let file = r#"{"user_name":"You","character_name":"Alice","create_date":"","chat_metadata":{}}
+{"name":"Alice","is_user":false,"is_system":true,"mes":"Hidden"}
+{"name":"Alice","is_user":false,"mes":"Shown"}"#;
+
+    assert_eq!(transcript::import(file).unwrap(), vec![Message { speaker: String::from("Alice"), content: String::from("Shown") }]);
+}
+
+#[test]
+fn invalid_transcripts_are_rejected() {
+    assert!(transcript::import("").is_err());
+    assert!(transcript::import("no speaker here").is_err());
+    assert!(transcript::import("{\"speaker\": \"Bob\"}").is_err());
+}
+
+#[test]
+fn formats_are_picked_by_name_or_file_name() {
+    assert_eq!(ExportFormat::parse("SillyTavern"), Some(ExportFormat::SillyTavern));
+    assert_eq!(ExportFormat::parse("pdf"), None);
+    for format in ExportFormat::ALL {
+        assert_eq!(ExportFormat::of_file(&format.file_name("chat")), format);
+    }
+    assert_eq!(ExportFormat::of_file("notes.md"), ExportFormat::Text);
+}
@@ -0,0 +1,60 @@
+use uc207::users::{UserProfile, UserProfiles};
+
+fn users_path(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("uc207-users-{}-{}.json", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path.to_string_lossy().into_owned()
+}
+
+#[test]
+fn profiles_are_saved_and_loaded() {
+    let path = users_path("saved");
+    let mut profiles = UserProfiles::load(&path).unwrap();
+    profiles.set("1", None, UserProfile { name: String::from("Bobby"), description: String::from("Likes robots") }).unwrap();
+    profiles.set("2", None, UserProfile { name: String::from("Cee"), description: String::new() }).unwrap();
+    profiles.remove("2", None).unwrap();
+
+    let loaded = UserProfiles::load(&path).unwrap();
+
+    assert_eq!(loaded.get("1", None), Some(&UserProfile { name: String::from("Bobby"), description: String::from("Likes robots") }));
+    assert_eq!(loaded.get("2", None), None);
+}
+
+fn profile(name: &str) -> UserProfile {
+    UserProfile { name: String::from(name), description: String::new() }
+}
+
+#[test]
+fn server_profiles_override_global_ones() {
+    let path = users_path("servers");
+    let mut profiles = UserProfiles::load(&path).unwrap();
+    profiles.set("1", None, profile("Bobby")).unwrap();
+    profiles.set("1", Some("42"), profile("Sir Robert")).unwrap();
+
+    let loaded = UserProfiles::load(&path).unwrap();
+
+    assert_eq!(loaded.get("1", Some("42")), Some(&profile("Sir Robert")));
+    assert_eq!(loaded.get("1", Some("43")), Some(&profile("Bobby")));
+    assert_eq!(loaded.get("1", None), Some(&profile("Bobby")));
+    assert_eq!(loaded.get_exact("1", Some("43")), None);
+
+    profiles.remove("1", Some("42")).unwrap();
+    assert_eq!(profiles.get("1", Some("42")), Some(&profile("Bobby")));
+}
+
+#[test]
+fn call_me_names_keep_the_persona_description() {
+    let path = users_path("callme");
+    let mut profiles = UserProfiles::load(&path).unwrap();
+    profiles.set("1", None, UserProfile { name: String::from("Bobby"), description: String::from("Likes robots") }).unwrap();
+
+    profiles.set_name("1", "Rob", None).unwrap();
+    assert_eq!(profiles.get("1", None), Some(&UserProfile { name: String::from("Rob"), description: String::from("Likes robots") }));
+
+    profiles.set_name("1", "", None).unwrap();
+    assert_eq!(UserProfiles::load(&path).unwrap().get("1", None), Some(&UserProfile { name: String::new(), description: String::from("Likes robots") }));
+
+    profiles.set_name("2", "Cee", None).unwrap();
+    profiles.set_name("2", "", None).unwrap();
+    assert_eq!(profiles.get("2", None), None);
+}
@@ -0,0 +1,130 @@
+use std::fs;
+use std::path::PathBuf;
+
+use serde_json::Value;
+use uc207::textgen::character::Character;
+use uc207::validation::{self, CHARACTER_SCHEMA, CONFIG_SCHEMA};
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("uc207-validation-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn config(template: &str, extra: &str) -> String {
+    format!(r#"{{
+    "backend": {{
+        "endpoints": [{{"textgen_url": "http://127.0.0.1:1/run/textgen", "model_url": "http://127.0.0.1:1/api/v1/model"}}],
+        "prompt_template": "{}"{}
+    }},
+    "sampling": {{"temperature": 0.7, "num_beams": 1}}
+}}"#, template, extra)
+}
+
+#[test]
+fn valid_config_has_no_problems() {
+    let dir = temp_dir("valid");
+    fs::write(dir.join("template.txt"), "[[CONTEXT]]\n[[NAME]]:").unwrap();
+    fs::write(dir.join("config.json"), config("template.txt", "")).unwrap();
+
+    assert_eq!(validation::check_config(dir.join("config.json").to_str().unwrap()), vec![]);
+}
+
+#[test]
+fn sample_data_is_valid() {
+    let (checked, problems) = validation::check_characters("data/characters");
+
+    assert_eq!(validation::check_config("data/config.toml"), vec![]);
+    assert!(checked > 0);
+    assert_eq!(problems, vec![]);
+}
+
+#[test]
+fn config_problems_have_lines() {
+    let dir = temp_dir("config");
+    fs::write(dir.join("template.txt"), "[[CONTEXT]]\n[[NAME]]:").unwrap();
+    fs::write(dir.join("config.json"), config("template.txt", ",\n        \"retries\": 3")).unwrap();
+
+    let problems = validation::check_config(dir.join("config.json").to_str().unwrap());
+
+    assert_eq!(problems.len(), 1);
+    assert_eq!(problems[0].line, Some(5));
+    assert!(problems[0].message.contains("backend.retries"));
+}
+
+#[test]
+fn config_type_errors_have_lines() {
+    let dir = temp_dir("types");
+    fs::write(dir.join("config.json"), config("template.txt", "").replace("\"num_beams\": 1", "\"num_beams\": \"1\"")).unwrap();
+
+    let problems = validation::check_config(dir.join("config.json").to_str().unwrap());
+
+    assert_eq!(problems.len(), 1);
+    assert_eq!(problems[0].line, Some(6));
+    assert!(problems[0].message.contains("sampling.num_beams"));
+}
+
+#[test]
+fn toml_and_yaml_problems_have_lines() {
+    let dir = temp_dir("formats");
+    fs::write(dir.join("config.toml"), "[backend]\nprompt_template = \"template.txt\"\n\n[sampling]\ntemprature = 0.5\n").unwrap();
+    fs::write(dir.join("config.yaml"), "sampling:\n  temperature: 0.5\n  top_p: [\n").unwrap();
+
+    let toml = validation::check_config(dir.join("config.toml").to_str().unwrap());
+    let yaml = validation::check_config(dir.join("config.yaml").to_str().unwrap());
+
+    assert_eq!(toml.len(), 1);
+    assert_eq!(toml[0].line, Some(5));
+    assert_eq!(yaml.len(), 1);
+    assert!(yaml[0].line.is_some());
+}
+
+#[test]
+fn template_placeholders_are_checked() {
+    let dir = temp_dir("template");
+    let template = dir.join("template.txt");
+    fs::write(&template, "[[PERSONA]]\n[[HISTORY]]\n[[NAME]]:").unwrap();
+
+    let problems = validation::check_template(template.to_str().unwrap());
+
+    assert_eq!(problems.len(), 2);
+    assert_eq!(problems[0].line, Some(2));
+    assert!(problems[0].message.contains("[[HISTORY]]"));
+    assert!(problems[1].message.contains("[[CONTEXT]]"));
+}
+
+#[test]
+fn character_problems_point_at_fields() {
+    let dir = temp_dir("character");
+    let path = dir.join("robot.json");
+    fs::write(&path, "{\n    \"char_name\": \"Clyde\",\n    \"char_description\": \"\",\n    \"char_persona\": \"A robot.\",\n    \"example_dialogue\": [],\n    \"avatar_url\": \"\",\n    \"tag\": []\n}").unwrap();
+
+    let (character, problems) = validation::check_character(&path);
+
+    assert!(character.is_some());
+    let lines: Vec<Option<usize>> = problems.iter().map(|problem| problem.line).collect();
+    assert_eq!(lines, vec![Some(7), Some(2)]);
+}
+
+#[test]
+fn schemas_cover_every_character_field() {
+    let schema: Value = serde_json::from_str(CHARACTER_SCHEMA).unwrap();
+    serde_json::from_str::<Value>(CONFIG_SCHEMA).unwrap();
+    let character = Character {
+        owner_id: Some(1),
+        guild_id: Some(1),
+        tags: vec![String::new()],
+        author: String::from("a"),
+        version: String::from("a"),
+        creator_notes: String::from("a"),
+        scenario: String::from("a"),
+        greeting: String::from("a"),
+        alternate_greetings: vec![String::new()],
+        ..Default::default()
+    };
+
+    for field in serde_json::to_value(character).unwrap().as_object().unwrap().keys() {
+        assert!(schema["properties"].get(field).is_some(), "{} is missing from the schema", field);
+    }
+}
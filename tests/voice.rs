@@ -0,0 +1,157 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use serde_json::json;
+use uc207::textgen::api::TextgenApi;
+use uc207::voice::stt::parse_transcription;
+use uc207::voice::tts::spoken_text;
+use uc207::voice::utterance::Utterances;
+use uc207::voice::wav::{self, Pcm, DISCORD_SAMPLE_RATE};
+
+use common::MockTextgen;
+
+#[test]
+fn wav_files_round_trip() {
+    let encoded = wav::encode(16000, 1, &[0, 16384, -32768]);
+    let decoded = wav::decode(&encoded).unwrap();
+
+    assert_eq!(decoded, Pcm { sample_rate: 16000, channels: 1, samples: vec![0.0, 0.5, -1.0] });
+}
+
+#[test]
+fn float_wav_files_decode() {
+    let mut file = wav::encode(24000, 2, &[]);
+    // Same layout with float samples: format tag 3, 32 bits per sample
+    file[20..22].copy_from_slice(&3u16.to_le_bytes());
+    file[34..36].copy_from_slice(&32u16.to_le_bytes());
+    file[40..44].copy_from_slice(&8u32.to_le_bytes());
+    file.extend(0.25f32.to_le_bytes());
+    file.extend((-0.5f32).to_le_bytes());
+
+    let decoded = wav::decode(&file).unwrap();
+
+    assert_eq!(decoded, Pcm { sample_rate: 24000, channels: 2, samples: vec![0.25, -0.5] });
+}
+
+#[test]
+fn unknown_chunks_are_skipped() {
+    let encoded = wav::encode(8000, 1, &[8192]);
+    let mut file = encoded[..36].to_vec();
+    file.extend(b"LIST");
+    file.extend(3u32.to_le_bytes());
+    file.extend(b"abc\0");
+    file.extend(&encoded[36..]);
+
+    assert_eq!(wav::decode(&file).unwrap().samples, vec![0.25]);
+}
+
+#[test]
+fn invalid_files_are_rejected() {
+    assert!(wav::decode(b"not a wav file").is_err());
+    assert!(wav::decode(&wav::encode(8000, 1, &[])[..36]).is_err());
+}
+
+#[test]
+fn mono_audio_is_resampled_to_discord_stereo() {
+    let pcm = Pcm { sample_rate: 24000, channels: 1, samples: vec![0.0, 1.0] };
+
+    let stereo = pcm.to_discord_stereo();
+
+    assert_eq!(DISCORD_SAMPLE_RATE, 48000);
+    assert_eq!(stereo, vec![0.0, 0.0, 0.5, 0.5, 1.0, 1.0, 1.0, 1.0]);
+}
+
+#[test]
+fn stereo_channels_stay_apart() {
+    let pcm = Pcm { sample_rate: 48000, channels: 2, samples: vec![0.5, -0.5, 0.25, -0.25] };
+
+    assert_eq!(pcm.to_discord_stereo(), pcm.samples);
+    assert_eq!(wav::to_le_bytes(&[1.0]), 1.0f32.to_le_bytes().to_vec());
+}
+
+#[test]
+fn markdown_is_not_read_out() {
+    assert_eq!(spoken_text("*smiles*  Hi ~~there~~,\n`friend`!"), "smiles Hi there, friend!");
+    assert_eq!(spoken_text("**"), "");
+}
+
+#[test]
+fn discord_audio_is_downmixed_for_speech_recognition() {
+    let stereo = [300, 0, 300, 0, 300, 0, -600, -600, 0, 0, 0, 0, 7];
+
+    assert_eq!(wav::to_speech_mono(&stereo), vec![150, -200]);
+}
+
+#[test]
+fn utterances_end_after_a_pause() {
+    let start = Instant::now();
+    let mut utterances = Utterances::new(1000, 1, Duration::from_millis(500), Duration::from_millis(2));
+    utterances.push(1, &[1000, 1000], start);
+    utterances.push(2, &[2000, 2000], start);
+    utterances.push(1, &[1000, -1000], start + Duration::from_millis(400));
+    // Silent frames don't keep an utterance going
+    utterances.push(2, &[0, 10], start + Duration::from_millis(400));
+
+    assert_eq!(utterances.take_finished(start + Duration::from_millis(600)), vec![(2, vec![2000, 2000])]);
+    assert_eq!(utterances.take_finished(start + Duration::from_millis(900)), vec![(1, vec![1000, 1000, 1000, -1000])]);
+    assert!(utterances.take_finished(start + Duration::from_secs(2)).is_empty());
+}
+
+#[test]
+fn short_and_discarded_utterances_are_dropped() {
+    let start = Instant::now();
+    let mut utterances = Utterances::new(1000, 2, Duration::from_millis(100), Duration::from_millis(2));
+    utterances.push(1, &[1000, 1000], start);
+    utterances.push(2, &[1000, 1000, 1000, 1000], start);
+    utterances.push(3, &[1000, 1000, 1000, 1000], start);
+    utterances.discard(3);
+
+    assert_eq!(utterances.take_finished(start + Duration::from_millis(100)), vec![(2, vec![1000, 1000, 1000, 1000])]);
+}
+
+#[test]
+fn long_utterances_are_cut() {
+    let start = Instant::now();
+    let mut utterances = Utterances::new(1000, 1, Duration::from_secs(1), Duration::ZERO);
+    for second in 0..31 {
+        utterances.push(1, &[1000], start + Duration::from_secs(second));
+    }
+
+    assert_eq!(utterances.take_finished(start + Duration::from_secs(30)).len(), 1);
+}
+
+#[test]
+fn transcriptions_are_parsed() {
+    assert_eq!(parse_transcription(r#"{"text": " Hello,\n  world. "}"#), "Hello, world.");
+    assert_eq!(parse_transcription(" plain text\n"), "plain text");
+    assert_eq!(parse_transcription(r#"{"error": "no audio"}"#), "");
+}
+
+#[tokio::test]
+async fn speech_is_sent_to_the_transcription_endpoint() {
+    let mock = MockTextgen::start().await;
+    let template = common::write_template("voice-stt", "[[CONTEXT]]\n[[NAME]]:");
+    let mut config = common::config(json!([common::endpoint(&mock, 0)]), &template);
+    config.voice.stt_url = Some(mock.url("/v1/audio/transcriptions"));
+    config.voice.stt_language = String::from("en");
+    let api = TextgenApi::new(&config).unwrap();
+
+    let transcriber = api.transcriber().unwrap();
+    let text = transcriber.transcribe(wav::encode(16000, 1, &[0; 160])).await.unwrap();
+
+    assert_eq!(text, "What's the weather like?");
+    let request = &mock.transcription_requests()[0];
+    assert!(request.contains("filename=\"speech.wav\""));
+    assert!(request.contains("RIFF"));
+    assert!(request.contains("whisper-1"));
+    assert!(request.contains("name=\"language\"\r\n\r\nen"));
+}
+
+#[test]
+fn listening_needs_a_transcription_endpoint() {
+    let template = common::write_template("voice-no-stt", "[[CONTEXT]]\n[[NAME]]:");
+    let config = common::config(json!([{ "textgen_url": "http://localhost:1", "model_url": "http://localhost:1" }]), &template);
+
+    assert!(TextgenApi::new(&config).unwrap().transcriber().is_none());
+}